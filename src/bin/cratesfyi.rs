@@ -2,11 +2,17 @@ use std::env;
 use std::fmt::Write;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 
+use chrono::{NaiveDate, Utc};
+use docs_rs::db::sandbox_overrides::{self, SandboxOverride};
+use docs_rs::db::toolchains;
 use docs_rs::db::{self, add_path_into_database, Pool, PoolClient};
+use docs_rs::storage::{MigrationOptions, StorageKind};
 use docs_rs::utils::{remove_crate_priority, set_crate_priority};
 use docs_rs::{
-    BuildQueue, Config, Context, DocBuilder, Index, Metrics, RustwideBuilder, Server, Storage,
+    BuildQueue, Config, Context, DocBuilder, Index, Limits, Metrics, RebuildFilter,
+    RustwideBuilder, Server, Storage,
 };
 use failure::{err_msg, Error, ResultExt};
 use once_cell::sync::OnceCell;
@@ -50,6 +56,14 @@ fn logger_init() {
     rustwide::logging::init_with(logger);
 }
 
+fn parse_percent(value: &str) -> Result<u8, String> {
+    match value.parse() {
+        Ok(percent) if percent <= 100 => Ok(percent),
+        Ok(percent) => Err(format!("{}% is more than 100%", percent)),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, strum::EnumString, strum::EnumVariantNames)]
 #[strum(serialize_all = "snake_case")]
 enum Toggle {
@@ -103,6 +117,18 @@ enum CommandLine {
         #[structopt(subcommand)]
         subcommand: QueueSubcommand,
     },
+
+    /// Manage the sandbox limits of crates
+    Limits {
+        #[structopt(subcommand)]
+        subcommand: LimitsSubcommand,
+    },
+
+    /// Storage maintenance
+    Storage {
+        #[structopt(subcommand)]
+        subcommand: StorageSubcommand,
+    },
 }
 
 impl CommandLine {
@@ -129,6 +155,8 @@ impl CommandLine {
             }
             Self::Database { subcommand } => subcommand.handle_args(ctx)?,
             Self::Queue { subcommand } => subcommand.handle_args(ctx)?,
+            Self::Limits { subcommand } => subcommand.handle_args(ctx)?,
+            Self::Storage { subcommand } => subcommand.handle_args(ctx)?,
         }
 
         Ok(())
@@ -160,6 +188,76 @@ enum QueueSubcommand {
         #[structopt(subcommand)]
         subcommand: PrioritySubcommand,
     },
+
+    /// List the crates in the build queue
+    List {
+        /// List the crates that failed to build too many times instead
+        #[structopt(long = "failed")]
+        failed: bool,
+        /// Print the list as JSON
+        #[structopt(long = "json")]
+        json: bool,
+    },
+
+    /// Remove a crate from the build queue
+    Remove {
+        /// Name of the crate to remove
+        #[structopt(name = "CRATE_NAME")]
+        crate_name: String,
+        /// Version of the crate to remove, all the versions are removed if missing
+        #[structopt(name = "CRATE_VERSION")]
+        crate_version: Option<String>,
+    },
+
+    /// Reset the failed attempts of a crate, so that it's built again
+    Retry {
+        /// Name of the crate to retry
+        #[structopt(name = "CRATE_NAME")]
+        crate_name: String,
+        /// Version of the crate to retry
+        #[structopt(name = "CRATE_VERSION")]
+        crate_version: String,
+    },
+
+    /// Queue a rebuild of all the releases matching the filters
+    Rebuild {
+        /// Only rebuild releases built with a rustc older than this date (YYYY-MM-DD)
+        #[structopt(long)]
+        rustc_version_before: Option<NaiveDate>,
+        /// Only rebuild releases that failed to build
+        #[structopt(long)]
+        failed_only: bool,
+        /// Only rebuild releases published on or after this date (YYYY-MM-DD)
+        #[structopt(long)]
+        released_after: Option<NaiveDate>,
+        /// Only rebuild crates whose name matches this pattern (postgres LIKE syntax)
+        #[structopt(long)]
+        crate_pattern: Option<String>,
+        /// Priority of the rebuilds (new crate builds get priority 0)
+        #[structopt(
+            name = "BUILD_PRIORITY",
+            short = "p",
+            long = "priority",
+            default_value = "20"
+        )]
+        build_priority: i32,
+        /// Only print how many releases would be queued
+        #[structopt(long)]
+        dry_run: bool,
+    },
+
+    /// Change the priority of a queued crate
+    SetPriority {
+        /// Name of the queued crate
+        #[structopt(name = "CRATE_NAME")]
+        crate_name: String,
+        /// Version of the queued crate
+        #[structopt(name = "CRATE_VERSION")]
+        crate_version: String,
+        /// New priority of the crate (lower values are built first)
+        #[structopt(name = "BUILD_PRIORITY")]
+        build_priority: i32,
+    },
 }
 
 impl QueueSubcommand {
@@ -174,6 +272,99 @@ impl QueueSubcommand {
                 .add_crate(&crate_name, &crate_version, build_priority)?,
 
             Self::DefaultPriority { subcommand } => subcommand.handle_args(ctx)?,
+
+            Self::List { failed, json } => {
+                let build_queue = ctx.build_queue()?;
+                let crates = if failed {
+                    build_queue.failed_crates()?
+                } else {
+                    build_queue.queued_crates()?
+                };
+
+                if json {
+                    println!("{}", serde_json::to_string_pretty(&crates)?);
+                } else {
+                    for krate in &crates {
+                        print!(
+                            "{} {} (priority: {}, attempts: {})",
+                            krate.name, krate.version, krate.priority, krate.attempt
+                        );
+                        if let Some(next_attempt_at) = krate.next_attempt_at {
+                            print!(" next attempt at {}", next_attempt_at);
+                        }
+                        println!();
+                        if let Some(last_error) = &krate.last_error {
+                            println!("    last error: {}", last_error);
+                        }
+                    }
+                    println!("{} crates", crates.len());
+                }
+            }
+
+            Self::Remove {
+                crate_name,
+                crate_version,
+            } => {
+                let removed = ctx
+                    .build_queue()?
+                    .remove_crate(&crate_name, crate_version.as_deref())?;
+                println!("removed {} crates from the queue", removed);
+            }
+
+            Self::Retry {
+                crate_name,
+                crate_version,
+            } => {
+                if !ctx
+                    .build_queue()?
+                    .retry_crate(&crate_name, &crate_version)?
+                {
+                    return Err(err_msg(format!(
+                        "{} {} is not in the queue",
+                        crate_name, crate_version
+                    )));
+                }
+            }
+
+            Self::Rebuild {
+                rustc_version_before,
+                failed_only,
+                released_after,
+                crate_pattern,
+                build_priority,
+                dry_run,
+            } => {
+                let filter = RebuildFilter {
+                    rustc_version_before,
+                    failed_only,
+                    released_after,
+                    crate_pattern,
+                };
+                let count =
+                    ctx.build_queue()?
+                        .rebuild_releases(&filter, build_priority, dry_run)?;
+                if dry_run {
+                    println!("{} releases would be queued for a rebuild", count);
+                } else {
+                    println!("queued {} releases for a rebuild", count);
+                }
+            }
+
+            Self::SetPriority {
+                crate_name,
+                crate_version,
+                build_priority,
+            } => {
+                if !ctx
+                    .build_queue()?
+                    .set_priority(&crate_name, &crate_version, build_priority)?
+                {
+                    return Err(err_msg(format!(
+                        "{} {} is not in the queue",
+                        crate_name, crate_version
+                    )));
+                }
+            }
         }
         Ok(())
     }
@@ -267,11 +458,52 @@ enum BuildSubcommand {
         /// Update the toolchain only if no toolchain is currently installed
         #[structopt(name = "ONLY_FIRST_TIME", long = "only-first-time")]
         only_first_time: bool,
+
+        /// Build this number of crates that built successfully in the past with the new
+        /// toolchain, and switch to it only if few of them start failing. The new toolchain is
+        /// then used until another one is chosen with `build set-toolchain`
+        #[structopt(long, conflicts_with = "ONLY_FIRST_TIME")]
+        canary: Option<usize>,
+
+        /// Toolchain tested by the canary run, defaults to today's nightly
+        #[structopt(long, requires = "canary")]
+        canary_toolchain: Option<String>,
+
+        /// Maximum percentage of the crates of the canary run that can start failing
+        #[structopt(long, default_value = "5", parse(try_from_str = parse_percent))]
+        max_regression_percent: u8,
     },
 
     /// Adds essential files for the installed version of rustc
     AddEssentialFiles,
 
+    /// Switch to another toolchain and install it
+    SetToolchain {
+        /// Name of the toolchain, for example nightly-2020-11-21
+        #[structopt(name = "TOOLCHAIN")]
+        toolchain: String,
+    },
+
+    /// Go back to the toolchain installed before the current one
+    RollbackToolchain,
+
+    /// Build a crate with a specific toolchain instead of the current one
+    PinToolchain {
+        /// Crate name
+        #[structopt(name = "CRATE_NAME")]
+        crate_name: String,
+        /// Name of the toolchain, for example nightly-2020-11-21
+        #[structopt(name = "TOOLCHAIN")]
+        toolchain: String,
+    },
+
+    /// Build a crate with the current toolchain again
+    UnpinToolchain {
+        /// Crate name
+        #[structopt(name = "CRATE_NAME")]
+        crate_name: String,
+    },
+
     /// Locks cratesfyi daemon to stop building new crates
     Lock,
 
@@ -319,7 +551,32 @@ impl BuildSubcommand {
                 }
             }
 
-            Self::UpdateToolchain { only_first_time } => {
+            Self::UpdateToolchain {
+                canary: Some(sample_size),
+                canary_toolchain,
+                max_regression_percent,
+                ..
+            } => {
+                let candidate = canary_toolchain.unwrap_or_else(|| {
+                    format!("nightly-{}", Utc::today().naive_utc().format("%Y-%m-%d"))
+                });
+                let report = rustwide_builder()?
+                    .update_toolchain_canary(
+                        &candidate,
+                        sample_size,
+                        f64::from(max_regression_percent) / 100.0,
+                    )
+                    .context("failed to run the canary builds")?;
+
+                println!("{}", report);
+                if !report.promoted {
+                    return Err(err_msg("the new toolchain was rejected"));
+                }
+            }
+
+            Self::UpdateToolchain {
+                only_first_time, ..
+            } => {
                 if only_first_time {
                     let mut conn = ctx
                         .pool()?
@@ -337,6 +594,15 @@ impl BuildSubcommand {
                 rustwide_builder()?
                     .update_toolchain()
                     .context("failed to update toolchain")?;
+
+                // Promoted canaries and rollbacks replace the configured toolchain.
+                if let Some(toolchain) = toolchains::current_toolchain(&mut *ctx.conn()?)? {
+                    println!(
+                        "updated {}, which replaces the configured toolchain: \
+                         use `build set-toolchain` to choose another one",
+                        toolchain
+                    );
+                }
             }
 
             Self::AddEssentialFiles => {
@@ -345,6 +611,36 @@ impl BuildSubcommand {
                     .context("failed to add essential files")?;
             }
 
+            Self::SetToolchain { toolchain } => {
+                toolchains::set_current_toolchain(&mut *ctx.conn()?, &toolchain)?;
+                rustwide_builder()?
+                    .update_toolchain()
+                    .context("failed to install the toolchain")?;
+            }
+
+            Self::RollbackToolchain => {
+                let previous = toolchains::rollback_toolchain(&mut *ctx.conn()?)
+                    .context("failed to roll back the toolchain")?;
+                println!(
+                    "rolled back to {} ({})",
+                    previous.name, previous.rustc_version
+                );
+                rustwide_builder()?
+                    .update_toolchain()
+                    .context("failed to install the toolchain")?;
+            }
+
+            Self::PinToolchain {
+                crate_name,
+                toolchain,
+            } => toolchains::pin_crate_toolchain(&mut *ctx.conn()?, &crate_name, &toolchain)
+                .context("failed to pin the toolchain")?,
+
+            Self::UnpinToolchain { crate_name } => {
+                toolchains::unpin_crate_toolchain(&mut *ctx.conn()?, &crate_name)
+                    .context("failed to unpin the toolchain")?
+            }
+
             Self::Lock => docbuilder.lock().context("Failed to lock")?,
             Self::Unlock => docbuilder.unlock().context("Failed to unlock")?,
         }
@@ -400,6 +696,39 @@ enum DatabaseSubcommand {
         /// Don't actually resolve the inconsistencies, just log them
         #[structopt(long)]
         dry_run: bool,
+
+        /// Don't resolve anything if there are more inconsistencies than this
+        #[structopt(long)]
+        max_actions: Option<usize>,
+    },
+
+    /// Copies the stored files from a storage backend to another
+    MigrateStorage {
+        /// Storage backend to copy the files from (database, s3 or local)
+        #[structopt(long)]
+        from: StorageKind,
+        /// Storage backend to copy the files to (database, s3 or local)
+        #[structopt(long)]
+        to: StorageKind,
+        /// Only copy the files whose path starts with this prefix
+        #[structopt(long, default_value = "")]
+        prefix: String,
+        /// Number of files copied at once
+        #[structopt(long, default_value = "100")]
+        batch_size: usize,
+        /// Check the copied files are identical to the original ones
+        #[structopt(long)]
+        verify: bool,
+        /// Discard the progress of an interrupted migration and start from the beginning
+        #[structopt(long)]
+        restart: bool,
+    },
+
+    /// Deletes the essential files of the toolchains no release or toolchain uses anymore
+    GcEssentialFiles {
+        /// Only list the unused files, without deleting them
+        #[structopt(long)]
+        dry_run: bool,
     },
 }
 
@@ -447,8 +776,47 @@ impl DatabaseSubcommand {
                 .context("failed to delete the crate")?,
             Self::Blacklist { command } => command.handle_args(ctx)?,
 
-            Self::Synchronize { dry_run } => {
-                docs_rs::utils::consistency::run_check(&mut *ctx.conn()?, &*ctx.index()?, dry_run)?;
+            Self::Synchronize {
+                dry_run,
+                max_actions,
+            } => {
+                docs_rs::utils::consistency::run_check(&ctx, dry_run, max_actions)?;
+            }
+
+            Self::MigrateStorage {
+                from,
+                to,
+                prefix,
+                batch_size,
+                verify,
+                restart,
+            } => {
+                let options = MigrationOptions {
+                    prefix,
+                    batch_size,
+                    verify,
+                    restart,
+                };
+                let copied = docs_rs::storage::migrate_storage(&ctx, from, to, &options)
+                    .context("failed to migrate the storage")?;
+                println!("Copied {} files from {} to {}", copied, from, to);
+            }
+
+            Self::GcEssentialFiles { dry_run } => {
+                let unused = docs_rs::utils::gc_essential_files(
+                    &mut *ctx.conn()?,
+                    &*ctx.storage()?,
+                    dry_run,
+                )
+                .context("failed to delete the unused essential files")?;
+                for (suffix, paths) in &unused {
+                    println!("{}: {} files", suffix, paths.len());
+                }
+                if unused.is_empty() {
+                    println!("No unused essential files");
+                } else if dry_run {
+                    println!("Dry run, nothing was deleted");
+                }
             }
         }
         Ok(())
@@ -496,6 +864,148 @@ impl BlacklistSubcommand {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, StructOpt)]
+enum StorageSubcommand {
+    /// Compares the stored documentation and sources with the releases, reporting the files that
+    /// don't belong to any release and the releases whose files are missing
+    Gc {
+        /// Delete the orphaned files. No builds must be running outside of the build queue, as
+        /// their files are stored before their release is added.
+        #[structopt(long)]
+        delete: bool,
+    },
+}
+
+impl StorageSubcommand {
+    fn handle_args(self, ctx: BinContext) -> Result<(), Error> {
+        match self {
+            Self::Gc { delete } => {
+                let report =
+                    docs_rs::storage::gc_storage(&mut *ctx.conn()?, &*ctx.storage()?, !delete)
+                        .context("failed to collect the orphaned storage")?;
+                for path in &report.orphans {
+                    println!("orphaned: {}", path);
+                }
+                for (name, version) in &report.missing_docs {
+                    println!("missing documentation: {} {}", name, version);
+                }
+                for (name, version) in &report.missing_sources {
+                    println!("missing sources: {} {}", name, version);
+                }
+                println!("{}", report);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, StructOpt)]
+enum LimitsSubcommand {
+    /// Show the sandbox limits of a crate
+    Get {
+        /// Crate name
+        #[structopt(name = "CRATE_NAME")]
+        crate_name: String,
+    },
+
+    /// Override the sandbox limits of a crate, keeping the limits not passed unchanged
+    Set {
+        /// Crate name
+        #[structopt(name = "CRATE_NAME")]
+        crate_name: String,
+        /// Available memory, in bytes
+        #[structopt(long)]
+        memory: Option<u64>,
+        /// Maximum build time, in seconds
+        #[structopt(long)]
+        timeout: Option<u64>,
+        /// Maximum number of targets to build
+        #[structopt(long)]
+        targets: Option<u32>,
+        /// Whether the build can access the network (true or false)
+        #[structopt(long)]
+        networking: Option<bool>,
+        /// Maximum size of the build log, in bytes
+        #[structopt(long)]
+        max_log_size: Option<u64>,
+        /// Number of CPUs available to the build
+        #[structopt(long)]
+        cpu_limit: Option<u32>,
+        /// Why the limits are changed, stored for future reference
+        #[structopt(long)]
+        reason: String,
+    },
+
+    /// Remove the overrides of a crate, restoring the default sandbox limits
+    Remove {
+        /// Crate name
+        #[structopt(name = "CRATE_NAME")]
+        crate_name: String,
+    },
+}
+
+impl LimitsSubcommand {
+    fn handle_args(self, ctx: BinContext) -> Result<(), Error> {
+        let conn = &mut *ctx.conn()?;
+        match self {
+            Self::Get { crate_name } => {
+                let limits = Limits::for_crate(&*ctx.config()?, conn, &crate_name)?;
+                println!("memory: {} bytes", limits.memory());
+                println!("timeout: {} seconds", limits.timeout().as_secs());
+                println!("targets: {}", limits.targets());
+                println!("networking: {}", limits.networking());
+                println!("max log size: {} bytes", limits.max_log_size());
+                match limits.cpu_limit() {
+                    Some(cpus) => println!("cpus: {}", cpus),
+                    None => println!("cpus: unlimited"),
+                }
+
+                match sandbox_overrides::get_override(conn, &crate_name)? {
+                    Some(overrides) => {
+                        print!("overridden");
+                        if let Some(updated_at) = overrides.updated_at {
+                            print!(" on {}", updated_at);
+                        }
+                        if let Some(reason) = overrides.reason {
+                            print!(": {}", reason);
+                        }
+                        println!();
+                    }
+                    None => println!("using the default limits"),
+                }
+            }
+
+            Self::Set {
+                crate_name,
+                memory,
+                timeout,
+                targets,
+                networking,
+                max_log_size,
+                cpu_limit,
+                reason,
+            } => {
+                let overrides = SandboxOverride {
+                    memory,
+                    timeout: timeout.map(Duration::from_secs),
+                    targets,
+                    networking,
+                    max_log_size,
+                    cpu_limit,
+                    reason: Some(reason),
+                    updated_at: None,
+                };
+                sandbox_overrides::set_override(conn, &crate_name, &overrides)
+                    .context("failed to override the sandbox limits")?;
+            }
+
+            Self::Remove { crate_name } => sandbox_overrides::remove_override(conn, &crate_name)
+                .context("failed to remove the sandbox overrides")?,
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, StructOpt)]
 enum DeleteSubcommand {
     /// Delete a whole crate
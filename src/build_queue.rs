@@ -1,16 +1,49 @@
 use crate::db::Pool;
 use crate::error::Result;
 use crate::{Config, Metrics};
+use chrono::{DateTime, NaiveDate, Utc};
 use log::error;
 use std::sync::Arc;
 
 #[derive(Debug, Clone, Eq, PartialEq, serde::Serialize)]
-pub(crate) struct QueuedCrate {
+pub struct QueuedCrate {
     #[serde(skip)]
     id: i32,
-    pub(crate) name: String,
-    pub(crate) version: String,
-    pub(crate) priority: i32,
+    pub name: String,
+    pub version: String,
+    pub priority: i32,
+    pub attempt: i32,
+    /// When a failed build will be retried, if it failed before.
+    pub next_attempt_at: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+}
+
+impl QueuedCrate {
+    fn from_row(row: &postgres::Row) -> Self {
+        QueuedCrate {
+            id: row.get("id"),
+            name: row.get("name"),
+            version: row.get("version"),
+            priority: row.get("priority"),
+            attempt: row.get("attempt"),
+            next_attempt_at: row.get("next_attempt_at"),
+            last_error: row.get("last_error"),
+        }
+    }
+}
+
+/// Filters selecting the releases to rebuild with [`BuildQueue::rebuild_releases`]. Only the
+/// releases matching all the filters are selected.
+#[derive(Debug, Clone, Default)]
+pub struct RebuildFilter {
+    /// Only select the releases built with a rustc older than this date.
+    pub rustc_version_before: Option<NaiveDate>,
+    /// Only select the releases that failed to build.
+    pub failed_only: bool,
+    /// Only select the releases published on or after this date.
+    pub released_after: Option<NaiveDate>,
+    /// Only select the crates whose name matches this `LIKE` pattern.
+    pub crate_pattern: Option<String>,
 }
 
 #[derive(Debug)]
@@ -18,6 +51,8 @@ pub struct BuildQueue {
     db: Pool,
     metrics: Arc<Metrics>,
     max_attempts: i32,
+    lease_duration: u64,
+    retry_delay: u64,
 }
 
 impl BuildQueue {
@@ -26,12 +61,18 @@ impl BuildQueue {
             db,
             metrics,
             max_attempts: config.build_attempts.into(),
+            lease_duration: config.build_lease_duration,
+            retry_delay: config.build_retry_delay,
         }
     }
 
+    /// Adds a crate to the queue. Adding a crate that's already queued does nothing, so it's safe
+    /// to process the same index changes multiple times.
     pub fn add_crate(&self, name: &str, version: &str, priority: i32) -> Result<()> {
         self.db.get()?.execute(
-            "INSERT INTO queue (name, version, priority) VALUES ($1, $2, $3);",
+            "INSERT INTO queue (name, version, priority)
+             VALUES ($1, $2, $3)
+             ON CONFLICT (name, version) DO NOTHING;",
             &[&name, &version, &priority],
         )?;
         Ok(())
@@ -61,35 +102,154 @@ impl BuildQueue {
         Ok(res[0].get::<_, i64>(0) as usize)
     }
 
-    pub(crate) fn queued_crates(&self) -> Result<Vec<QueuedCrate>> {
+    /// Returns the crates waiting to be built, in the order they will be built.
+    pub fn queued_crates(&self) -> Result<Vec<QueuedCrate>> {
         let query = self.db.get()?.query(
-            "SELECT id, name, version, priority
+            "SELECT id, name, version, priority, attempt, next_attempt_at, last_error
              FROM queue
              WHERE attempt < $1
              ORDER BY priority ASC, attempt ASC, id ASC",
             &[&self.max_attempts],
         )?;
 
-        Ok(query
-            .into_iter()
-            .map(|row| QueuedCrate {
-                id: row.get("id"),
-                name: row.get("name"),
-                version: row.get("version"),
-                priority: row.get("priority"),
-            })
-            .collect())
+        Ok(query.iter().map(QueuedCrate::from_row).collect())
+    }
+
+    /// Returns the crates that failed to build too many times, and won't be retried.
+    pub fn failed_crates(&self) -> Result<Vec<QueuedCrate>> {
+        let query = self.db.get()?.query(
+            "SELECT id, name, version, priority, attempt, next_attempt_at, last_error
+             FROM queue
+             WHERE attempt >= $1
+             ORDER BY name ASC, version ASC",
+            &[&self.max_attempts],
+        )?;
+
+        Ok(query.iter().map(QueuedCrate::from_row).collect())
+    }
+
+    /// Removes all the versions of a crate from the queue, or only one of them if `version` is
+    /// passed. Returns the number of removed crates.
+    pub fn remove_crate(&self, name: &str, version: Option<&str>) -> Result<u64> {
+        Ok(self.db.get()?.execute(
+            "DELETE FROM queue WHERE name = $1 AND ($2::TEXT IS NULL OR version = $2);",
+            &[&name, &version],
+        )?)
+    }
+
+    /// Resets the attempts of a crate, so that it's built again as soon as possible even if it
+    /// failed too many times. Returns whether the crate is in the queue.
+    pub fn retry_crate(&self, name: &str, version: &str) -> Result<bool> {
+        let updated = self.db.get()?.execute(
+            "UPDATE queue
+             SET attempt = 0, next_attempt_at = NULL, last_error = NULL
+             WHERE name = $1 AND version = $2;",
+            &[&name, &version],
+        )?;
+        Ok(updated > 0)
+    }
+
+    /// Changes the priority of a queued crate. Returns whether the crate is in the queue.
+    pub fn set_priority(&self, name: &str, version: &str, priority: i32) -> Result<bool> {
+        let updated = self.db.get()?.execute(
+            "UPDATE queue SET priority = $3 WHERE name = $1 AND version = $2;",
+            &[&name, &version, &priority],
+        )?;
+        Ok(updated > 0)
+    }
+
+    /// Adds all the releases matching `filter` to the queue with the given priority, skipping the
+    /// yanked releases and the ones already in the queue. Returns the number of queued releases,
+    /// or the number of releases that would be queued if `dry_run` is set.
+    pub fn rebuild_releases(
+        &self,
+        filter: &RebuildFilter,
+        priority: i32,
+        dry_run: bool,
+    ) -> Result<u64> {
+        // The date of the rustc version is the last part of the version string, for example
+        // `rustc 1.46.0-nightly (346aec9b0 2020-07-11)`.
+        let selected = r"
+            SELECT crates.name, releases.version
+            FROM releases
+            INNER JOIN crates ON crates.id = releases.crate_id
+            WHERE NOT releases.yanked
+                AND ($1::DATE IS NULL OR
+                    SUBSTRING(releases.doc_rustc_version FROM '(\d{4}-\d{2}-\d{2})\)$')::DATE < $1)
+                AND (NOT $2 OR NOT releases.build_status)
+                AND ($3::DATE IS NULL OR releases.release_time >= $3)
+                AND ($4::TEXT IS NULL OR crates.name LIKE $4)
+                AND NOT EXISTS (
+                    SELECT 1 FROM queue
+                    WHERE queue.name = crates.name AND queue.version = releases.version
+                )";
+        let mut params: Vec<&(dyn postgres::types::ToSql + Sync)> = vec![
+            &filter.rustc_version_before,
+            &filter.failed_only,
+            &filter.released_after,
+            &filter.crate_pattern,
+        ];
+
+        let mut conn = self.db.get()?;
+        if dry_run {
+            let count: i64 = conn
+                .query_one(
+                    format!("SELECT COUNT(*) FROM ({}) AS selected;", selected).as_str(),
+                    &params,
+                )?
+                .get(0);
+            Ok(count as u64)
+        } else {
+            params.push(&priority);
+            Ok(conn.execute(
+                format!(
+                    "INSERT INTO queue (name, version, priority)
+                     SELECT name, version, $5 FROM ({}) AS selected
+                     ON CONFLICT (name, version) DO NOTHING;",
+                    selected
+                )
+                .as_str(),
+                &params,
+            )?)
+        }
     }
 
+    /// Claims the next crate in the queue on behalf of `worker`, and processes it with `f`.
+    ///
+    /// Crates whose build failed are retried with an exponential backoff, starting from the
+    /// configured retry delay.
+    ///
+    /// Crates claimed by other workers are skipped, so multiple workers (even on different
+    /// machines) can process the queue at the same time. If a worker dies while processing a
+    /// crate, the claim expires after the configured lease duration and the crate is picked up
+    /// again by another worker.
     pub(crate) fn process_next_crate(
         &self,
+        worker: &str,
         f: impl FnOnce(&QueuedCrate) -> Result<()>,
     ) -> Result<()> {
         let mut conn = self.db.get()?;
 
-        let queued = self.queued_crates()?;
-        let to_process = match queued.get(0) {
-            Some(krate) => krate,
+        // Locking the row with SKIP LOCKED prevents two workers from claiming it at the same
+        // time, while the claim itself prevents it from being picked up during the build.
+        let rows = conn.query(
+            "UPDATE queue
+             SET claimed_by = $1, claimed_at = NOW()
+             WHERE id = (
+                 SELECT id
+                 FROM queue
+                 WHERE attempt < $2
+                   AND (claimed_at IS NULL OR claimed_at < NOW() - make_interval(secs => $3))
+                   AND (next_attempt_at IS NULL OR next_attempt_at <= NOW())
+                 ORDER BY priority ASC, attempt ASC, id ASC
+                 LIMIT 1
+                 FOR UPDATE SKIP LOCKED
+             )
+             RETURNING id, name, version, priority, attempt, next_attempt_at, last_error;",
+            &[&worker, &self.max_attempts, &(self.lease_duration as f64)],
+        )?;
+        let to_process = match rows.get(0) {
+            Some(row) => QueuedCrate::from_row(row),
             None => return Ok(()),
         };
 
@@ -100,10 +260,17 @@ impl BuildQueue {
                 conn.execute("DELETE FROM queue WHERE id = $1;", &[&to_process.id])?;
             }
             Err(e) => {
-                // Increase attempt count
+                // Increase attempt count, and release the crate so that it can be retried later
                 let rows = conn.query(
-                    "UPDATE queue SET attempt = attempt + 1 WHERE id = $1 RETURNING attempt;",
-                    &[&to_process.id],
+                    "UPDATE queue
+                     SET attempt = attempt + 1,
+                         claimed_by = NULL,
+                         claimed_at = NULL,
+                         next_attempt_at = NOW() + make_interval(secs => $2 * power(2, attempt)),
+                         last_error = $3
+                     WHERE id = $1
+                     RETURNING attempt;",
+                    &[&to_process.id, &(self.retry_delay as f64), &e.to_string()],
                 )?;
                 let attempt: i32 = rows[0].get(0);
 
@@ -129,6 +296,8 @@ impl BuildQueue {
 mod tests {
     use super::*;
 
+    const WORKER: &str = "test-worker";
+
     #[test]
     fn test_add_and_process_crates() {
         const MAX_ATTEMPTS: u16 = 3;
@@ -136,6 +305,8 @@ mod tests {
         crate::test::wrapper(|env| {
             env.override_config(|config| {
                 config.build_attempts = MAX_ATTEMPTS;
+                // Retry failed builds immediately.
+                config.build_retry_delay = 0;
             });
 
             let queue = env.build_queue();
@@ -153,14 +324,14 @@ mod tests {
             }
 
             let assert_next = |name| -> Result<()> {
-                queue.process_next_crate(|krate| {
+                queue.process_next_crate(WORKER, |krate| {
                     assert_eq!(name, krate.name);
                     Ok(())
                 })?;
                 Ok(())
             };
             let assert_next_and_fail = |name| -> Result<()> {
-                queue.process_next_crate(|krate| {
+                queue.process_next_crate(WORKER, |krate| {
                     assert_eq!(name, krate.name);
                     failure::bail!("simulate a failure");
                 })?;
@@ -193,7 +364,7 @@ mod tests {
             // Since low-priority failed many times it will be removed from the queue. Because of
             // that the queue should now be empty.
             let mut called = false;
-            queue.process_next_crate(|_| {
+            queue.process_next_crate(WORKER, |_| {
                 called = true;
                 Ok(())
             })?;
@@ -208,6 +379,150 @@ mod tests {
         })
     }
 
+    #[test]
+    fn test_retry_backoff() {
+        crate::test::wrapper(|env| {
+            env.override_config(|config| {
+                config.build_retry_delay = 60;
+            });
+            let queue = env.build_queue();
+            queue.add_crate("foo", "1.0.0", -10)?;
+            queue.add_crate("bar", "1.0.0", 0)?;
+
+            queue.process_next_crate(WORKER, |krate| {
+                assert_eq!("foo", krate.name);
+                failure::bail!("network error");
+            })?;
+
+            // The failed crate is not retried before the delay, even with a higher priority.
+            queue.process_next_crate(WORKER, |krate| {
+                assert_eq!("bar", krate.name);
+                Ok(())
+            })?;
+            let mut called = false;
+            queue.process_next_crate(WORKER, |_| {
+                called = true;
+                Ok(())
+            })?;
+            assert!(!called, "the failed crate was retried too early");
+
+            let queued = queue.queued_crates()?;
+            assert_eq!(queued.len(), 1);
+            assert_eq!(queued[0].attempt, 1);
+            assert_eq!(queued[0].last_error.as_deref(), Some("network error"));
+            let delay = queued[0].next_attempt_at.unwrap() - Utc::now();
+            assert!(
+                delay > chrono::Duration::seconds(50) && delay <= chrono::Duration::seconds(60)
+            );
+
+            // The delay doubles after every failed attempt.
+            let mut conn = env.db().conn();
+            conn.execute("UPDATE queue SET next_attempt_at = NOW()", &[])?;
+            queue.process_next_crate(WORKER, |_| failure::bail!("network error again"))?;
+            let queued = queue.queued_crates()?;
+            assert_eq!(queued[0].attempt, 2);
+            assert_eq!(queued[0].last_error.as_deref(), Some("network error again"));
+            let delay = queued[0].next_attempt_at.unwrap() - Utc::now();
+            assert!(
+                delay > chrono::Duration::seconds(110) && delay <= chrono::Duration::seconds(120)
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_claimed_crates_are_skipped() {
+        crate::test::wrapper(|env| {
+            let queue = env.build_queue();
+            queue.add_crate("foo", "1.0.0", 0)?;
+            queue.add_crate("bar", "1.0.0", 0)?;
+
+            let mut other_worker_built = None;
+            queue.process_next_crate("first-worker", |krate| {
+                assert_eq!("foo", krate.name);
+
+                // While foo is being built, another worker picks up the next crate.
+                queue.process_next_crate("second-worker", |krate| {
+                    other_worker_built = Some(krate.name.clone());
+                    Ok(())
+                })?;
+
+                // No crates are left for a third worker.
+                queue.process_next_crate("third-worker", |krate| {
+                    panic!("{} was built twice", krate.name);
+                })?;
+
+                Ok(())
+            })?;
+
+            assert_eq!(other_worker_built.as_deref(), Some("bar"));
+            assert_eq!(queue.pending_count()?, 0);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_expired_claims_are_released() {
+        crate::test::wrapper(|env| {
+            env.override_config(|config| {
+                config.build_lease_duration = 60 * 60;
+            });
+            let queue = env.build_queue();
+            queue.add_crate("foo", "1.0.0", 0)?;
+
+            // Simulate a worker that claimed the crate and died while building it.
+            let mut conn = env.db().conn();
+            conn.execute(
+                "UPDATE queue SET claimed_by = 'dead-worker', claimed_at = NOW()",
+                &[],
+            )?;
+
+            let mut built = false;
+            queue.process_next_crate(WORKER, |_| {
+                built = true;
+                Ok(())
+            })?;
+            assert!(!built, "a crate with an active claim was built");
+
+            conn.execute(
+                "UPDATE queue SET claimed_at = NOW() - INTERVAL '2 hours'",
+                &[],
+            )?;
+            queue.process_next_crate(WORKER, |krate| {
+                assert_eq!("foo", krate.name);
+                built = true;
+                Ok(())
+            })?;
+            assert!(built, "the expired claim was not released");
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_add_duplicate_crate() {
+        crate::test::wrapper(|env| {
+            let queue = env.build_queue();
+
+            queue.add_crate("foo", "1.0.0", 0)?;
+            queue.add_crate("foo", "1.0.0", -10)?;
+            queue.add_crate("foo", "2.0.0", 0)?;
+
+            assert_eq!(
+                vec![("foo", "1.0.0", 0), ("foo", "2.0.0", 0)],
+                queue
+                    .queued_crates()?
+                    .iter()
+                    .map(|c| (c.name.as_str(), c.version.as_str(), c.priority))
+                    .collect::<Vec<_>>()
+            );
+
+            Ok(())
+        });
+    }
+
     #[test]
     fn test_pending_count() {
         crate::test::wrapper(|env| {
@@ -219,7 +534,7 @@ mod tests {
             queue.add_crate("bar", "1.0.0", 0)?;
             assert_eq!(queue.pending_count()?, 2);
 
-            queue.process_next_crate(|krate| {
+            queue.process_next_crate(WORKER, |krate| {
                 assert_eq!("foo", krate.name);
                 Ok(())
             })?;
@@ -242,7 +557,7 @@ mod tests {
             queue.add_crate("baz", "1.0.0", 100)?;
             assert_eq!(queue.prioritized_count()?, 2);
 
-            queue.process_next_crate(|krate| {
+            queue.process_next_crate(WORKER, |krate| {
                 assert_eq!("bar", krate.name);
                 Ok(())
             })?;
@@ -258,6 +573,8 @@ mod tests {
         crate::test::wrapper(|env| {
             env.override_config(|config| {
                 config.build_attempts = MAX_ATTEMPTS;
+                // Retry failed builds immediately.
+                config.build_retry_delay = 0;
             });
             let queue = env.build_queue();
 
@@ -268,14 +585,14 @@ mod tests {
 
             for _ in 0..MAX_ATTEMPTS {
                 assert_eq!(queue.failed_count()?, 0);
-                queue.process_next_crate(|krate| {
+                queue.process_next_crate(WORKER, |krate| {
                     assert_eq!("foo", krate.name);
                     failure::bail!("this failed");
                 })?;
             }
             assert_eq!(queue.failed_count()?, 1);
 
-            queue.process_next_crate(|krate| {
+            queue.process_next_crate(WORKER, |krate| {
                 assert_eq!("bar", krate.name);
                 Ok(())
             })?;
@@ -285,6 +602,153 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_manage_queued_crates() {
+        const MAX_ATTEMPTS: u16 = 1;
+        crate::test::wrapper(|env| {
+            env.override_config(|config| {
+                config.build_attempts = MAX_ATTEMPTS;
+            });
+            let queue = env.build_queue();
+            let queued = |queue: &BuildQueue| -> Result<Vec<(String, String, i32)>> {
+                Ok(queue
+                    .queued_crates()?
+                    .into_iter()
+                    .map(|c| (c.name, c.version, c.priority))
+                    .collect())
+            };
+
+            queue.add_crate("foo", "1.0.0", 0)?;
+            queue.add_crate("foo", "2.0.0", 0)?;
+            queue.add_crate("bar", "1.0.0", 0)?;
+            queue.add_crate("baz", "1.0.0", 0)?;
+
+            assert!(queue.set_priority("bar", "1.0.0", -10)?);
+            assert!(!queue.set_priority("bar", "2.0.0", -10)?);
+            assert_eq!(queued(&queue)?[0], ("bar".into(), "1.0.0".into(), -10));
+
+            // Removing a single version, and then all of them.
+            assert_eq!(queue.remove_crate("foo", Some("2.0.0"))?, 1);
+            assert_eq!(queue.remove_crate("foo", Some("2.0.0"))?, 0);
+            queue.add_crate("foo", "2.0.0", 0)?;
+            assert_eq!(queue.remove_crate("foo", None)?, 2);
+            assert_eq!(
+                queued(&queue)?,
+                vec![
+                    ("bar".into(), "1.0.0".into(), -10),
+                    ("baz".into(), "1.0.0".into(), 0),
+                ]
+            );
+
+            // Failed crates can be retried.
+            queue.process_next_crate(WORKER, |_| failure::bail!("failed"))?;
+            let failed = queue.failed_crates()?;
+            assert_eq!(failed.len(), 1);
+            assert_eq!(failed[0].name, "bar");
+            assert_eq!(failed[0].last_error.as_deref(), Some("failed"));
+
+            assert!(queue.retry_crate("bar", "1.0.0")?);
+            assert!(!queue.retry_crate("bar", "2.0.0")?);
+            assert!(queue.failed_crates()?.is_empty());
+            queue.process_next_crate(WORKER, |krate| {
+                assert_eq!("bar", krate.name);
+                Ok(())
+            })?;
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_rebuild_releases() {
+        crate::test::wrapper(|env| {
+            let old_rustc = "rustc 1.40.0-nightly (000000000 2019-10-01)";
+            let new_rustc = "rustc 1.50.0-nightly (000000000 2020-12-01)";
+            let release_time = |date: &str| -> DateTime<Utc> {
+                DateTime::parse_from_rfc3339(&format!("{}T12:00:00Z", date))
+                    .unwrap()
+                    .into()
+            };
+            for (name, rustc_version, successful, released) in &[
+                ("old-rustc", old_rustc, true, "2019-10-02"),
+                ("new-rustc", new_rustc, true, "2020-12-02"),
+                ("old-rustc-failed", old_rustc, false, "2019-10-02"),
+                ("new-rustc-failed", new_rustc, false, "2020-12-02"),
+            ] {
+                env.fake_release()
+                    .name(name)
+                    .rustc_version(rustc_version)
+                    .build_result_successful(*successful)
+                    .release_time(release_time(released))
+                    .create()?;
+            }
+            env.fake_release()
+                .name("yanked-failed")
+                .build_result_successful(false)
+                .yanked(true)
+                .create()?;
+
+            let queue = env.build_queue();
+            let date = |date: &str| Some(NaiveDate::parse_from_str(date, "%Y-%m-%d").unwrap());
+            let count = |filter: RebuildFilter| queue.rebuild_releases(&filter, 10, true);
+
+            assert_eq!(count(RebuildFilter::default())?, 4);
+            assert_eq!(
+                count(RebuildFilter {
+                    rustc_version_before: date("2020-01-01"),
+                    ..RebuildFilter::default()
+                })?,
+                2
+            );
+            assert_eq!(
+                count(RebuildFilter {
+                    failed_only: true,
+                    ..RebuildFilter::default()
+                })?,
+                2
+            );
+            assert_eq!(
+                count(RebuildFilter {
+                    released_after: date("2020-12-02"),
+                    ..RebuildFilter::default()
+                })?,
+                2
+            );
+            assert_eq!(
+                count(RebuildFilter {
+                    crate_pattern: Some("%-failed".into()),
+                    released_after: date("2020-01-01"),
+                    ..RebuildFilter::default()
+                })?,
+                1
+            );
+            // Dry runs don't queue anything.
+            assert!(queue.queued_crates()?.is_empty());
+
+            // Releases already in the queue are skipped.
+            queue.add_crate("old-rustc-failed", "1.0.0", 0)?;
+            let filter = RebuildFilter {
+                rustc_version_before: date("2020-01-01"),
+                ..RebuildFilter::default()
+            };
+            assert_eq!(queue.rebuild_releases(&filter, 10, false)?, 1);
+            assert_eq!(queue.rebuild_releases(&filter, 10, false)?, 0);
+            assert_eq!(
+                queue
+                    .queued_crates()?
+                    .into_iter()
+                    .map(|c| (c.name, c.priority))
+                    .collect::<Vec<_>>(),
+                vec![
+                    ("old-rustc-failed".to_string(), 0),
+                    ("old-rustc".to_string(), 10)
+                ]
+            );
+
+            Ok(())
+        });
+    }
+
     #[test]
     fn test_queued_crates() {
         crate::test::wrapper(|env| {
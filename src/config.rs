@@ -9,10 +9,32 @@ use std::str::FromStr;
 pub struct Config {
     // Build params
     pub(crate) build_attempts: u16,
+    // Number of crates built at the same time by the daemon
+    pub(crate) build_workers: usize,
+    // Time after which a crate claimed by a builder is considered abandoned, in seconds. This must
+    // be longer than the time a build can take, or the crate will be built twice.
+    pub(crate) build_lease_duration: u64,
+    // Time to wait before retrying a failed build for the first time, in seconds. The delay is
+    // doubled after every failed attempt.
+    pub(crate) build_retry_delay: u64,
+    // Time between updates of the toolchain used by the daemon, in seconds
+    pub(crate) toolchain_update_interval: u64,
+    // Default sandbox limits of the builds, which can be overridden for single crates
+    pub(crate) build_default_memory_limit: usize,
+    // Maximum build time, in seconds
+    pub(crate) build_default_timeout: u64,
+    pub(crate) build_default_max_targets: usize,
+    pub(crate) build_default_networking: bool,
+    pub(crate) build_default_max_log_size: usize,
+    pub(crate) build_cpu_limit: Option<u32>,
 
     pub prefix: PathBuf,
     pub registry_index_path: PathBuf,
 
+    // Public URL of the instance without the trailing slash, used in the links that must be
+    // absolute, like the dependencies' documentation, the sitemap and the feeds
+    pub(crate) base_url: String,
+
     // Database connection params
     pub(crate) database_url: String,
     pub(crate) max_pool_size: u32,
@@ -28,6 +50,14 @@ pub struct Config {
     #[cfg(test)]
     pub(crate) s3_bucket_is_temporary: bool,
 
+    // Local storage params
+    pub(crate) local_storage_path: PathBuf,
+    #[cfg(test)]
+    pub(crate) local_storage_is_temporary: bool,
+
+    // Secret used to validate the signature of the registry index webhooks
+    pub(crate) index_webhook_secret: Option<String>,
+
     // Github authentication
     pub(crate) github_username: Option<String>,
     pub(crate) github_accesstoken: Option<String>,
@@ -44,7 +74,6 @@ pub struct Config {
     pub(crate) inside_docker: bool,
     pub(crate) local_docker_image: Option<String>,
     pub(crate) toolchain: String,
-    pub(crate) build_cpu_limit: Option<u32>,
 }
 
 impl Config {
@@ -53,10 +82,27 @@ impl Config {
 
         Ok(Self {
             build_attempts: env("DOCSRS_BUILD_ATTEMPTS", 5)?,
+            build_workers: env("DOCSRS_BUILD_WORKERS", 1)?,
+            build_lease_duration: env("DOCSRS_BUILD_LEASE_DURATION", 2 * 60 * 60)?,
+            build_retry_delay: env("DOCSRS_BUILD_RETRY_DELAY", 5 * 60)?,
+            toolchain_update_interval: env("DOCSRS_TOOLCHAIN_UPDATE_INTERVAL", 60 * 60)?,
+            build_default_memory_limit: env(
+                "DOCSRS_BUILD_DEFAULT_MEMORY_LIMIT",
+                3 * 1024 * 1024 * 1024,
+            )?,
+            build_default_timeout: env("DOCSRS_BUILD_DEFAULT_TIMEOUT", 15 * 60)?,
+            build_default_max_targets: env("DOCSRS_BUILD_DEFAULT_MAX_TARGETS", 10)?,
+            build_default_networking: env("DOCSRS_BUILD_DEFAULT_NETWORKING", false)?,
+            build_default_max_log_size: env("DOCSRS_BUILD_DEFAULT_MAX_LOG_SIZE", 100 * 1024)?,
+            build_cpu_limit: maybe_env("DOCS_RS_BUILD_CPU_LIMIT")?,
 
             prefix: prefix.clone(),
             registry_index_path: env("REGISTRY_INDEX_PATH", prefix.join("crates.io-index"))?,
 
+            base_url: env("DOCSRS_BASE_URL", "https://docs.rs".to_string())?
+                .trim_end_matches('/')
+                .into(),
+
             database_url: require_env("CRATESFYI_DATABASE_URL")?,
             max_pool_size: env("DOCSRS_MAX_POOL_SIZE", 90)?,
             min_pool_idle: env("DOCSRS_MIN_POOL_IDLE", 10)?,
@@ -72,6 +118,14 @@ impl Config {
             #[cfg(test)]
             s3_bucket_is_temporary: false,
 
+            local_storage_path: env("DOCSRS_LOCAL_STORAGE_PATH", prefix.join("storage"))?,
+            // DO NOT CONFIGURE THIS THROUGH AN ENVIRONMENT VARIABLE!
+            // Same as `s3_bucket_is_temporary`, the whole directory is removed after the tests.
+            #[cfg(test)]
+            local_storage_is_temporary: false,
+
+            index_webhook_secret: maybe_env("DOCSRS_INDEX_WEBHOOK_SECRET")?,
+
             github_username: maybe_env("CRATESFYI_GITHUB_USERNAME")?,
             github_accesstoken: maybe_env("CRATESFYI_GITHUB_ACCESSTOKEN")?,
 
@@ -86,7 +140,6 @@ impl Config {
             inside_docker: env("DOCS_RS_DOCKER", false)?,
             local_docker_image: maybe_env("DOCS_RS_LOCAL_DOCKER_IMAGE")?,
             toolchain: env("CRATESFYI_TOOLCHAIN", "nightly".to_string())?,
-            build_cpu_limit: maybe_env("DOCS_RS_BUILD_CPU_LIMIT")?,
         })
     }
 
@@ -5,7 +5,7 @@ use std::{
 };
 
 use crate::{
-    docbuilder::{BuildResult, DocCoverage},
+    docbuilder::{BuildFailureReason, BuildResult, BuildStats, DocCoverage, SearchItem},
     error::Result,
     index::api::{CrateData, CrateOwner, ReleaseData},
     storage::CompressionAlgorithm,
@@ -153,18 +153,80 @@ pub(crate) fn add_doc_coverage(
     Ok(rows[0].get(0))
 }
 
+/// Replaces the items listed in the search index of a release. Only the latest releases are
+/// searched, so the items of the older releases of the crate are removed, and the items of a
+/// release aren't added when a newer release is searched instead, like when rebuilding it.
+pub(crate) fn add_search_items(
+    conn: &mut Client,
+    release_id: i32,
+    items: &[SearchItem],
+) -> Result<()> {
+    debug!("Adding {} search items into database", items.len());
+    let mut transaction = conn.transaction()?;
+    transaction.execute(
+        "DELETE FROM search_items
+         WHERE release_id IN (
+             SELECT older.id
+             FROM releases
+             INNER JOIN releases AS older ON older.crate_id = releases.crate_id
+             WHERE releases.id = $1
+               AND (older.id = releases.id OR older.release_time < releases.release_time)
+         )",
+        &[&release_id],
+    )?;
+    let superseded = !transaction
+        .query(
+            "SELECT 1
+             FROM releases
+             INNER JOIN releases AS newer ON newer.crate_id = releases.crate_id
+             WHERE releases.id = $1
+               AND newer.release_time > releases.release_time
+               AND newer.rustdoc_status
+               AND NOT newer.yanked",
+            &[&release_id],
+        )?
+        .is_empty();
+    if superseded {
+        debug!("Not adding search items of a release superseded by a newer one");
+        transaction.commit()?;
+        return Ok(());
+    }
+
+    let statement = transaction.prepare(
+        "INSERT INTO search_items (release_id, name, path, kind, url, description)
+         VALUES ($1, $2, $3, $4, $5, $6)",
+    )?;
+    for item in items {
+        transaction.execute(
+            &statement,
+            &[
+                &release_id,
+                &item.name,
+                &item.path,
+                &item.kind,
+                &item.url,
+                &item.description,
+            ],
+        )?;
+    }
+    transaction.commit()?;
+    Ok(())
+}
+
 /// Adds a build into database
 pub(crate) fn add_build_into_database(
     conn: &mut Client,
     release_id: i32,
     res: &BuildResult,
+    stats: &BuildStats,
 ) -> Result<i32> {
     debug!("Adding build into database");
     let rows = conn.query(
         "INSERT INTO builds (rid, rustc_version,
                                                     cratesfyi_version,
-                                                    build_status, output)
-                                VALUES ($1, $2, $3, $4, $5)
+                                                    build_status, output, failure_reason,
+                                                    duration, documentation_size, peak_memory)
+                                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
                                 RETURNING id",
         &[
             &release_id,
@@ -172,11 +234,50 @@ pub(crate) fn add_build_into_database(
             &res.docsrs_version,
             &res.successful,
             &res.build_log,
+            &res.failure_reason.map(BuildFailureReason::as_str),
+            &stats.duration.as_secs_f64(),
+            &stats.documentation_size.map(|size| size as i64),
+            &stats.peak_memory.map(|peak| peak as i64),
         ],
     )?;
     Ok(rows[0].get(0))
 }
 
+/// Adds the result of building a single target into the database. The log of the default target
+/// is only stored in the build itself.
+pub(crate) fn add_build_target_into_database(
+    conn: &mut Client,
+    build_id: i32,
+    target: &str,
+    is_default_target: bool,
+    res: &BuildResult,
+) -> Result<()> {
+    debug!("Adding build of target {} into database", target);
+    let output = if is_default_target {
+        None
+    } else {
+        Some(&res.build_log)
+    };
+    conn.execute(
+        "INSERT INTO build_targets (build_id, target, rustc_version, build_status,
+                                    failure_reason, duration, documentation_size, output,
+                                    peak_memory)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+        &[
+            &build_id,
+            &target,
+            &res.rustc_version,
+            &res.successful,
+            &res.failure_reason.map(BuildFailureReason::as_str),
+            &res.duration.as_secs_f64(),
+            &res.documentation_size.map(|size| size as i64),
+            &output,
+            &res.peak_memory.map(|peak| peak as i64),
+        ],
+    )?;
+    Ok(())
+}
+
 fn initialize_package_in_database(conn: &mut Client, pkg: &MetadataPackage) -> Result<i32> {
     let mut rows = conn.query("SELECT id FROM crates WHERE name = $1", &[&pkg.name])?;
     // insert crate into database if it is not exists
@@ -348,6 +449,25 @@ fn add_authors_into_database(
     Ok(())
 }
 
+/// Updates the yanked status of a release, returning whether the release exists.
+pub(crate) fn set_yanked(
+    conn: &mut Client,
+    name: &str,
+    version: &str,
+    yanked: bool,
+) -> Result<bool> {
+    let updated = conn.execute(
+        "UPDATE releases
+         SET yanked = $3
+         FROM crates
+         WHERE crates.id = releases.crate_id
+            AND name = $1
+            AND version = $2",
+        &[&name, &version, &yanked],
+    )?;
+    Ok(updated > 0)
+}
+
 pub fn update_crate_data_in_database(
     conn: &mut Client,
     name: &str,
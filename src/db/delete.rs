@@ -1,3 +1,4 @@
+use super::escape_like;
 use crate::Storage;
 use failure::{Error, Fail};
 use postgres::Client;
@@ -55,6 +56,7 @@ const METADATA: &[(&str, &str)] = &[
     ("builds", "rid"),
     ("compression_rels", "release"),
     ("doc_coverage", "release_id"),
+    ("search_items", "release_id"),
 ];
 
 fn delete_version_from_database(conn: &mut Client, name: &str, version: &str) -> Result<(), Error> {
@@ -82,7 +84,10 @@ fn delete_version_from_database(conn: &mut Client, name: &str, version: &str) ->
     for prefix in STORAGE_PATHS_TO_DELETE {
         transaction.execute(
             "DELETE FROM files WHERE path LIKE $1;",
-            &[&format!("{}/{}/{}/%", prefix, name, version)],
+            &[&format!(
+                "{}%",
+                escape_like(&format!("{}/{}/{}/", prefix, name, version))
+            )],
         )?;
     }
 
@@ -96,6 +101,10 @@ fn delete_crate_from_database(conn: &mut Client, name: &str, crate_id: i32) -> R
         "DELETE FROM sandbox_overrides WHERE crate_name = $1",
         &[&name],
     )?;
+    transaction.execute(
+        "DELETE FROM crate_toolchains WHERE crate_name = $1",
+        &[&name],
+    )?;
     for &(table, column) in METADATA {
         transaction.execute(
             format!(
@@ -152,6 +161,13 @@ mod tests {
                 .version("2.0.0")
                 .create()?;
             let pkg2_id = env.fake_release().name("package-2").create()?;
+            for name in &["package-1", "package-2"] {
+                crate::db::toolchains::pin_crate_toolchain(
+                    &mut db.conn(),
+                    name,
+                    "nightly-2020-11-21",
+                )?;
+            }
 
             assert!(crate_exists(&mut db.conn(), "package-1")?);
             assert!(crate_exists(&mut db.conn(), "package-2")?);
@@ -171,6 +187,15 @@ mod tests {
             assert!(!release_exists(&mut db.conn(), pkg1_v1_id)?);
             assert!(!release_exists(&mut db.conn(), pkg1_v2_id)?);
             assert!(release_exists(&mut db.conn(), pkg2_id)?);
+            // A crate published again with the same name isn't pinned to the old toolchain.
+            assert_eq!(
+                crate::db::toolchains::crate_toolchain(&mut db.conn(), "package-1")?,
+                None
+            );
+            assert_eq!(
+                crate::db::toolchains::crate_toolchain(&mut db.conn(), "package-2")?,
+                Some("nightly-2020-11-21".into())
+            );
 
             Ok(())
         });
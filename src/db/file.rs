@@ -1,11 +1,11 @@
 //! Simple module to store files in database.
 //!
-//! docs.rs supports two ways of storing files: in a postgres database and in an S3 bucket.
-//! It does not support storing files on disk because of the sheer number of files:
-//! doing so would quickly run into file descriptor limits when running the web server.
+//! docs.rs supports three ways of storing files: in a postgres database, in an S3 bucket and in
+//! a directory on the local filesystem.
 //!
 //! It's recommended that you use the S3 bucket in production to avoid running out of disk space.
-//! However, postgres is still available for testing and backwards compatibility.
+//! However, postgres is still available for testing and backwards compatibility, and the local
+//! filesystem is available for self-hosted instances that don't want to run an S3 server.
 
 use crate::error::Result;
 use crate::storage::{CompressionAlgorithms, Storage};
@@ -15,8 +15,8 @@ use std::path::{Path, PathBuf};
 
 /// Store all files in a directory and return [[mimetype, filename]] as Json
 ///
-/// If there is an S3 Client configured, store files into an S3 bucket;
-/// otherwise, stores files into the 'files' table of the local database.
+/// Depending on the configured storage backend, store files into an S3 bucket, into the 'files'
+/// table of the local database or into a directory on the local filesystem.
 ///
 /// The mimetype is detected using `magic`.
 ///
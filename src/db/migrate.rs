@@ -398,6 +398,311 @@ pub fn migrate(version: Option<Version>, conn: &mut Client) -> CratesfyiResult<(
             // downgrade query
             "DROP TABLE doc_coverage;"
         ),
+        migration!(
+            context,
+            // version
+            17,
+            // description
+            "Store the last seen commit of the registry index",
+            // upgrade query
+            "
+            -- Only a single row is stored in this table, shared by all the machines using the
+            -- database, so that a new machine with a fresh clone of the index can catch up.
+            CREATE TABLE registry_index (
+                id BOOLEAN PRIMARY KEY DEFAULT TRUE CHECK (id),
+                last_seen_commit VARCHAR(40) NOT NULL,
+                updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+            );
+            ",
+            // downgrade query
+            "DROP TABLE registry_index;"
+        ),
+        migration!(
+            context,
+            // version
+            18,
+            // description
+            "Add a full-text search index over the releases",
+            // upgrade query
+            "
+            ALTER TABLE releases ADD COLUMN search_vector tsvector;
+
+            -- The name of the crate is the most relevant part, followed by the description and
+            -- keywords, while the crate-level documentation only matters as a last resort.
+            CREATE FUNCTION release_search_vector(INT, TEXT, JSON, TEXT) RETURNS tsvector AS $$
+                SELECT
+                    setweight(to_tsvector('english',
+                        COALESCE((SELECT name FROM crates WHERE id = $1), '')), 'A') ||
+                    setweight(to_tsvector('english', COALESCE($2, '')), 'B') ||
+                    setweight(to_tsvector('english', COALESCE((
+                        SELECT string_agg(keyword, ' ')
+                        FROM json_array_elements_text(
+                            CASE WHEN json_typeof($3) = 'array' THEN $3 ELSE '[]' END
+                        ) AS keyword
+                    ), '')), 'B') ||
+                    setweight(to_tsvector('english', COALESCE($4, '')), 'D')
+            $$ LANGUAGE sql STABLE;
+
+            CREATE FUNCTION update_release_search_vector() RETURNS trigger AS $$
+            BEGIN
+                NEW.search_vector := release_search_vector(
+                    NEW.crate_id, NEW.description, NEW.keywords, NEW.description_long
+                );
+                RETURN NEW;
+            END
+            $$ LANGUAGE plpgsql;
+
+            CREATE TRIGGER releases_search_vector_update
+                BEFORE INSERT OR UPDATE OF crate_id, description, keywords, description_long
+                ON releases
+                FOR EACH ROW EXECUTE PROCEDURE update_release_search_vector();
+
+            UPDATE releases SET search_vector = release_search_vector(
+                crate_id, description, keywords, description_long
+            );
+
+            CREATE INDEX releases_search_vector_idx ON releases USING gin(search_vector);
+            ",
+            // downgrade query
+            "
+            DROP TRIGGER releases_search_vector_update ON releases;
+            DROP FUNCTION update_release_search_vector();
+            DROP FUNCTION release_search_vector(INT, TEXT, JSON, TEXT);
+            ALTER TABLE releases DROP COLUMN search_vector;
+            "
+        ),
+        migration!(
+            context,
+            // version
+            19,
+            // description
+            "Store the items of the rustdoc search index",
+            // upgrade query
+            "
+            CREATE TABLE search_items (
+                id SERIAL PRIMARY KEY,
+                release_id INT NOT NULL REFERENCES releases(id),
+                name TEXT NOT NULL,
+                path TEXT NOT NULL,
+                kind VARCHAR(32) NOT NULL,
+                url TEXT NOT NULL,
+                description TEXT
+            );
+            CREATE INDEX search_items_release_id_idx ON search_items (release_id);
+            -- Used for prefix searches on the item names.
+            CREATE INDEX search_items_name_idx ON search_items (LOWER(name) text_pattern_ops);
+            ",
+            // downgrade query
+            "DROP TABLE search_items;"
+        ),
+        migration!(
+            context,
+            // version
+            20,
+            // description
+            "Allow builders to claim the crates in the queue",
+            // upgrade query
+            "
+            ALTER TABLE queue
+                ADD COLUMN claimed_by VARCHAR(255),
+                ADD COLUMN claimed_at TIMESTAMPTZ;
+            ",
+            // downgrade query
+            "
+            ALTER TABLE queue
+                DROP COLUMN claimed_by,
+                DROP COLUMN claimed_at;
+            "
+        ),
+        migration!(
+            context,
+            // version
+            21,
+            // description
+            "Delay the retries of failed builds",
+            // upgrade query
+            "
+            ALTER TABLE queue
+                ADD COLUMN next_attempt_at TIMESTAMPTZ,
+                ADD COLUMN last_error TEXT;
+            ",
+            // downgrade query
+            "
+            ALTER TABLE queue
+                DROP COLUMN next_attempt_at,
+                DROP COLUMN last_error;
+            "
+        ),
+        migration!(
+            context,
+            // version
+            22,
+            // description
+            "Store why builds failed",
+            // upgrade query
+            "
+            ALTER TABLE builds ADD COLUMN failure_reason VARCHAR(32);
+            CREATE INDEX builds_failure_reason_idx ON builds (rid, failure_reason);
+            ",
+            // downgrade query
+            "
+            DROP INDEX builds_failure_reason_idx;
+            ALTER TABLE builds DROP COLUMN failure_reason;
+            "
+        ),
+        migration!(
+            context,
+            // version
+            23,
+            // description
+            "Store the result of building each target",
+            // upgrade query
+            "
+            ALTER TABLE builds ADD PRIMARY KEY (id);
+            CREATE TABLE build_targets (
+                id SERIAL PRIMARY KEY,
+                build_id INT NOT NULL REFERENCES builds(id) ON DELETE CASCADE,
+                target VARCHAR(100) NOT NULL,
+                rustc_version VARCHAR(100) NOT NULL,
+                build_status BOOL NOT NULL,
+                failure_reason VARCHAR(32),
+                -- in seconds
+                duration FLOAT8 NOT NULL,
+                output TEXT,
+                UNIQUE (build_id, target)
+            );
+            ",
+            // downgrade query
+            "
+            DROP TABLE build_targets;
+            ALTER TABLE builds DROP CONSTRAINT builds_pkey;
+            "
+        ),
+        migration!(
+            context,
+            // version
+            24,
+            // description
+            "Store the duration, documentation size and peak memory usage of builds",
+            // upgrade query
+            "
+            -- the duration is in seconds, and the sizes in bytes
+            ALTER TABLE builds
+                ADD COLUMN duration FLOAT8,
+                ADD COLUMN documentation_size BIGINT,
+                ADD COLUMN peak_memory BIGINT;
+            ALTER TABLE build_targets
+                ADD COLUMN documentation_size BIGINT,
+                ADD COLUMN peak_memory BIGINT;
+            ",
+            // downgrade query
+            "
+            ALTER TABLE builds
+                DROP COLUMN duration,
+                DROP COLUMN documentation_size,
+                DROP COLUMN peak_memory;
+            ALTER TABLE build_targets
+                DROP COLUMN documentation_size,
+                DROP COLUMN peak_memory;
+            "
+        ),
+        migration!(
+            context,
+            // version
+            25,
+            // description
+            "Allow networking to be overridden and record why the sandbox limits were changed",
+            // upgrade query
+            "
+            ALTER TABLE sandbox_overrides
+                ADD COLUMN networking BOOL,
+                ADD COLUMN reason TEXT,
+                ADD COLUMN updated_at TIMESTAMPTZ;
+            ",
+            // downgrade query
+            "
+            ALTER TABLE sandbox_overrides
+                DROP COLUMN networking,
+                DROP COLUMN reason,
+                DROP COLUMN updated_at;
+            "
+        ),
+        migration!(
+            context,
+            // version
+            26,
+            // description
+            "Allow the maximum log size and the number of CPUs to be overridden",
+            // upgrade query
+            "
+            ALTER TABLE sandbox_overrides
+                ADD COLUMN max_log_size_bytes BIGINT,
+                ADD COLUMN cpu_limit INT;
+            ",
+            // downgrade query
+            "
+            ALTER TABLE sandbox_overrides
+                DROP COLUMN max_log_size_bytes,
+                DROP COLUMN cpu_limit;
+            "
+        ),
+        migration!(
+            context,
+            // version
+            27,
+            // description
+            "Keep the history of the installed toolchains and allow pinning crates to a toolchain",
+            // upgrade query
+            "
+            CREATE TABLE toolchains (
+                id SERIAL PRIMARY KEY,
+                name VARCHAR(100) NOT NULL,
+                rustc_version VARCHAR(100) NOT NULL,
+                installed_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                rolled_back_at TIMESTAMPTZ
+            );
+            CREATE TABLE crate_toolchains (
+                crate_name VARCHAR(255) NOT NULL PRIMARY KEY,
+                toolchain VARCHAR(100) NOT NULL
+            );
+            ",
+            // downgrade query
+            "
+            DROP TABLE toolchains;
+            DROP TABLE crate_toolchains;
+            "
+        ),
+        migration!(
+            context,
+            // version
+            28,
+            // description
+            "Deduplicate the stored files by the hash of their content",
+            // upgrade query
+            "
+            CREATE TABLE storage_blobs (
+                backend VARCHAR(16) NOT NULL,
+                hash VARCHAR(64) NOT NULL,
+                refcount INT NOT NULL,
+                PRIMARY KEY (backend, hash)
+            );
+            CREATE TABLE storage_paths (
+                backend VARCHAR(16) NOT NULL,
+                -- Sorting the paths by bytes matches the order of the storage backends.
+                path TEXT COLLATE \"C\" NOT NULL,
+                hash VARCHAR(64) NOT NULL,
+                mime VARCHAR(100) NOT NULL,
+                PRIMARY KEY (backend, path),
+                FOREIGN KEY (backend, hash) REFERENCES storage_blobs (backend, hash)
+            );
+            CREATE INDEX storage_paths_hash_idx ON storage_paths (backend, hash);
+            ",
+            // downgrade query
+            "
+            DROP TABLE storage_paths;
+            DROP TABLE storage_blobs;
+            "
+        ),
     ];
 
     for migration in migrations {
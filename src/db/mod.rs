@@ -2,16 +2,28 @@
 
 pub use self::add_package::update_crate_data_in_database;
 pub(crate) use self::add_package::{
-    add_build_into_database, add_doc_coverage, add_package_into_database,
+    add_build_into_database, add_build_target_into_database, add_doc_coverage,
+    add_package_into_database, add_search_items, set_yanked,
 };
 pub use self::delete::{delete_crate, delete_version};
 pub use self::file::add_path_into_database;
 pub use self::migrate::migrate;
 pub use self::pool::{Pool, PoolClient, PoolError};
 
+/// Escape the wildcards of a `LIKE` pattern, so that it's matched literally. The queries using
+/// it must not change the default `\` escape character.
+pub(crate) fn escape_like(pattern: &str) -> String {
+    pattern
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
 mod add_package;
 pub mod blacklist;
 mod delete;
 pub(crate) mod file;
 mod migrate;
 mod pool;
+pub mod sandbox_overrides;
+pub mod toolchains;
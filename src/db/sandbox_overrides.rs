@@ -0,0 +1,165 @@
+//! Per-crate overrides of the sandbox limits, used to build crates that need more resources than
+//! the default limits allow.
+
+use chrono::{DateTime, Utc};
+use failure::{Error, Fail};
+use postgres::Client;
+use serde::Serialize;
+use std::time::Duration;
+
+#[derive(Debug, Fail)]
+enum SandboxOverrideError {
+    #[fail(display = "crate {} doesn't have any sandbox override", _0)]
+    NoOverride(String),
+}
+
+/// The limits overridden for a crate. The limits set to `None` use the default value.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct SandboxOverride {
+    pub memory: Option<u64>,
+    pub timeout: Option<Duration>,
+    pub targets: Option<u32>,
+    pub networking: Option<bool>,
+    pub max_log_size: Option<u64>,
+    /// Number of CPUs available to the build.
+    pub cpu_limit: Option<u32>,
+    /// Why the limits were overridden.
+    pub reason: Option<String>,
+    /// When the override was last changed, unknown for the overrides older than this field.
+    /// It's ignored when setting the override.
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+/// Returns the sandbox override of a crate, if any.
+pub fn get_override(conn: &mut Client, name: &str) -> Result<Option<SandboxOverride>, Error> {
+    let rows = conn.query(
+        "SELECT max_memory_bytes, timeout_seconds, max_targets, networking, max_log_size_bytes,
+                cpu_limit, reason, updated_at
+         FROM sandbox_overrides
+         WHERE crate_name = $1;",
+        &[&name],
+    )?;
+
+    Ok(rows.get(0).map(|row| SandboxOverride {
+        memory: row
+            .get::<_, Option<i64>>("max_memory_bytes")
+            .map(|memory| memory as u64),
+        timeout: row
+            .get::<_, Option<i32>>("timeout_seconds")
+            .map(|timeout| Duration::from_secs(timeout as u64)),
+        targets: row
+            .get::<_, Option<i32>>("max_targets")
+            .map(|targets| targets as u32),
+        networking: row.get("networking"),
+        max_log_size: row
+            .get::<_, Option<i64>>("max_log_size_bytes")
+            .map(|size| size as u64),
+        cpu_limit: row
+            .get::<_, Option<i32>>("cpu_limit")
+            .map(|cpus| cpus as u32),
+        reason: row.get("reason"),
+        updated_at: row.get("updated_at"),
+    }))
+}
+
+/// Overrides the sandbox limits of a crate. The limits set to `None` keep their current value,
+/// while the reason is always replaced.
+pub fn set_override(conn: &mut Client, name: &str, limits: &SandboxOverride) -> Result<(), Error> {
+    conn.execute(
+        "INSERT INTO sandbox_overrides
+            (crate_name, max_memory_bytes, timeout_seconds, max_targets, networking,
+             max_log_size_bytes, cpu_limit, reason, updated_at)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, NOW())
+         ON CONFLICT (crate_name) DO UPDATE SET
+            max_memory_bytes = COALESCE($2, sandbox_overrides.max_memory_bytes),
+            timeout_seconds = COALESCE($3, sandbox_overrides.timeout_seconds),
+            max_targets = COALESCE($4, sandbox_overrides.max_targets),
+            networking = COALESCE($5, sandbox_overrides.networking),
+            max_log_size_bytes = COALESCE($6, sandbox_overrides.max_log_size_bytes),
+            cpu_limit = COALESCE($7, sandbox_overrides.cpu_limit),
+            reason = $8,
+            updated_at = NOW();",
+        &[
+            &name,
+            &limits.memory.map(|memory| memory as i64),
+            &limits.timeout.map(|timeout| timeout.as_secs() as i32),
+            &limits.targets.map(|targets| targets as i32),
+            &limits.networking,
+            &limits.max_log_size.map(|size| size as i64),
+            &limits.cpu_limit.map(|cpus| cpus as i32),
+            &limits.reason,
+        ],
+    )?;
+
+    Ok(())
+}
+
+/// Removes the sandbox override of a crate, restoring the default limits.
+pub fn remove_override(conn: &mut Client, name: &str) -> Result<(), Error> {
+    let removed = conn.execute(
+        "DELETE FROM sandbox_overrides WHERE crate_name = $1;",
+        &[&name],
+    )?;
+    if removed == 0 {
+        return Err(SandboxOverrideError::NoOverride(name.into()).into());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_and_remove_override() {
+        crate::test::wrapper(|env| {
+            let mut conn = env.db().conn();
+            assert_eq!(get_override(&mut conn, "foo")?, None);
+            assert!(remove_override(&mut conn, "foo").is_err());
+
+            set_override(
+                &mut conn,
+                "foo",
+                &SandboxOverride {
+                    memory: Some(8 * 1024 * 1024 * 1024),
+                    reason: Some("needs more memory".into()),
+                    ..SandboxOverride::default()
+                },
+            )?;
+            // Updating an override keeps the limits that aren't set.
+            set_override(
+                &mut conn,
+                "foo",
+                &SandboxOverride {
+                    timeout: Some(Duration::from_secs(30 * 60)),
+                    networking: Some(true),
+                    cpu_limit: Some(4),
+                    reason: Some("needs more time".into()),
+                    ..SandboxOverride::default()
+                },
+            )?;
+
+            let limits = get_override(&mut conn, "foo")?.unwrap();
+            assert!(limits.updated_at.is_some());
+            assert_eq!(
+                limits,
+                SandboxOverride {
+                    memory: Some(8 * 1024 * 1024 * 1024),
+                    timeout: Some(Duration::from_secs(30 * 60)),
+                    targets: None,
+                    networking: Some(true),
+                    max_log_size: None,
+                    cpu_limit: Some(4),
+                    reason: Some("needs more time".into()),
+                    updated_at: limits.updated_at,
+                }
+            );
+
+            remove_override(&mut conn, "foo")?;
+            assert_eq!(get_override(&mut conn, "foo")?, None);
+
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,315 @@
+//! The toolchains used to build the documentation: the current one, the history of the ones
+//! installed in the past, and the crates pinned to a specific toolchain.
+
+use chrono::{DateTime, Utc};
+use failure::{Error, Fail};
+use postgres::Client;
+use serde_json::Value;
+
+#[derive(Debug, Fail)]
+enum ToolchainError {
+    #[fail(display = "there is no previous toolchain to roll back to")]
+    NoPreviousToolchain,
+
+    #[fail(
+        display = "can't roll back to {}, which is updated in place: use `build set-toolchain` \
+                   with a dated toolchain instead",
+        _0
+    )]
+    UndatedToolchain(String),
+
+    #[fail(display = "crate {} is not pinned to a toolchain", _0)]
+    CrateNotPinned(String),
+}
+
+/// A toolchain installed in the past, whose essential files are in the storage.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstalledToolchain {
+    /// Name of the toolchain, for example `nightly-2020-11-21`.
+    pub name: String,
+    /// Output of `rustc --version`, which the resource suffix of the essential files comes from.
+    pub rustc_version: String,
+    pub installed_at: DateTime<Utc>,
+}
+
+/// Returns the toolchain chosen with [`set_current_toolchain`], if any. Otherwise the configured
+/// toolchain is used.
+pub fn current_toolchain(conn: &mut Client) -> Result<Option<String>, Error> {
+    let rows = conn.query("SELECT value FROM config WHERE name = 'toolchain';", &[])?;
+    Ok(match rows.get(0).map(|row| row.get(0)) {
+        Some(Value::String(toolchain)) => Some(toolchain),
+        _ => None,
+    })
+}
+
+/// Use `toolchain` for all the following builds. The builders install it before their next build.
+pub fn set_current_toolchain(conn: &mut Client, toolchain: &str) -> Result<(), Error> {
+    conn.execute(
+        "INSERT INTO config (name, value) VALUES ('toolchain', $1)
+         ON CONFLICT (name) DO UPDATE SET value = $1;",
+        &[&Value::String(toolchain.into())],
+    )?;
+    Ok(())
+}
+
+/// Record that the essential files of a toolchain were added to the storage, unless it's already
+/// the last recorded toolchain.
+pub(crate) fn record_toolchain(
+    conn: &mut Client,
+    name: &str,
+    rustc_version: &str,
+) -> Result<(), Error> {
+    conn.execute(
+        "INSERT INTO toolchains (name, rustc_version)
+         SELECT $1, $2
+         WHERE NOT EXISTS (
+             SELECT 1 FROM (
+                 SELECT name, rustc_version
+                 FROM toolchains
+                 WHERE rolled_back_at IS NULL
+                 ORDER BY id DESC
+                 LIMIT 1
+             ) AS latest
+             WHERE latest.name = $1 AND latest.rustc_version = $2
+         );",
+        &[&name, &rustc_version],
+    )?;
+    Ok(())
+}
+
+/// Returns the installed toolchains that were not rolled back, the most recent first.
+pub fn toolchain_history(conn: &mut Client) -> Result<Vec<InstalledToolchain>, Error> {
+    Ok(conn
+        .query(
+            "SELECT name, rustc_version, installed_at
+             FROM toolchains
+             WHERE rolled_back_at IS NULL
+             ORDER BY id DESC;",
+            &[],
+        )?
+        .into_iter()
+        .map(|row| InstalledToolchain {
+            name: row.get("name"),
+            rustc_version: row.get("rustc_version"),
+            installed_at: row.get("installed_at"),
+        })
+        .collect())
+}
+
+/// Go back to the toolchain installed before the current one, which is marked as rolled back.
+/// The essential files of the previous toolchain are already in the storage, so they are used
+/// again right away.
+///
+/// Channels like `nightly` can't be rolled back to, as installing them again would install their
+/// latest version instead of the previous one.
+pub fn rollback_toolchain(conn: &mut Client) -> Result<InstalledToolchain, Error> {
+    let history = toolchain_history(conn)?;
+    let (current, previous) = match history.as_slice() {
+        [current, previous, ..] => (current, previous.clone()),
+        _ => return Err(ToolchainError::NoPreviousToolchain.into()),
+    };
+    if !is_fixed_toolchain(&previous.name) {
+        return Err(ToolchainError::UndatedToolchain(previous.name).into());
+    }
+
+    let mut transaction = conn.transaction()?;
+    transaction.execute(
+        "UPDATE toolchains
+         SET rolled_back_at = NOW()
+         WHERE id = (
+             SELECT id FROM toolchains
+             WHERE rolled_back_at IS NULL AND name = $1 AND rustc_version = $2
+             ORDER BY id DESC
+             LIMIT 1
+         );",
+        &[&current.name, &current.rustc_version],
+    )?;
+    transaction.execute(
+        "INSERT INTO config (name, value) VALUES ('toolchain', $1)
+         ON CONFLICT (name) DO UPDATE SET value = $1;",
+        &[&Value::String(previous.name.clone())],
+    )?;
+    transaction.execute(
+        "INSERT INTO config (name, value) VALUES ('rustc_version', $1)
+         ON CONFLICT (name) DO UPDATE SET value = $1;",
+        &[&Value::String(previous.rustc_version.clone())],
+    )?;
+    transaction.commit()?;
+
+    Ok(previous)
+}
+
+/// Whether installing `name` always installs the same toolchain, like `nightly-2020-11-21` or
+/// `1.48.0`, unlike the `stable`, `beta` and `nightly` channels.
+fn is_fixed_toolchain(name: &str) -> bool {
+    let channel = name.split('-').next().unwrap_or(name);
+    match channel {
+        "stable" | "beta" | "nightly" => name[channel.len()..]
+            .trim_start_matches('-')
+            .starts_with(|c: char| c.is_ascii_digit()),
+        _ => true,
+    }
+}
+
+/// Returns the toolchain a crate is pinned to, if any.
+pub fn crate_toolchain(conn: &mut Client, name: &str) -> Result<Option<String>, Error> {
+    let rows = conn.query(
+        "SELECT toolchain FROM crate_toolchains WHERE crate_name = $1;",
+        &[&name],
+    )?;
+    Ok(rows.get(0).map(|row| row.get(0)))
+}
+
+/// Build a crate with `toolchain` instead of the current toolchain.
+pub fn pin_crate_toolchain(conn: &mut Client, name: &str, toolchain: &str) -> Result<(), Error> {
+    conn.execute(
+        "INSERT INTO crate_toolchains (crate_name, toolchain) VALUES ($1, $2)
+         ON CONFLICT (crate_name) DO UPDATE SET toolchain = $2;",
+        &[&name, &toolchain],
+    )?;
+    Ok(())
+}
+
+/// Build a crate with the current toolchain again.
+pub fn unpin_crate_toolchain(conn: &mut Client, name: &str) -> Result<(), Error> {
+    let removed = conn.execute(
+        "DELETE FROM crate_toolchains WHERE crate_name = $1;",
+        &[&name],
+    )?;
+    if removed == 0 {
+        return Err(ToolchainError::CrateNotPinned(name.into()).into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::wrapper;
+
+    #[test]
+    fn test_current_toolchain() {
+        wrapper(|env| {
+            let mut conn = env.db().conn();
+            assert_eq!(current_toolchain(&mut conn)?, None);
+
+            set_current_toolchain(&mut conn, "nightly-2020-11-20")?;
+            set_current_toolchain(&mut conn, "nightly-2020-11-21")?;
+            assert_eq!(
+                current_toolchain(&mut conn)?.as_deref(),
+                Some("nightly-2020-11-21")
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_rollback_toolchain() {
+        wrapper(|env| {
+            let mut conn = env.db().conn();
+            let names = |conn: &mut Client| -> Result<Vec<String>, Error> {
+                Ok(toolchain_history(conn)?
+                    .into_iter()
+                    .map(|toolchain| toolchain.name)
+                    .collect())
+            };
+
+            record_toolchain(
+                &mut conn,
+                "nightly-2020-11-20",
+                "rustc 1.50.0 (aaa 2020-11-19)",
+            )?;
+            assert!(rollback_toolchain(&mut conn).is_err());
+
+            record_toolchain(
+                &mut conn,
+                "nightly-2020-11-21",
+                "rustc 1.50.0 (bbb 2020-11-20)",
+            )?;
+            // Recording the current toolchain again doesn't add it to the history.
+            record_toolchain(
+                &mut conn,
+                "nightly-2020-11-21",
+                "rustc 1.50.0 (bbb 2020-11-20)",
+            )?;
+            record_toolchain(
+                &mut conn,
+                "nightly-2020-11-22",
+                "rustc 1.50.0 (ccc 2020-11-21)",
+            )?;
+            assert_eq!(
+                names(&mut conn)?,
+                vec![
+                    "nightly-2020-11-22",
+                    "nightly-2020-11-21",
+                    "nightly-2020-11-20"
+                ]
+            );
+
+            let previous = rollback_toolchain(&mut conn)?;
+            assert_eq!(previous.name, "nightly-2020-11-21");
+            assert_eq!(
+                current_toolchain(&mut conn)?.as_deref(),
+                Some("nightly-2020-11-21")
+            );
+            let rows = conn.query(
+                "SELECT value FROM config WHERE name = 'rustc_version';",
+                &[],
+            )?;
+            assert_eq!(
+                rows[0].get::<_, Value>(0),
+                Value::String("rustc 1.50.0 (bbb 2020-11-20)".into())
+            );
+
+            assert_eq!(rollback_toolchain(&mut conn)?.name, "nightly-2020-11-20");
+            assert!(rollback_toolchain(&mut conn).is_err());
+            assert_eq!(names(&mut conn)?, vec!["nightly-2020-11-20"]);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_rollback_undated_toolchain() {
+        wrapper(|env| {
+            let mut conn = env.db().conn();
+            record_toolchain(&mut conn, "nightly", "rustc 1.50.0 (aaa 2020-11-19)")?;
+            record_toolchain(&mut conn, "nightly", "rustc 1.50.0 (bbb 2020-11-20)")?;
+
+            // Installing `nightly` again would undo the rollback.
+            assert!(rollback_toolchain(&mut conn).is_err());
+            assert_eq!(toolchain_history(&mut conn)?.len(), 2);
+            assert_eq!(current_toolchain(&mut conn)?, None);
+
+            assert!(is_fixed_toolchain("nightly-2020-11-21"));
+            assert!(is_fixed_toolchain("1.48.0"));
+            assert!(!is_fixed_toolchain("stable"));
+            assert!(!is_fixed_toolchain("nightly-x86_64-unknown-linux-gnu"));
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_pin_crate_toolchain() {
+        wrapper(|env| {
+            let mut conn = env.db().conn();
+            assert_eq!(crate_toolchain(&mut conn, "foo")?, None);
+            assert!(unpin_crate_toolchain(&mut conn, "foo").is_err());
+
+            pin_crate_toolchain(&mut conn, "foo", "nightly-2020-11-20")?;
+            pin_crate_toolchain(&mut conn, "foo", "nightly-2020-11-21")?;
+            assert_eq!(
+                crate_toolchain(&mut conn, "foo")?.as_deref(),
+                Some("nightly-2020-11-21")
+            );
+            assert_eq!(crate_toolchain(&mut conn, "bar")?, None);
+
+            unpin_crate_toolchain(&mut conn, "foo")?;
+            assert_eq!(crate_toolchain(&mut conn, "foo")?, None);
+
+            Ok(())
+        });
+    }
+}
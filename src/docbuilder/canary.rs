@@ -0,0 +1,169 @@
+//! Canary runs of a new toolchain, which build a sample of crates with it before it's used for
+//! all the builds, to catch rustdoc regressions early.
+
+use crate::error::Result;
+use postgres::Client;
+use std::fmt;
+
+/// Prefix of the storage where the documentation built by the canary runs is stored.
+pub(super) const CANARY_PREFIX: &str = "canary/";
+
+/// Pick `count` random releases whose last build was successful. Only libraries are picked, as
+/// binaries don't have any documentation to compare.
+pub(super) fn sample_releases(conn: &mut Client, count: usize) -> Result<Vec<(String, String)>> {
+    Ok(conn
+        .query(
+            "SELECT crates.name, releases.version
+             FROM releases
+             INNER JOIN crates ON crates.id = releases.crate_id
+             WHERE releases.build_status = TRUE
+               AND releases.is_library = TRUE
+               AND NOT releases.yanked
+             ORDER BY RANDOM()
+             LIMIT $1;",
+            &[&(count as i64)],
+        )?
+        .into_iter()
+        .map(|row| (row.get(0), row.get(1)))
+        .collect())
+}
+
+/// Check that a canary run can tell whether the candidate toolchain regressed: the maximum
+/// regression rate must be a share of the sample, and the sample can't be empty, as the candidate
+/// would then be promoted without building a single crate.
+pub(super) fn check_canary_run(
+    sample: &[(String, String)],
+    max_regression_rate: f64,
+) -> Result<()> {
+    if !(0.0..=1.0).contains(&max_regression_rate) {
+        failure::bail!(
+            "the maximum regression rate must be between 0% and 100%, not {:.1}%",
+            max_regression_rate * 100.0
+        );
+    }
+    if sample.is_empty() {
+        failure::bail!("no successfully built releases to test the new toolchain with");
+    }
+    Ok(())
+}
+
+/// The outcome of a canary run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CanaryReport {
+    pub toolchain: String,
+    pub rustc_version: String,
+    /// Number of crates built with the new toolchain.
+    pub built: usize,
+    /// The releases that built successfully with the previous toolchain, but not with the new one.
+    pub newly_failing: Vec<(String, String)>,
+    pub max_regression_rate: f64,
+    /// Whether the new toolchain is now used for all the builds.
+    pub promoted: bool,
+}
+
+impl CanaryReport {
+    /// The share of the built crates that started failing with the new toolchain.
+    pub fn regression_rate(&self) -> f64 {
+        if self.built == 0 {
+            0.0
+        } else {
+            self.newly_failing.len() as f64 / self.built as f64
+        }
+    }
+}
+
+impl fmt::Display for CanaryReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Built {} crates with {}", self.built, self.rustc_version)?;
+        writeln!(
+            f,
+            "{} crates started failing ({:.1}%, the maximum is {:.1}%)",
+            self.newly_failing.len(),
+            self.regression_rate() * 100.0,
+            self.max_regression_rate * 100.0,
+        )?;
+        for (name, version) in &self.newly_failing {
+            writeln!(f, "  {} {}", name, version)?;
+        }
+        if self.promoted {
+            write!(
+                f,
+                "Promoted {}, which is used instead of the configured toolchain until \
+                 `build set-toolchain` is run",
+                self.toolchain
+            )
+        } else {
+            write!(
+                f,
+                "Rejected {}, the toolchain was not changed",
+                self.toolchain
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::wrapper;
+
+    #[test]
+    fn test_sample_releases() {
+        wrapper(|env| {
+            env.fake_release().name("foo").version("1.0.0").create()?;
+            env.fake_release()
+                .name("failed")
+                .version("1.0.0")
+                .build_result_successful(false)
+                .create()?;
+            env.fake_release()
+                .name("yanked")
+                .version("1.0.0")
+                .yanked(true)
+                .create()?;
+            env.fake_release()
+                .name("binary")
+                .version("1.0.0")
+                .binary(true)
+                .create()?;
+
+            let mut conn = env.db().conn();
+            assert_eq!(
+                sample_releases(&mut conn, 10)?,
+                vec![("foo".to_string(), "1.0.0".to_string())]
+            );
+            assert!(sample_releases(&mut conn, 0)?.is_empty());
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_check_canary_run() {
+        let sample = vec![("foo".to_string(), "1.0.0".to_string())];
+        assert!(check_canary_run(&sample, 0.05).is_ok());
+        assert!(check_canary_run(&sample, 1.0).is_ok());
+        assert!(check_canary_run(&sample, 2.55).is_err());
+        assert!(check_canary_run(&sample, -0.01).is_err());
+        // Nothing would be built, so the candidate would always be promoted.
+        assert!(check_canary_run(&[], 0.05).is_err());
+    }
+
+    #[test]
+    fn test_regression_rate() {
+        let mut report = CanaryReport {
+            toolchain: "nightly-2020-11-21".into(),
+            rustc_version: "rustc 1.50.0-nightly (1c389ffef 2020-11-20)".into(),
+            built: 0,
+            newly_failing: Vec::new(),
+            max_regression_rate: 0.05,
+            promoted: false,
+        };
+        assert_eq!(report.regression_rate(), 0.0);
+
+        report.built = 20;
+        report.newly_failing = vec![("foo".into(), "1.0.0".into())];
+        assert!((report.regression_rate() - 0.05).abs() < f64::EPSILON);
+        assert!(report.to_string().contains("  foo 1.0.0\n"));
+    }
+}
@@ -0,0 +1,181 @@
+//! Classification of build failures, based on the error returned by rustwide and on the output
+//! of the build.
+
+use rustwide::cmd::CommandError;
+use serde::Serialize;
+
+/// Messages printed when a build script or a `-sys` crate can't find a native library.
+const MISSING_LIBRARY_MESSAGES: &[&str] = &[
+    "was not found in the pkg-config search path",
+    "Could not run `\"pkg-config\"",
+    "unable to find library -l",
+    "cannot find -l",
+    ".h: No such file or directory",
+    "Unable to find libclang",
+];
+
+/// Messages printed when something tries to access the network, which is disabled in the sandbox.
+const NETWORK_MESSAGES: &[&str] = &[
+    "Could not resolve host",
+    "failed to lookup address information",
+    "Temporary failure in name resolution",
+    "Network is unreachable",
+    "error sending request for url",
+];
+
+/// Messages printed when a process of the build is killed because it used too much memory. The
+/// sandbox running out of memory as a whole is reported by rustwide instead.
+const OUT_OF_MEMORY_MESSAGES: &[&str] = &["memory allocation of", "(signal: 9, SIGKILL: kill)"];
+
+/// Messages printed by cargo when rustc or rustdoc fail.
+const COMPILE_ERROR_MESSAGES: &[&str] = &[
+    "error[E",
+    "error: could not compile",
+    "error: could not document",
+    "error: aborting due to",
+];
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Serialize,
+    strum::EnumString,
+    strum::IntoStaticStr,
+    strum::EnumIter,
+)]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case")]
+pub(crate) enum BuildFailureReason {
+    CompileError,
+    Timeout,
+    OutOfMemory,
+    MissingSystemLibrary,
+    NetworkAccess,
+    /// The build failed, but the reason couldn't be determined.
+    Other,
+}
+
+impl BuildFailureReason {
+    /// Classify a failed build from the error it returned and its log.
+    pub(crate) fn classify(error: &failure::Error, build_log: &str) -> Self {
+        match error.downcast_ref::<CommandError>() {
+            Some(CommandError::Timeout(_)) | Some(CommandError::NoOutputFor(_)) => {
+                return BuildFailureReason::Timeout
+            }
+            Some(CommandError::SandboxOOM) => return BuildFailureReason::OutOfMemory,
+            _ => {}
+        }
+
+        let contains_any = |messages: &[&str]| messages.iter().any(|msg| build_log.contains(msg));
+        // The more specific reasons are checked first, as they're usually followed by a generic
+        // compile error when a build script fails.
+        if contains_any(OUT_OF_MEMORY_MESSAGES) {
+            BuildFailureReason::OutOfMemory
+        } else if contains_any(MISSING_LIBRARY_MESSAGES) {
+            BuildFailureReason::MissingSystemLibrary
+        } else if contains_any(NETWORK_MESSAGES) {
+            BuildFailureReason::NetworkAccess
+        } else if contains_any(COMPILE_ERROR_MESSAGES) {
+            BuildFailureReason::CompileError
+        } else {
+            BuildFailureReason::Other
+        }
+    }
+
+    /// The name stored in the database and used in URLs, for example `out-of-memory`.
+    pub(crate) fn as_str(self) -> &'static str {
+        self.into()
+    }
+
+    /// Human readable description of the reason.
+    pub(crate) fn description(self) -> &'static str {
+        match self {
+            BuildFailureReason::CompileError => "Compilation error",
+            BuildFailureReason::Timeout => "Timed out",
+            BuildFailureReason::OutOfMemory => "Out of memory",
+            BuildFailureReason::MissingSystemLibrary => "Missing system library",
+            BuildFailureReason::NetworkAccess => "Network access",
+            BuildFailureReason::Other => "Unknown reason",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use failure::err_msg;
+
+    #[test]
+    fn test_classify_command_errors() {
+        let classify = |err: CommandError| BuildFailureReason::classify(&err.into(), "");
+        assert_eq!(
+            classify(CommandError::Timeout(900)),
+            BuildFailureReason::Timeout
+        );
+        assert_eq!(
+            classify(CommandError::NoOutputFor(900)),
+            BuildFailureReason::Timeout
+        );
+        assert_eq!(
+            classify(CommandError::SandboxOOM),
+            BuildFailureReason::OutOfMemory
+        );
+    }
+
+    #[test]
+    fn test_classify_build_logs() {
+        let classify = |log: &str| BuildFailureReason::classify(&err_msg("failed"), log);
+        assert_eq!(
+            classify(
+                "[stderr] error[E0425]: cannot find value `x` in this scope\n\
+                 [stderr] error: could not document `foo`"
+            ),
+            BuildFailureReason::CompileError
+        );
+        assert_eq!(
+            classify(
+                "[stderr] error: failed to run custom build command for `openssl-sys v0.9.58`\n\
+                 [stderr] Package openssl was not found in the pkg-config search path.\n\
+                 [stderr] error: could not compile `openssl-sys`"
+            ),
+            BuildFailureReason::MissingSystemLibrary
+        );
+        assert_eq!(
+            classify(
+                "[stderr] error: failed to run custom build command for `foo v1.0.0`\n\
+                 [stderr] curl: (6) Could not resolve host: example.com"
+            ),
+            BuildFailureReason::NetworkAccess
+        );
+        assert_eq!(
+            classify("[stderr] memory allocation of 1073741824 bytes failed"),
+            BuildFailureReason::OutOfMemory
+        );
+        assert_eq!(
+            classify(
+                "[stderr] warning: use `try_reserve` to handle out of memory errors\n\
+                 [stderr] error[E0308]: mismatched types\n\
+                 [stderr] error: could not document `foo`"
+            ),
+            BuildFailureReason::CompileError
+        );
+        assert_eq!(classify(""), BuildFailureReason::Other);
+    }
+
+    #[test]
+    fn test_names() {
+        use strum::IntoEnumIterator;
+
+        for reason in BuildFailureReason::iter() {
+            assert_eq!(reason.as_str().parse(), Ok(reason));
+            assert_eq!(
+                serde_json::to_value(reason).unwrap(),
+                serde_json::Value::String(reason.as_str().into())
+            );
+        }
+        assert_eq!(BuildFailureReason::OutOfMemory.as_str(), "out-of-memory");
+    }
+}
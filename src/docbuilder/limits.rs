@@ -1,75 +1,88 @@
+use crate::db::sandbox_overrides::get_override;
 use crate::error::Result;
+use crate::Config;
 use postgres::Client;
 use serde::Serialize;
 use std::time::Duration;
 
+/// The resources available to the builds of a crate.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize)]
-pub(crate) struct Limits {
+pub struct Limits {
     memory: usize,
     targets: usize,
     timeout: Duration,
     networking: bool,
     max_log_size: usize,
+    cpu_limit: Option<u32>,
 }
 
-impl Default for Limits {
-    fn default() -> Self {
+impl Limits {
+    /// Returns the default limits, used by the crates without sandbox overrides.
+    pub fn new(config: &Config) -> Self {
         Self {
-            memory: 3 * 1024 * 1024 * 1024,        // 3 GB
-            timeout: Duration::from_secs(15 * 60), // 15 minutes
-            targets: 10,
-            networking: false,
-            max_log_size: 100 * 1024, // 100 KB
+            memory: config.build_default_memory_limit,
+            timeout: Duration::from_secs(config.build_default_timeout),
+            targets: config.build_default_max_targets,
+            networking: config.build_default_networking,
+            max_log_size: config.build_default_max_log_size,
+            cpu_limit: config.build_cpu_limit,
         }
     }
-}
 
-impl Limits {
-    pub(crate) fn for_crate(conn: &mut Client, name: &str) -> Result<Self> {
-        let mut limits = Self::default();
-
-        let res = conn.query(
-            "SELECT * FROM sandbox_overrides WHERE crate_name = $1;",
-            &[&name],
-        )?;
-        if !res.is_empty() {
-            let row = &res[0];
-            if let Some(memory) = row.get::<_, Option<i64>>("max_memory_bytes") {
+    /// Returns the limits of a crate, applying its sandbox overrides to the default limits.
+    pub fn for_crate(config: &Config, conn: &mut Client, name: &str) -> Result<Self> {
+        let mut limits = Self::new(config);
+
+        if let Some(overrides) = get_override(conn, name)? {
+            if let Some(memory) = overrides.memory {
                 limits.memory = memory as usize;
             }
-            let timeout = row.get::<_, Option<i32>>("timeout_seconds");
-            if let Some(timeout) = timeout {
-                limits.timeout = Duration::from_secs(timeout as u64);
+            if let Some(timeout) = overrides.timeout {
+                limits.timeout = timeout;
             }
-            if let Some(targets) = row.get::<_, Option<i32>>("max_targets") {
+            if let Some(targets) = overrides.targets {
                 limits.targets = targets as usize;
-            } else if timeout.is_some() {
+            } else if overrides.timeout.is_some() {
                 limits.targets = 1;
             }
+            if let Some(networking) = overrides.networking {
+                limits.networking = networking;
+            }
+            if let Some(max_log_size) = overrides.max_log_size {
+                limits.max_log_size = max_log_size as usize;
+            }
+            if let Some(cpu_limit) = overrides.cpu_limit {
+                limits.cpu_limit = Some(cpu_limit);
+            }
         }
 
         Ok(limits)
     }
 
-    pub(crate) fn memory(&self) -> usize {
+    pub fn memory(&self) -> usize {
         self.memory
     }
 
-    pub(crate) fn timeout(&self) -> Duration {
+    pub fn timeout(&self) -> Duration {
         self.timeout
     }
 
-    pub(crate) fn networking(&self) -> bool {
+    pub fn networking(&self) -> bool {
         self.networking
     }
 
-    pub(crate) fn max_log_size(&self) -> usize {
+    pub fn max_log_size(&self) -> usize {
         self.max_log_size
     }
 
-    pub(crate) fn targets(&self) -> usize {
+    pub fn targets(&self) -> usize {
         self.targets
     }
+
+    /// The number of CPUs available to the build, unlimited if `None`.
+    pub fn cpu_limit(&self) -> Option<u32> {
+        self.cpu_limit
+    }
 }
 
 #[cfg(test)]
@@ -84,20 +97,20 @@ mod test {
 
             let krate = "hexponent";
             // limits work if no crate has limits set
-            let hexponent = Limits::for_crate(&mut db.conn(), krate)?;
-            assert_eq!(hexponent, Limits::default());
+            let hexponent = Limits::for_crate(&env.config(), &mut db.conn(), krate)?;
+            assert_eq!(hexponent, Limits::new(&env.config()));
 
             db.conn().query(
                 "INSERT INTO sandbox_overrides (crate_name, max_targets) VALUES ($1, 15)",
                 &[&krate],
             )?;
             // limits work if crate has limits set
-            let hexponent = Limits::for_crate(&mut db.conn(), krate)?;
+            let hexponent = Limits::for_crate(&env.config(), &mut db.conn(), krate)?;
             assert_eq!(
                 hexponent,
                 Limits {
                     targets: 15,
-                    ..Limits::default()
+                    ..Limits::new(&env.config())
                 }
             );
 
@@ -107,14 +120,63 @@ mod test {
                 memory: 100_000,
                 timeout: Duration::from_secs(300),
                 targets: 1,
-                ..Limits::default()
+                ..Limits::new(&env.config())
             };
             db.conn().query(
                 "INSERT INTO sandbox_overrides (crate_name, max_memory_bytes, timeout_seconds, max_targets)
                  VALUES ($1, $2, $3, $4)",
                 &[&krate, &(limits.memory as i64), &(limits.timeout.as_secs() as i32), &(limits.targets as i32)]
             )?;
-            assert_eq!(limits, Limits::for_crate(&mut db.conn(), krate)?);
+            assert_eq!(
+                limits,
+                Limits::for_crate(&env.config(), &mut db.conn(), krate)?
+            );
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn configured_defaults() {
+        wrapper(|env| {
+            env.override_config(|config| {
+                config.build_default_memory_limit = 1024;
+                config.build_default_timeout = 60;
+                config.build_default_networking = true;
+                config.build_cpu_limit = Some(2);
+            });
+            let limits = Limits::new(&env.config());
+            assert_eq!(limits.memory(), 1024);
+            assert_eq!(limits.timeout(), Duration::from_secs(60));
+            assert!(limits.networking());
+            assert_eq!(limits.cpu_limit(), Some(2));
+
+            let db = env.db();
+            db.conn().query(
+                "INSERT INTO sandbox_overrides (crate_name, cpu_limit, max_log_size_bytes)
+                 VALUES ($1, 4, 1048576);",
+                &[&"hexponent"],
+            )?;
+            let limits = Limits::for_crate(&env.config(), &mut db.conn(), "hexponent")?;
+            assert_eq!(limits.cpu_limit(), Some(4));
+            assert_eq!(limits.max_log_size(), 1024 * 1024);
+            assert_eq!(limits.memory(), 1024);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn networking_override() {
+        wrapper(|env| {
+            let db = env.db();
+            db.conn().query(
+                "INSERT INTO sandbox_overrides (crate_name, networking) VALUES ($1, TRUE);",
+                &[&"hexponent"],
+            )?;
+            let limits = Limits::for_crate(&env.config(), &mut db.conn(), "hexponent")?;
+            assert!(limits.networking);
+            assert_eq!(limits.targets, Limits::new(&env.config()).targets);
+
             Ok(())
         });
     }
@@ -128,7 +190,7 @@ mod test {
                 "INSERT INTO sandbox_overrides (crate_name, timeout_seconds) VALUES ($1, 20*60);",
                 &[&krate],
             )?;
-            let limits = Limits::for_crate(&mut db.conn(), krate)?;
+            let limits = Limits::for_crate(&env.config(), &mut db.conn(), krate)?;
             assert_eq!(limits.targets, 1);
 
             Ok(())
@@ -1,11 +1,18 @@
+mod canary;
 mod crates;
+mod failure_reason;
 mod limits;
 mod queue;
 mod rustwide_builder;
+mod search_index;
 
-pub(crate) use self::limits::Limits;
+pub(crate) use self::failure_reason::BuildFailureReason;
+pub use self::limits::Limits;
 pub use self::rustwide_builder::RustwideBuilder;
-pub(crate) use self::rustwide_builder::{BuildResult, DocCoverage};
+pub(crate) use self::rustwide_builder::{
+    BuildResult, BuildStats, DocCoverage, ESSENTIAL_FILES_VERSIONED,
+};
+pub(crate) use self::search_index::SearchItem;
 
 use crate::db::Pool;
 use crate::error::Result;
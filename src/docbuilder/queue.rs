@@ -1,6 +1,7 @@
 //! Updates registry index and builds new packages
 
 use super::{DocBuilder, RustwideBuilder};
+use crate::db::set_yanked;
 use crate::error::Result;
 use crate::utils::get_crate_priority;
 use crate::Index;
@@ -10,9 +11,12 @@ use log::{debug, error};
 impl DocBuilder {
     /// Updates registry index repository and adds new crates into build queue.
     /// Returns the number of crates added
+    ///
+    /// The index is locked while updating it, so concurrent calls are serialized.
     pub fn get_new_crates(&mut self, index: &Index) -> Result<usize> {
+        let _lock = index.lock()?;
         let mut conn = self.db.get()?;
-        let diff = index.diff()?;
+        let diff = index.diff(&mut conn)?;
         let (mut changes, oid) = diff.peek_changes()?;
         let mut crates_added = 0;
 
@@ -22,18 +26,7 @@ impl DocBuilder {
         for krate in &changes {
             match krate.kind {
                 ChangeKind::Yanked => {
-                    let res = conn.execute(
-                        "
-                        UPDATE releases
-                            SET yanked = TRUE
-                        FROM crates
-                        WHERE crates.id = releases.crate_id
-                            AND name = $1
-                            AND version = $2
-                        ",
-                        &[&krate.name, &krate.version],
-                    );
-                    match res {
+                    match set_yanked(&mut conn, &krate.name, &krate.version, true) {
                         Ok(_) => debug!("{}-{} yanked", krate.name, krate.version),
                         Err(err) => error!(
                             "error while setting {}-{} to yanked: {}",
@@ -43,6 +36,23 @@ impl DocBuilder {
                 }
 
                 ChangeKind::Added => {
+                    // An unyanked release is reported the same way as a new release. Releases
+                    // already in the database only need their yanked status to be reset.
+                    match set_yanked(&mut conn, &krate.name, &krate.version, false) {
+                        Ok(true) => {
+                            debug!("{}-{} unyanked", krate.name, krate.version);
+                            continue;
+                        }
+                        Ok(false) => {}
+                        Err(err) => {
+                            error!(
+                                "error while setting {}-{} to unyanked: {}",
+                                krate.name, krate.version, err
+                            );
+                            continue;
+                        }
+                    }
+
                     let priority = get_crate_priority(&mut conn, &krate.name)?;
 
                     match self
@@ -62,7 +72,7 @@ impl DocBuilder {
             }
         }
 
-        diff.set_last_seen_reference(oid)?;
+        index.set_last_seen_commit(&mut conn, oid)?;
 
         Ok(crates_added)
     }
@@ -76,7 +86,8 @@ impl DocBuilder {
     ) -> Result<bool> {
         let mut processed = false;
         let queue = self.build_queue.clone();
-        queue.process_next_crate(|krate| {
+        let worker = format!("{}:{}", std::process::id(), builder.worker_name());
+        queue.process_next_crate(&worker, |krate| {
             processed = true;
 
             builder.build_package(&krate.name, &krate.version, None)?;
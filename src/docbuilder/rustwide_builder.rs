@@ -1,10 +1,18 @@
 use crate::db::blacklist::is_blacklisted;
 use crate::db::file::add_path_into_database;
+use crate::db::toolchains::{
+    crate_toolchain, current_toolchain, record_toolchain, set_current_toolchain,
+};
 use crate::db::{
-    add_build_into_database, add_doc_coverage, add_package_into_database,
-    update_crate_data_in_database, Pool,
+    add_build_into_database, add_build_target_into_database, add_doc_coverage,
+    add_package_into_database, add_search_items, update_crate_data_in_database, Pool,
+};
+use crate::docbuilder::{
+    canary::{check_canary_run, sample_releases, CanaryReport, CANARY_PREFIX},
+    crates::crates_from_path,
+    search_index::extract_search_items,
+    BuildFailureReason, Limits,
 };
-use crate::docbuilder::{crates::crates_from_path, Limits};
 use crate::error::Result;
 use crate::index::api::ReleaseData;
 use crate::storage::CompressionAlgorithms;
@@ -14,17 +22,19 @@ use docsrs_metadata::{Metadata, DEFAULT_TARGETS, HOST_TARGET};
 use failure::ResultExt;
 use log::{debug, info, warn, LevelFilter};
 use postgres::Client;
-use rustwide::cmd::{Command, SandboxBuilder, SandboxImage};
+use rustwide::cmd::{Binary, Command, ProcessLinesActions, Runnable, SandboxBuilder, SandboxImage};
 use rustwide::logging::{self, LogStorage};
 use rustwide::toolchain::ToolchainError;
 use rustwide::{Build, Crate, Toolchain, Workspace, WorkspaceBuilder};
 use serde_json::Value;
 use std::collections::{HashMap, HashSet};
 use std::path::Path;
-use std::sync::Arc;
+use std::sync::{Arc, PoisonError, RwLock};
+use std::time::{Duration, Instant};
 
 const USER_AGENT: &str = "docs.rs builder (https://github.com/rust-lang/docs.rs)";
-const ESSENTIAL_FILES_VERSIONED: &[&str] = &[
+/// Files generated by rustdoc for every crate, whose names include the `--resource-suffix`.
+pub(crate) const ESSENTIAL_FILES_VERSIONED: &[&str] = &[
     "brush.svg",
     "wheel.svg",
     "down-arrow.svg",
@@ -55,6 +65,40 @@ const ESSENTIAL_FILES_UNVERSIONED: &[&str] = &[
 const DUMMY_CRATE_NAME: &str = "empty-library";
 const DUMMY_CRATE_VERSION: &str = "1.0.0";
 
+/// Prefix of the line printed by `PEAK_MEMORY_SCRIPT` with the peak memory usage of the sandbox.
+const PEAK_MEMORY_MARKER: &str = "docsrs-peak-memory: ";
+/// Runs cargo with the arguments of the script, and then prints the peak memory usage recorded by
+/// the memory cgroup of the sandbox, either cgroup v1 or v2, before exiting with the status of
+/// cargo.
+const PEAK_MEMORY_SCRIPT: &str = "\"$CARGO_HOME/bin/cargo\" \"$@\"; status=$?; \
+    for file in /sys/fs/cgroup/memory/memory.max_usage_in_bytes /sys/fs/cgroup/memory.peak; do \
+        if [ -r \"$file\" ]; then echo \"docsrs-peak-memory: $(cat \"$file\")\"; break; fi; \
+    done; \
+    exit $status";
+
+/// Runs cargo in the sandbox through `PEAK_MEMORY_SCRIPT`, as the sandbox is deleted as soon as
+/// the command exits and rustwide doesn't report its resource usage.
+struct CargoWithPeakMemory<R>(R);
+
+impl<R: Runnable> Runnable for CargoWithPeakMemory<R> {
+    fn name(&self) -> Binary {
+        Binary::Global("sh".into())
+    }
+
+    fn prepare_command<'w, 'pl>(&self, cmd: Command<'w, 'pl>) -> Command<'w, 'pl> {
+        // `$0` is set to `cargo`, and the arguments added by the toolchain follow.
+        self.0
+            .prepare_command(cmd.args(&["-c", PEAK_MEMORY_SCRIPT, "cargo"]))
+    }
+}
+
+/// The toolchain used by all the workers, installed by `RustwideBuilder::update_toolchain`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct CurrentToolchain {
+    name: String,
+    rustc_version: String,
+}
+
 pub struct RustwideBuilder {
     workspace: Workspace,
     toolchain: Toolchain,
@@ -65,6 +109,12 @@ pub struct RustwideBuilder {
     index: Arc<Index>,
     rustc_version: String,
     skip_build_if_exists: bool,
+    /// The toolchain shared by the workers, `None` until it's installed for the first time. It's
+    /// held for reading while building a crate, and for writing only while the toolchain in use
+    /// is replaced, so that it doesn't change in the middle of a build.
+    toolchain_lock: Arc<RwLock<Option<CurrentToolchain>>>,
+    /// Name of the build directory of this worker, if it's one of multiple concurrent workers.
+    worker_name: Option<String>,
 }
 
 impl RustwideBuilder {
@@ -80,36 +130,265 @@ impl RustwideBuilder {
         let workspace = builder.init()?;
         workspace.purge_all_build_dirs()?;
 
-        let toolchain = Toolchain::dist(&config.toolchain);
+        let pool = context.pool()?;
+        let toolchain = Toolchain::dist(&toolchain_name(&mut *pool.get()?, &config)?);
 
         Ok(RustwideBuilder {
             workspace,
             toolchain,
             config,
-            db: context.pool()?,
+            db: pool,
             storage: context.storage()?,
             metrics: context.metrics()?,
             index: context.index()?,
             rustc_version: String::new(),
             skip_build_if_exists: false,
+            toolchain_lock: Arc::new(RwLock::new(None)),
+            worker_name: None,
         })
     }
 
+    /// Create another builder sharing the workspace and the toolchain of this one, but using its
+    /// own build directory, so that multiple crates can be built at the same time.
+    pub fn worker(&self, id: usize) -> Self {
+        RustwideBuilder {
+            workspace: self.workspace.clone(),
+            toolchain: self.toolchain.clone(),
+            config: self.config.clone(),
+            db: self.db.clone(),
+            storage: self.storage.clone(),
+            metrics: self.metrics.clone(),
+            index: self.index.clone(),
+            rustc_version: self.rustc_version.clone(),
+            skip_build_if_exists: self.skip_build_if_exists,
+            toolchain_lock: self.toolchain_lock.clone(),
+            worker_name: Some(format!("worker-{}", id)),
+        }
+    }
+
+    pub(crate) fn worker_name(&self) -> &str {
+        self.worker_name.as_deref().unwrap_or("builder")
+    }
+
     pub fn set_skip_build_if_exists(&mut self, should: bool) {
         self.skip_build_if_exists = should;
     }
 
     fn prepare_sandbox(&self, limits: &Limits) -> SandboxBuilder {
         SandboxBuilder::new()
-            .cpu_limit(self.config.build_cpu_limit.map(|limit| limit as f32))
+            .cpu_limit(limits.cpu_limit().map(|limit| limit as f32))
             .memory_limit(Some(limits.memory()))
             .enable_networking(limits.networking())
     }
 
+    /// Install the latest version of the current toolchain, and switch all the workers to it.
+    /// The daemon calls this periodically, outside of the builds.
     pub fn update_toolchain(&mut self) -> Result<()> {
-        // Ignore errors if detection fails.
-        let old_version = self.detect_rustc_version().ok();
+        self.update_toolchain_inner(false)
+    }
+
+    /// Install the current toolchain unless another worker already did, when the daemon couldn't
+    /// install it at startup.
+    fn install_first_toolchain(&mut self) -> Result<()> {
+        self.update_toolchain_inner(true)
+    }
 
+    fn update_toolchain_inner(&mut self, only_first_time: bool) -> Result<()> {
+        let mut conn = self.db.get()?;
+        // Another toolchain might have been chosen since the last update.
+        let name = toolchain_name(&mut conn, &self.config)?;
+        self.toolchain = Toolchain::dist(&name);
+
+        let old_version = conn
+            .query(
+                "SELECT value FROM config WHERE name = 'rustc_version';",
+                &[],
+            )?
+            .get(0)
+            .and_then(|row| match row.get(0) {
+                Value::String(version) => Some(version),
+                _ => None,
+            });
+
+        let toolchain_lock = self.toolchain_lock.clone();
+        let in_use = toolchain_lock
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .as_ref()
+            .map(|current| current.name.clone());
+        // Installing the first toolchain and updating a channel like `nightly` in place can't
+        // happen while the workers install or use the toolchain, so they're blocked until it's
+        // done. Otherwise the workers keep using their toolchain while the new one is installed.
+        let updating = if in_use.is_none() || in_use.as_deref() == Some(name.as_str()) {
+            let updating = toolchain_lock
+                .write()
+                .unwrap_or_else(PoisonError::into_inner);
+            if only_first_time && updating.is_some() {
+                return Ok(());
+            }
+            Some(updating)
+        } else if only_first_time {
+            return Ok(());
+        } else {
+            None
+        };
+
+        self.install_toolchain()?;
+        self.rustc_version = self.detect_rustc_version()?;
+        if old_version.as_deref() != Some(&self.rustc_version) {
+            self.add_essential_files()?;
+            record_toolchain(&mut conn, &name, &self.rustc_version)?;
+        }
+
+        let installed = CurrentToolchain {
+            name,
+            rustc_version: self.rustc_version.clone(),
+        };
+        let mut current = match updating {
+            Some(updating) => updating,
+            None => toolchain_lock
+                .write()
+                .unwrap_or_else(PoisonError::into_inner),
+        };
+        if current.as_ref() != Some(&installed) {
+            info!("switching the workers to {}", installed.rustc_version);
+            *current = Some(installed);
+        }
+
+        Ok(())
+    }
+
+    /// Build a sample of `sample_size` crates that built successfully in the past with the
+    /// `candidate` toolchain, and use it for all the following builds only if at most
+    /// `max_regression_rate` of them fail to build with it.
+    ///
+    /// The documentation of the sample is stored under a scratch prefix, which is removed at the
+    /// end of the run, and the database is left untouched.
+    ///
+    /// Promoting the candidate pins it as the current toolchain, so the following updates keep
+    /// installing it until another toolchain is chosen with `set_current_toolchain`.
+    pub fn update_toolchain_canary(
+        &mut self,
+        candidate: &str,
+        sample_size: usize,
+        max_regression_rate: f64,
+    ) -> Result<CanaryReport> {
+        let mut conn = self.db.get()?;
+        let sample = sample_releases(&mut conn, sample_size)?;
+        check_canary_run(&sample, max_regression_rate)?;
+        info!(
+            "building {} crates with the candidate toolchain {}",
+            sample.len(),
+            candidate
+        );
+
+        let previous_toolchain = std::mem::replace(&mut self.toolchain, Toolchain::dist(candidate));
+        let previous_rustc_version = self.rustc_version.clone();
+        let newly_failing = match self.canary_builds(&sample) {
+            Ok(newly_failing) => newly_failing,
+            Err(err) => {
+                self.toolchain = previous_toolchain;
+                self.rustc_version = previous_rustc_version;
+                return Err(err);
+            }
+        };
+
+        let mut report = CanaryReport {
+            toolchain: candidate.into(),
+            rustc_version: self.rustc_version.clone(),
+            built: sample.len(),
+            newly_failing,
+            max_regression_rate,
+            promoted: false,
+        };
+        if report.built > 0 && report.regression_rate() <= max_regression_rate {
+            info!("promoting the toolchain {}", candidate);
+            set_current_toolchain(&mut conn, candidate)?;
+            self.add_essential_files()?;
+            record_toolchain(&mut conn, candidate, &self.rustc_version)?;
+            report.promoted = true;
+        } else {
+            warn!(
+                "rejecting the toolchain {}, {} crates started failing",
+                candidate,
+                report.newly_failing.len()
+            );
+            self.toolchain = previous_toolchain;
+            self.rustc_version = previous_rustc_version;
+        }
+
+        Ok(report)
+    }
+
+    /// Build the crates of a canary run with the current toolchain, returning the ones that failed.
+    fn canary_builds(&mut self, sample: &[(String, String)]) -> Result<Vec<(String, String)>> {
+        let res = self.canary_builds_inner(sample);
+        // Clean up the documentation even if the run failed halfway.
+        self.storage.delete_prefix(CANARY_PREFIX)?;
+        res
+    }
+
+    fn canary_builds_inner(
+        &mut self,
+        sample: &[(String, String)],
+    ) -> Result<Vec<(String, String)>> {
+        self.install_toolchain()?;
+        self.rustc_version = self.detect_rustc_version()?;
+
+        let mut newly_failing = Vec::new();
+        for (name, version) in sample {
+            let successful = self.canary_build(name, version).unwrap_or_else(|err| {
+                warn!(
+                    "failed to run the canary build of {} {}: {}",
+                    name, version, err
+                );
+                false
+            });
+            if !successful {
+                info!("{} {} fails to build with the new toolchain", name, version);
+                newly_failing.push((name.clone(), version.clone()));
+            }
+        }
+
+        Ok(newly_failing)
+    }
+
+    /// Build the default target of a crate, storing its documentation under the canary prefix.
+    fn canary_build(&self, name: &str, version: &str) -> Result<bool> {
+        let limits = Limits::for_crate(&self.config, &mut *self.db.get()?, name)?;
+
+        let mut build_dir = self
+            .workspace
+            .build_dir(&format!("canary-{}-{}", name, version));
+        build_dir.purge()?;
+
+        let krate = Crate::crates_io(name, version);
+        krate.fetch(&self.workspace)?;
+
+        let successful = build_dir
+            .build(&self.toolchain, &krate, self.prepare_sandbox(&limits))
+            .run(|build| {
+                let metadata = Metadata::from_crate_root(&build.host_source_dir())?;
+                let default_target = metadata.targets().default_target;
+
+                let res = self.execute_build(default_target, true, build, &limits, &metadata)?;
+                let doc_dir = build.host_target_dir().join("doc");
+                if res.result.successful && doc_dir.is_dir() {
+                    add_path_into_database(
+                        &self.storage,
+                        &format!("{}{}/{}", CANARY_PREFIX, name, version),
+                        doc_dir,
+                    )?;
+                }
+                Ok(res.result.successful)
+            })?;
+
+        build_dir.purge()?;
+        krate.purge_from_cache(&self.workspace)?;
+        Ok(successful)
+    }
+
+    fn install_toolchain(&self) -> Result<()> {
         let mut targets_to_install = DEFAULT_TARGETS
             .iter()
             .map(|&t| t.to_string()) // &str has a specialized ToString impl, while &&str goes through Display
@@ -158,11 +437,6 @@ impl RustwideBuilder {
             log::info!("continuing anyway, since this must be the first build");
         }
 
-        self.rustc_version = self.detect_rustc_version()?;
-        if old_version.as_deref() != Some(&self.rustc_version) {
-            self.add_essential_files()?;
-        }
-
         Ok(())
     }
 
@@ -183,14 +457,41 @@ impl RustwideBuilder {
         }
     }
 
+    /// Switch to the toolchain a crate is pinned to, adding its essential files if they're
+    /// missing. The current toolchain is restored at the start of the next build.
+    fn use_pinned_toolchain(&mut self, name: &str) -> Result<()> {
+        info!("using the pinned toolchain {}", name);
+        self.toolchain = Toolchain::dist(name);
+        self.install_toolchain()?;
+        self.rustc_version = self.detect_rustc_version()?;
+
+        let stylesheet = format!("rustdoc-{}.css", parse_rustc_version(&self.rustc_version)?);
+        if !self.storage.exists(&stylesheet)? {
+            self.upload_essential_files()?;
+        }
+        Ok(())
+    }
+
     pub fn add_essential_files(&mut self) -> Result<()> {
         self.rustc_version = self.detect_rustc_version()?;
+        self.upload_essential_files()?;
+
+        let mut conn = self.db.get()?;
+        conn.query(
+            "INSERT INTO config (name, value) VALUES ('rustc_version', $1) \
+             ON CONFLICT (name) DO UPDATE SET value = $1;",
+            &[&Value::String(self.rustc_version.clone())],
+        )?;
+        Ok(())
+    }
+
+    /// Upload the essential files of the current toolchain, without starting to use them.
+    fn upload_essential_files(&self) -> Result<()> {
         let rustc_version = parse_rustc_version(&self.rustc_version)?;
 
         info!("building a dummy crate to get essential files");
 
-        let mut conn = self.db.get()?;
-        let limits = Limits::for_crate(&mut conn, DUMMY_CRATE_NAME)?;
+        let limits = Limits::for_crate(&self.config, &mut *self.db.get()?, DUMMY_CRATE_NAME)?;
 
         let mut build_dir = self
             .workspace
@@ -240,11 +541,6 @@ impl RustwideBuilder {
                 }
 
                 add_path_into_database(&self.storage, "", &dest)?;
-                conn.query(
-                    "INSERT INTO config (name, value) VALUES ('rustc_version', $1) \
-                     ON CONFLICT (name) DO UPDATE SET value = $1;",
-                    &[&Value::String(self.rustc_version.clone())],
-                )?;
 
                 Ok(())
             })?;
@@ -287,7 +583,31 @@ impl RustwideBuilder {
             return Ok(false);
         }
 
-        self.update_toolchain()?;
+        let toolchain_lock = self.toolchain_lock.clone();
+        let installed = toolchain_lock
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .is_some();
+        if !installed {
+            // The toolchain couldn't be installed when the daemon started.
+            self.install_first_toolchain()?;
+        }
+        let pinned = crate_toolchain(&mut conn, name)?;
+        if let Some(pinned) = &pinned {
+            // Pinned toolchains are rare, and rustup can't install them while another toolchain
+            // is being updated.
+            let _installing = toolchain_lock
+                .write()
+                .unwrap_or_else(PoisonError::into_inner);
+            self.use_pinned_toolchain(pinned)?;
+        }
+        let building = toolchain_lock
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        if let (None, Some(current)) = (&pinned, &*building) {
+            self.toolchain = Toolchain::dist(&current.name);
+            self.rustc_version = current.rustc_version.clone();
+        }
 
         info!("building package {} {}", name, version);
 
@@ -296,9 +616,12 @@ impl RustwideBuilder {
             return Ok(false);
         }
 
-        let limits = Limits::for_crate(&mut conn, name)?;
+        let limits = Limits::for_crate(&self.config, &mut conn, name)?;
 
-        let mut build_dir = self.workspace.build_dir(&format!("{}-{}", name, version));
+        let mut build_dir = match &self.worker_name {
+            Some(worker) => self.workspace.build_dir(worker),
+            None => self.workspace.build_dir(&format!("{}-{}", name, version)),
+        };
         build_dir.purge()?;
 
         let krate = if let Some(path) = local {
@@ -315,8 +638,10 @@ impl RustwideBuilder {
             .run(|build| {
                 use docsrs_metadata::BuildTargets;
 
+                let start = Instant::now();
                 let mut has_docs = false;
                 let mut successful_targets = Vec::new();
+                let mut other_target_results = Vec::new();
                 let metadata = Metadata::from_crate_root(&build.host_source_dir())?;
                 let BuildTargets {
                     default_target,
@@ -324,7 +649,8 @@ impl RustwideBuilder {
                 } = metadata.targets();
 
                 // Perform an initial build
-                let res = self.execute_build(default_target, true, &build, &limits, &metadata)?;
+                let mut res =
+                    self.execute_build(default_target, true, &build, &limits, &metadata)?;
                 if res.result.successful {
                     if let Some(name) = res.cargo_metadata.root().library_name() {
                         let host_target = build.host_target_dir();
@@ -333,9 +659,12 @@ impl RustwideBuilder {
                 }
 
                 let mut algs = HashSet::new();
+                let mut documentation_size = None;
                 if has_docs {
                     debug!("adding documentation for the default target to the database");
                     self.copy_docs(&build.host_target_dir(), local_storage.path(), "", true)?;
+                    // The other targets are copied in subdirectories afterwards.
+                    res.result.documentation_size = Some(directory_size(local_storage.path())?);
 
                     successful_targets.push(res.target.clone());
 
@@ -343,15 +672,16 @@ impl RustwideBuilder {
                     // Limit the number of targets so that no one can try to build all 200000 possible targets
                     for target in other_targets.into_iter().take(limits.targets()) {
                         debug!("building package {} {} for {}", name, version, target);
-                        self.build_target(
+                        other_target_results.push(self.build_target(
                             target,
                             &build,
                             &limits,
                             &local_storage.path(),
                             &mut successful_targets,
                             &metadata,
-                        )?;
+                        )?);
                     }
+                    documentation_size = Some(directory_size(local_storage.path())?);
                     let new_algs = self.upload_docs(name, version, local_storage.path())?;
                     algs.extend(new_algs);
                 };
@@ -398,7 +728,45 @@ impl RustwideBuilder {
                     add_doc_coverage(&mut conn, release_id, doc_coverage)?;
                 }
 
-                add_build_into_database(&mut conn, release_id, &res.result)?;
+                if has_docs {
+                    self.add_search_items(&mut conn, release_id, &build, &res)?;
+                }
+
+                let stats = BuildStats {
+                    duration: start.elapsed(),
+                    documentation_size,
+                    peak_memory: std::iter::once(&res)
+                        .chain(&other_target_results)
+                        .filter_map(|target_res| target_res.result.peak_memory)
+                        .max(),
+                };
+                self.metrics
+                    .build_duration
+                    .observe(stats.duration.as_secs_f64());
+                if let Some(size) = stats.documentation_size {
+                    self.metrics.documentation_size.observe(size as f64);
+                }
+                if let Some(peak_memory) = stats.peak_memory {
+                    self.metrics.build_peak_memory.observe(peak_memory as f64);
+                }
+
+                let build_id = add_build_into_database(&mut conn, release_id, &res.result, &stats)?;
+                add_build_target_into_database(
+                    &mut conn,
+                    build_id,
+                    &res.target,
+                    true,
+                    &res.result,
+                )?;
+                for target_res in &other_target_results {
+                    add_build_target_into_database(
+                        &mut conn,
+                        build_id,
+                        &target_res.target,
+                        false,
+                        &target_res.result,
+                    )?;
+                }
 
                 // Some crates.io crate data is mutable, so we proactively update it during a release
                 match self.index.api().get_crate_data(name) {
@@ -415,6 +783,28 @@ impl RustwideBuilder {
         Ok(res.result.successful)
     }
 
+    /// Store the items in the search index of the default target, to allow searching for them
+    /// across all crates. Failing to parse the search index doesn't fail the build.
+    fn add_search_items(
+        &self,
+        conn: &mut Client,
+        release_id: i32,
+        build: &Build,
+        res: &FullBuildResult,
+    ) -> Result<()> {
+        let library_name = match res.cargo_metadata.root().library_name() {
+            Some(name) => name,
+            None => return Ok(()),
+        };
+
+        let doc_dir = build.host_target_dir().join("doc");
+        match extract_search_items(&doc_dir, &library_name) {
+            Ok(items) => add_search_items(conn, release_id, &items)?,
+            Err(err) => warn!("failed to extract the search index items: {}", err),
+        }
+        Ok(())
+    }
+
     fn build_target(
         &self,
         target: &str,
@@ -423,8 +813,8 @@ impl RustwideBuilder {
         local_storage: &Path,
         successful_targets: &mut Vec<String>,
         metadata: &Metadata,
-    ) -> Result<()> {
-        let target_res = self.execute_build(target, false, build, limits, metadata)?;
+    ) -> Result<FullBuildResult> {
+        let mut target_res = self.execute_build(target, false, build, limits, metadata)?;
         if target_res.result.successful {
             // Cargo is not giving any error and not generating documentation of some crates
             // when we use a target compile options. Check documentation exists before
@@ -432,10 +822,12 @@ impl RustwideBuilder {
             if build.host_target_dir().join(target).join("doc").is_dir() {
                 debug!("adding documentation for target {} to the database", target,);
                 self.copy_docs(&build.host_target_dir(), local_storage, target, false)?;
+                target_res.result.documentation_size =
+                    Some(directory_size(&local_storage.join(target))?);
                 successful_targets.push(target.to_string());
             }
         }
-        Ok(())
+        Ok(target_res)
     }
 
     fn get_coverage(
@@ -495,6 +887,7 @@ impl RustwideBuilder {
         limits: &Limits,
         metadata: &Metadata,
     ) -> Result<FullBuildResult> {
+        let start = Instant::now();
         let cargo_metadata =
             CargoMetadata::load(&self.workspace, &self.toolchain, &build.host_source_dir())?;
 
@@ -503,8 +896,9 @@ impl RustwideBuilder {
         for dep in &cargo_metadata.root_dependencies() {
             rustdoc_flags.push("--extern-html-root-url".to_string());
             rustdoc_flags.push(format!(
-                "{}=https://docs.rs/{}/{}",
+                "{}={}/{}/{}",
                 dep.name.replace("-", "_"),
+                self.config.base_url,
                 dep.name,
                 dep.version
             ));
@@ -518,11 +912,27 @@ impl RustwideBuilder {
         let mut storage = LogStorage::new(LevelFilter::Info);
         storage.set_max_size(limits.max_log_size());
 
-        let successful = logging::capture(&storage, || {
+        let mut peak_memory = None;
+        let mut read_peak_memory = |line: &str, actions: &mut ProcessLinesActions| {
+            if let Some(usage) = line.strip_prefix(PEAK_MEMORY_MARKER) {
+                peak_memory = usage.trim().parse().ok();
+                actions.remove_line();
+            }
+        };
+        let result = logging::capture(&storage, || {
             self.prepare_command(build, target, metadata, limits, rustdoc_flags)
-                .and_then(|command| command.run().map_err(failure::Error::from))
-                .is_ok()
+                .and_then(|command| {
+                    command
+                        .process_lines(&mut read_peak_memory)
+                        .run()
+                        .map_err(failure::Error::from)
+                })
         });
+        let build_log = storage.to_string();
+        let successful = result.is_ok();
+        let failure_reason = result
+            .err()
+            .map(|err| BuildFailureReason::classify(&err, &build_log));
         let doc_coverage = if successful {
             self.get_coverage(target, build, metadata, limits)?
         } else {
@@ -544,11 +954,16 @@ impl RustwideBuilder {
 
         Ok(FullBuildResult {
             result: BuildResult {
-                build_log: storage.to_string(),
+                build_log,
                 rustc_version: self.rustc_version.clone(),
                 docsrs_version: format!("docsrs {}", crate::BUILD_VERSION),
                 successful,
+                failure_reason,
                 doc_coverage,
+                duration: start.elapsed(),
+                // Measured once the documentation is copied, like the size of the whole build.
+                documentation_size: None,
+                peak_memory,
             },
             cargo_metadata,
             target: target.to_string(),
@@ -572,7 +987,7 @@ impl RustwideBuilder {
         let mut cargo_args = metadata.cargo_args();
 
         // Add docs.rs specific arguments
-        if let Some(cpu_limit) = self.config.build_cpu_limit {
+        if let Some(cpu_limit) = limits.cpu_limit() {
             cargo_args.push(format!("-j{}", cpu_limit));
         }
         if target != HOST_TARGET {
@@ -588,7 +1003,7 @@ impl RustwideBuilder {
         rustdoc_flags.push_str(&rustdoc_flags_extras.join(" "));
 
         let mut command = build
-            .cargo()
+            .cmd(CargoWithPeakMemory(self.toolchain.cargo()))
             .timeout(Some(limits.timeout()))
             .no_output_timeout(None);
         for (key, val) in env_vars {
@@ -654,6 +1069,12 @@ impl RustwideBuilder {
     }
 }
 
+/// Name of the toolchain used for the builds: the one chosen with `build set-toolchain` or
+/// promoted by a canary run, or the configured one if none was chosen.
+fn toolchain_name(conn: &mut Client, config: &Config) -> Result<String> {
+    Ok(current_toolchain(conn)?.unwrap_or_else(|| config.toolchain.clone()))
+}
+
 struct FullBuildResult {
     result: BuildResult,
     target: String,
@@ -669,10 +1090,38 @@ pub(crate) struct DocCoverage {
     pub(crate) documented_items: i32,
 }
 
+#[derive(Clone)]
 pub(crate) struct BuildResult {
     pub(crate) rustc_version: String,
     pub(crate) docsrs_version: String,
     pub(crate) build_log: String,
     pub(crate) successful: bool,
+    pub(crate) failure_reason: Option<BuildFailureReason>,
     pub(crate) doc_coverage: Option<DocCoverage>,
+    pub(crate) duration: Duration,
+    /// Size of the documentation in bytes, if the build was successful and generated some.
+    pub(crate) documentation_size: Option<u64>,
+    /// Peak memory usage of the sandbox in bytes, if it could be read.
+    pub(crate) peak_memory: Option<u64>,
+}
+
+/// Statistics about a whole build, including all its targets.
+pub(crate) struct BuildStats {
+    pub(crate) duration: Duration,
+    /// Size in bytes of the documentation of all the targets.
+    pub(crate) documentation_size: Option<u64>,
+    /// Highest peak memory usage of the targets, in bytes.
+    pub(crate) peak_memory: Option<u64>,
+}
+
+/// Total size of the files in a directory, in bytes.
+fn directory_size(path: &Path) -> Result<u64> {
+    let mut size = 0;
+    for entry in walkdir::WalkDir::new(path) {
+        let entry = entry?;
+        if entry.file_type().is_file() {
+            size += entry.metadata()?.len();
+        }
+    }
+    Ok(size)
 }
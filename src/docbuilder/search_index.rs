@@ -0,0 +1,276 @@
+//! Extraction of the items listed in the `search-index.js` file generated by rustdoc, used to
+//! search for items across all the crates hosted on docs.rs.
+
+use crate::error::Result;
+use failure::err_msg;
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+
+/// Maximum number of items stored for a single release, to avoid filling the database with the
+/// items of huge generated crates.
+const MAX_SEARCH_ITEMS: usize = 50_000;
+
+/// Names of the item types, in the order used by rustdoc in the search index. They're also used
+/// by rustdoc as the prefix of the file names of the items' pages.
+const ITEM_TYPES: &[&str] = &[
+    "mod",
+    "externcrate",
+    "import",
+    "struct",
+    "enum",
+    "fn",
+    "type",
+    "static",
+    "trait",
+    "impl",
+    "tymethod",
+    "method",
+    "structfield",
+    "variant",
+    "macro",
+    "primitive",
+    "associatedtype",
+    "constant",
+    "associatedconstant",
+    "union",
+    "foreigntype",
+    "keyword",
+    "opaque",
+    "attr",
+    "derive",
+    "traitalias",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SearchItem {
+    pub(crate) name: String,
+    /// Full path of the item, for example `std::collections::HashMap::entry`.
+    pub(crate) path: String,
+    pub(crate) kind: String,
+    /// Link to the item's page, relative to the root of the release's documentation.
+    pub(crate) url: String,
+    pub(crate) description: Option<String>,
+}
+
+/// Extract the items of the crate `crate_name` from the search index in the `doc_dir` directory.
+pub(crate) fn extract_search_items(doc_dir: &Path, crate_name: &str) -> Result<Vec<SearchItem>> {
+    // The name of the file depends on the `--resource-suffix` passed to rustdoc.
+    for entry in fs::read_dir(doc_dir)? {
+        let file_name = entry?.file_name();
+        let file_name = file_name.to_string_lossy();
+        if file_name.starts_with("search-index") && file_name.ends_with(".js") {
+            let content = fs::read_to_string(doc_dir.join(&*file_name))?;
+            return parse_search_index(&content, crate_name);
+        }
+    }
+
+    Ok(Vec::new())
+}
+
+fn parse_search_index(content: &str, crate_name: &str) -> Result<Vec<SearchItem>> {
+    let index = load_crate_index(content, crate_name)?;
+    let (items, parents) = match (index.get("i"), index.get("p")) {
+        (Some(Value::Array(items)), Some(Value::Array(parents))) => (items, parents),
+        _ => {
+            return Err(err_msg(
+                "the search index is missing the items or their parents",
+            ))
+        }
+    };
+
+    let mut result = Vec::new();
+    // Rustdoc only includes the path of an item when it's different from the previous one.
+    let mut last_path = String::new();
+    for item in items.iter().take(MAX_SEARCH_ITEMS) {
+        let item = match item.as_array() {
+            Some(item) if item.len() >= 5 => item,
+            _ => continue,
+        };
+        let path = item[2].as_str().unwrap_or_default();
+        if !path.is_empty() {
+            last_path = path.into();
+        }
+
+        let (kind, name) = match (item_type(&item[0]), item[1].as_str()) {
+            (Some(kind), Some(name)) => (kind, name),
+            _ => continue,
+        };
+        let module_url = last_path.replace("::", "/");
+        let description = item[3]
+            .as_str()
+            .filter(|desc| !desc.is_empty())
+            .map(String::from);
+
+        let parent = item[4]
+            .as_u64()
+            .and_then(|idx| parents.get(idx as usize))
+            .and_then(|parent| parent.as_array())
+            .and_then(|parent| match (parent.get(0), parent.get(1)) {
+                (Some(kind), Some(Value::String(name))) => Some((item_type(kind)?, name.as_str())),
+                _ => None,
+            });
+
+        let (path, url) = if let Some((parent_kind, parent_name)) = parent {
+            (
+                format!("{}::{}::{}", last_path, parent_name, name),
+                format!(
+                    "{}/{}.{}.html#{}.{}",
+                    module_url, parent_kind, parent_name, kind, name
+                ),
+            )
+        } else if kind == "mod" {
+            (
+                format!("{}::{}", last_path, name),
+                format!("{}/{}/index.html", module_url, name),
+            )
+        } else {
+            (
+                format!("{}::{}", last_path, name),
+                format!("{}/{}.{}.html", module_url, kind, name),
+            )
+        };
+
+        result.push(SearchItem {
+            name: name.into(),
+            path,
+            kind: kind.into(),
+            url,
+            description,
+        });
+    }
+
+    Ok(result)
+}
+
+fn item_type(value: &Value) -> Option<&'static str> {
+    value
+        .as_u64()
+        .and_then(|idx| ITEM_TYPES.get(idx as usize))
+        .copied()
+}
+
+/// Load the index of a single crate from the search index, which is a JavaScript file in the
+/// `var searchIndex = JSON.parse('{"name":{...}}');` format rather than plain JSON.
+fn load_crate_index(content: &str, crate_name: &str) -> Result<Value> {
+    const JSON_PARSE_START: &str = "JSON.parse('";
+
+    let start = content
+        .find(JSON_PARSE_START)
+        .ok_or_else(|| err_msg("unsupported search index format"))?
+        + JSON_PARSE_START.len();
+    // Quotes in the string are escaped, so the last unescaped one ends it.
+    let end = content
+        .rfind("');")
+        .filter(|&end| end >= start)
+        .ok_or_else(|| err_msg("unterminated string in the search index"))?;
+
+    let mut index: Value = serde_json::from_str(&unescape_js_string(&content[start..end]))?;
+    index
+        .get_mut(crate_name)
+        .map(Value::take)
+        .ok_or_else(|| err_msg("the crate is missing from the search index"))
+}
+
+/// Rustdoc escapes backslashes and quotes in the string, and splits it over multiple lines by
+/// ending each line with a backslash.
+fn unescape_js_string(escaped: &str) -> String {
+    let mut result = String::with_capacity(escaped.len());
+    let mut chars = escaped.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('\n') | None => {}
+                Some(escaped) => result.push(escaped),
+            }
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ITEMS: &str = r#"{"doc":"A crate.","i":[[3,"Foo","foo","A struct's \"docs\"",null,null],[11,"new","","Creates a Foo.",0,null],[0,"bar","","",null,null],[5,"baz","foo::bar","",null,null]],"p":[[3,"Foo"]]}"#;
+
+    fn expected() -> Vec<SearchItem> {
+        vec![
+            SearchItem {
+                name: "Foo".into(),
+                path: "foo::Foo".into(),
+                kind: "struct".into(),
+                url: "foo/struct.Foo.html".into(),
+                description: Some("A struct's \"docs\"".into()),
+            },
+            SearchItem {
+                name: "new".into(),
+                path: "foo::Foo::new".into(),
+                kind: "method".into(),
+                url: "foo/struct.Foo.html#method.new".into(),
+                description: Some("Creates a Foo.".into()),
+            },
+            SearchItem {
+                name: "bar".into(),
+                path: "foo::bar".into(),
+                kind: "mod".into(),
+                url: "foo/bar/index.html".into(),
+                description: None,
+            },
+            SearchItem {
+                name: "baz".into(),
+                path: "foo::bar::baz".into(),
+                kind: "fn".into(),
+                url: "foo/bar/fn.baz.html".into(),
+                description: None,
+            },
+        ]
+    }
+
+    /// Generate a search index in the same format as rustdoc.
+    fn search_index(crates: &[(&str, &str)]) -> String {
+        let crates = crates
+            .iter()
+            .map(|(name, index)| {
+                format!(
+                    "\"{}\":{}",
+                    name,
+                    index.replace('\\', "\\\\").replace('\'', "\\'")
+                )
+            })
+            .collect::<Vec<_>>();
+        format!(
+            "var searchIndex = JSON.parse('{{\\\n{}\\\n}}');\n\
+             addSearchOptions(searchIndex);initSearch(searchIndex);",
+            crates.join(",\\\n")
+        )
+    }
+
+    #[test]
+    fn test_parse_search_index() {
+        let content = search_index(&[("other", r#"{"doc":"","i":[],"p":[]}"#), ("foo", ITEMS)]);
+        assert_eq!(parse_search_index(&content, "foo").unwrap(), expected());
+        assert!(parse_search_index(&content, "missing").is_err());
+    }
+
+    #[test]
+    fn test_parse_unsupported_format() {
+        let content = format!("searchIndex[\"foo\"] = {};", ITEMS);
+        assert!(parse_search_index(&content, "foo").is_err());
+    }
+
+    #[test]
+    fn test_extract_search_items() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("main-20200101.js"), "").unwrap();
+        fs::write(
+            dir.path().join("search-index-20200101.js"),
+            search_index(&[("foo", ITEMS)]),
+        )
+        .unwrap();
+
+        assert_eq!(extract_search_items(dir.path(), "foo").unwrap(), expected());
+    }
+}
@@ -1,10 +1,13 @@
-use std::{path::PathBuf, process::Command};
+use std::{fs, path::PathBuf, process::Command};
 
 use url::Url;
 
 use self::{api::Api, crates::Crates};
 use crate::{error::Result, Config};
 use failure::ResultExt;
+use fs2::FileExt;
+use git2::Oid;
+use postgres::Client;
 
 pub(crate) mod api;
 mod crates;
@@ -14,6 +17,11 @@ pub struct Index {
     api: Api,
 }
 
+/// Exclusive lock on the index repository, released when dropped.
+pub(crate) struct IndexLock {
+    _file: fs::File,
+}
+
 #[derive(serde::Deserialize, Clone)]
 #[serde(rename_all = "kebab-case")]
 struct IndexConfig {
@@ -39,6 +47,18 @@ fn load_config(repo: &git2::Repository) -> Result<IndexConfig> {
     Ok(config)
 }
 
+/// Fetches the latest changes of the index, to the same remote branch crates-index-diff uses.
+fn fetch(repo: &git2::Repository) -> Result<()> {
+    repo.find_remote("origin")?
+        .fetch(
+            &["+refs/heads/master:refs/remotes/origin/master"],
+            None,
+            None,
+        )
+        .context("fetching the registry index")?;
+    Ok(())
+}
+
 impl Index {
     pub fn new(app_config: &Config) -> Result<Self> {
         let path = app_config.registry_index_path.clone();
@@ -51,21 +71,82 @@ impl Index {
         Ok(Self { path, api })
     }
 
-    pub(crate) fn diff(&self) -> Result<crates_index_diff::Index> {
+    /// Opens the index to look for changes, starting from the last seen commit stored in the
+    /// database.
+    pub(crate) fn diff(&self, conn: &mut Client) -> Result<crates_index_diff::Index> {
         let diff = crates_index_diff::Index::from_path_or_cloned(&self.path)
             .context("re-opening registry index for diff")?;
+
+        // crates-index-diff starts from a branch in the local repository: point it to the commit
+        // stored in the database. If nothing is stored yet, the branch is used as is.
+        if let Some(oid) = self.last_seen_commit(conn)? {
+            let repo = diff.repository();
+            if repo.find_commit(oid).is_err() {
+                // The commit might have been processed by another machine after the last fetch.
+                fetch(repo)?;
+            }
+            if repo.find_commit(oid).is_err() {
+                failure::bail!(
+                    "the last seen commit {} is missing from the registry index, \
+                     was the index squashed?",
+                    oid
+                );
+            }
+            diff.set_last_seen_reference(oid)
+                .context("updating the last seen reference")?;
+        }
+
         Ok(diff)
     }
 
+    /// Returns the last commit of the index processed by docs.rs, if any.
+    pub(crate) fn last_seen_commit(&self, conn: &mut Client) -> Result<Option<Oid>> {
+        let rows = conn.query("SELECT last_seen_commit FROM registry_index;", &[])?;
+        match rows.get(0) {
+            Some(row) => Ok(Some(Oid::from_str(row.get(0))?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Records the last commit of the index processed by docs.rs.
+    pub(crate) fn set_last_seen_commit(&self, conn: &mut Client, oid: Oid) -> Result<()> {
+        conn.execute(
+            "INSERT INTO registry_index (last_seen_commit) VALUES ($1)
+             ON CONFLICT (id) DO UPDATE
+                SET last_seen_commit = EXCLUDED.last_seen_commit,
+                    updated_at = CURRENT_TIMESTAMP;",
+            &[&oid.to_string()],
+        )?;
+        Ok(())
+    }
+
+    /// Locks the index repository, waiting for the lock to be released if another thread or
+    /// process on this machine is already holding it.
+    pub(crate) fn lock(&self) -> Result<IndexLock> {
+        let mut path = self.path.clone().into_os_string();
+        path.push(".lock");
+
+        let file = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .open(&path)
+            .context("opening the registry index lock file")?;
+        file.lock_exclusive()
+            .context("locking the registry index")?;
+
+        // The lock is released automatically by the OS when the file is closed.
+        Ok(IndexLock { _file: file })
+    }
+
     pub(crate) fn crates(&self) -> Result<Crates> {
-        // First ensure the index is up to date, peeking will pull the latest changes without
-        // affecting anything else.
+        // First ensure the index is up to date.
         log::debug!("Updating index");
-        self.diff()?.peek_changes()?;
+        let repo = git2::Repository::open(&self.path)?;
+        fetch(&repo)?;
         // It'd be nice to use `crates_index` directly for interacting with the index, but it
         // doesn't support bare repositories. So we use its `Crate` type but walk the index
         // ourselves.
-        Ok(Crates::new(git2::Repository::open(&self.path)?))
+        Ok(Crates::new(repo))
     }
 
     pub fn api(&self) -> &Api {
@@ -2,10 +2,11 @@
 //! documentation of crates for the Rust Programming Language.
 #![allow(clippy::cognitive_complexity)]
 
-pub use self::build_queue::BuildQueue;
+pub use self::build_queue::{BuildQueue, QueuedCrate, RebuildFilter};
 pub use self::config::Config;
 pub use self::context::Context;
 pub use self::docbuilder::DocBuilder;
+pub use self::docbuilder::Limits;
 pub use self::docbuilder::RustwideBuilder;
 pub use self::index::Index;
 pub use self::metrics::Metrics;
@@ -1,5 +1,10 @@
 pub(super) trait MetricFromOpts: Sized {
-    fn from_opts(opts: prometheus::Opts) -> Result<Self, prometheus::Error>;
+    /// Create the metric. `buckets` is only used by histograms, which use the default buckets of
+    /// the prometheus crate when it's `None`.
+    fn from_opts(
+        opts: prometheus::Opts,
+        buckets: Option<Vec<f64>>,
+    ) -> Result<Self, prometheus::Error>;
 }
 
 #[macro_export]
@@ -10,6 +15,7 @@ macro_rules! metrics {
                 #[doc = $help:expr]
                 $(#[$meta:meta])*
                 $metric_vis:vis $metric:ident: $ty:ty $([$($label:expr),* $(,)?])?
+                $(= buckets $buckets:expr)?
             ),* $(,)?
         }
         namespace: $namespace:expr,
@@ -29,7 +35,8 @@ macro_rules! metrics {
                     let $metric = <$ty>::from_opts(
                         prometheus::Opts::new(stringify!($metric), $help)
                             .namespace($namespace)
-                            $(.variable_labels(vec![$($label.into()),*]))?
+                            $(.variable_labels(vec![$($label.into()),*]))?,
+                        None$(.or(Some($buckets.to_vec())))?,
                     )?;
                     $(#[$meta])*
                     registry.register(Box::new($metric.clone()))?;
@@ -56,7 +63,10 @@ macro_rules! load_metric_type {
     ($name:ident as single) => {
         use prometheus::$name;
         impl MetricFromOpts for $name {
-            fn from_opts(opts: prometheus::Opts) -> Result<Self, prometheus::Error> {
+            fn from_opts(
+                opts: prometheus::Opts,
+                _buckets: Option<Vec<f64>>,
+            ) -> Result<Self, prometheus::Error> {
                 $name::with_opts(opts)
             }
         }
@@ -64,7 +74,10 @@ macro_rules! load_metric_type {
     ($name:ident as vec) => {
         use prometheus::$name;
         impl MetricFromOpts for $name {
-            fn from_opts(opts: prometheus::Opts) -> Result<Self, prometheus::Error> {
+            fn from_opts(
+                opts: prometheus::Opts,
+                _buckets: Option<Vec<f64>>,
+            ) -> Result<Self, prometheus::Error> {
                 $name::new(
                     opts.clone().into(),
                     opts.variable_labels
@@ -76,4 +89,42 @@ macro_rules! load_metric_type {
             }
         }
     };
+    ($name:ident as histogram) => {
+        use prometheus::$name;
+        impl MetricFromOpts for $name {
+            fn from_opts(
+                opts: prometheus::Opts,
+                buckets: Option<Vec<f64>>,
+            ) -> Result<Self, prometheus::Error> {
+                let mut opts = prometheus::HistogramOpts::from(opts);
+                if let Some(buckets) = buckets {
+                    opts = opts.buckets(buckets);
+                }
+                $name::with_opts(opts)
+            }
+        }
+    };
+    ($name:ident as histogram_vec) => {
+        use prometheus::$name;
+        impl MetricFromOpts for $name {
+            fn from_opts(
+                opts: prometheus::Opts,
+                buckets: Option<Vec<f64>>,
+            ) -> Result<Self, prometheus::Error> {
+                let labels = opts.variable_labels.clone();
+                let mut opts = prometheus::HistogramOpts::from(opts);
+                if let Some(buckets) = buckets {
+                    opts = opts.buckets(buckets);
+                }
+                $name::new(
+                    opts,
+                    labels
+                        .iter()
+                        .map(|s| s.as_str())
+                        .collect::<Vec<_>>()
+                        .as_slice(),
+                )
+            }
+        }
+    };
 }
@@ -10,7 +10,8 @@ use prometheus::proto::MetricFamily;
 load_metric_type!(IntGauge as single);
 load_metric_type!(IntCounter as single);
 load_metric_type!(IntCounterVec as vec);
-load_metric_type!(HistogramVec as vec);
+load_metric_type!(Histogram as histogram);
+load_metric_type!(HistogramVec as histogram_vec);
 
 metrics! {
     pub struct Metrics {
@@ -52,9 +53,25 @@ metrics! {
         pub(crate) failed_builds: IntCounter,
         /// Number of builds that did not complete due to not being a library
         pub(crate) non_library_builds: IntCounter,
+        /// The time it takes to build a crate, in seconds
+        pub(crate) build_duration: Histogram
+            = buckets [1.0, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0, 900.0, 1800.0],
+        /// The size of the generated documentation, in bytes
+        pub(crate) documentation_size: Histogram
+            = buckets [1e4, 1e5, 1e6, 1e7, 5e7, 1e8, 5e8, 1e9],
+        /// The peak memory usage of the builds, in bytes
+        pub(crate) build_peak_memory: Histogram
+            = buckets [1e8, 2.5e8, 5e8, 1e9, 2e9, 3e9, 4e9, 6e9, 8e9],
 
         /// Number of files uploaded to the storage backend
         pub(crate) uploaded_files_total: IntCounter,
+        /// Number of files not uploaded because the same content was already stored
+        pub(crate) deduplicated_files_total: IntCounter,
+        /// Number of bytes not uploaded because the same content was already stored
+        pub(crate) deduplicated_bytes_total: IntCounter,
+
+        /// Number of index syncs started by the registry index webhook
+        pub(crate) index_webhook_syncs: IntCounter,
 
         /// The number of attempted files that failed due to a memory limit
         pub(crate) html_rewrite_ooms: IntCounter,
@@ -1,5 +1,5 @@
 use super::{Blob, StorageTransaction};
-use crate::db::Pool;
+use crate::db::{escape_like, Pool};
 use crate::Metrics;
 use chrono::{DateTime, NaiveDateTime, Utc};
 use failure::Error;
@@ -68,25 +68,44 @@ impl DatabaseBackend {
         }
     }
 
-    pub(super) fn start_connection(&self) -> Result<DatabaseClient, Error> {
-        Ok(DatabaseClient {
-            conn: self.pool.get()?,
-            metrics: self.metrics.clone(),
-        })
+    pub(super) fn list_paths(
+        &self,
+        prefix: &str,
+        start_after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<String>, Error> {
+        let limit = limit.min(std::i64::MAX as usize) as i64;
+        let rows = self.pool.get()?.query(
+            "SELECT path
+             FROM files
+             WHERE path LIKE $1 AND ($2::TEXT IS NULL OR path COLLATE \"C\" > $2)
+             ORDER BY path COLLATE \"C\"
+             LIMIT $3;",
+            &[&format!("{}%", escape_like(prefix)), &start_after, &limit],
+        )?;
+        Ok(rows.into_iter().map(|row| row.get(0)).collect())
     }
-}
 
-pub(super) struct DatabaseClient {
-    conn: crate::db::PoolClient,
-    metrics: Arc<Metrics>,
-}
+    pub(super) fn list_directories(&self, prefix: &str) -> Result<Vec<String>, Error> {
+        // `SUBSTR` counts characters, not bytes, and starts at 1.
+        let start = (prefix.chars().count() + 1) as i32;
+        let rows = self.pool.get()?.query(
+            "SELECT DISTINCT SPLIT_PART(SUBSTR(path, $2), '/', 1)
+             FROM files
+             WHERE path LIKE $1 AND STRPOS(SUBSTR(path, $2), '/') > 0;",
+            &[&format!("{}%", escape_like(prefix)), &start],
+        )?;
+        Ok(rows.into_iter().map(|row| row.get(0)).collect())
+    }
 
-impl DatabaseClient {
-    pub(super) fn start_storage_transaction(
-        &mut self,
-    ) -> Result<DatabaseStorageTransaction<'_>, Error> {
+    /// Start a transaction nested in `index`, as the files are stored in the same database as the
+    /// deduplication index.
+    pub(super) fn start_storage_transaction<'a>(
+        &'a self,
+        index: &'a mut Transaction<'_>,
+    ) -> Result<DatabaseStorageTransaction<'a>, Error> {
         Ok(DatabaseStorageTransaction {
-            transaction: self.conn.transaction()?,
+            transaction: index.transaction()?,
             metrics: &self.metrics,
         })
     }
@@ -116,11 +135,17 @@ impl<'a> StorageTransaction for DatabaseStorageTransaction<'a> {
     fn delete_prefix(&mut self, prefix: &str) -> Result<(), Error> {
         self.transaction.execute(
             "DELETE FROM files WHERE path LIKE $1;",
-            &[&format!("{}%", prefix.replace('%', "\\%"))],
+            &[&format!("{}%", escape_like(prefix))],
         )?;
         Ok(())
     }
 
+    fn delete_paths(&mut self, paths: &[String]) -> Result<(), Error> {
+        self.transaction
+            .execute("DELETE FROM files WHERE path = ANY($1);", &[&paths])?;
+        Ok(())
+    }
+
     fn complete(self: Box<Self>) -> Result<(), Error> {
         self.transaction.commit()?;
         Ok(())
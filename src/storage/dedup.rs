@@ -0,0 +1,266 @@
+//! Content-addressed deduplication of the stored files.
+//!
+//! Many releases of the same crate contain byte-identical files, so `Storage::store_all` stores
+//! the content of every file once under `blobs/`, named after the SHA-256 of the content, and
+//! records the paths pointing to it in the database. Each blob keeps the number of paths
+//! referencing it, and is deleted from the storage backend when the last of them is deleted.
+//!
+//! The index is kept for each storage backend, as the blobs of a backend are not available in
+//! the others.
+
+use crate::db::escape_like;
+use failure::Error;
+use postgres::{Client, Transaction};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// Prefix of the storage where the content of the deduplicated files is stored.
+pub(super) const BLOBS_PREFIX: &str = "blobs/";
+
+/// Path sorting after all the blobs, as the hashes are lowercase hexadecimal strings.
+pub(super) const AFTER_BLOBS: &str = "blobs/g";
+
+/// Path of the blob storing `hash`. The blobs are spread across subdirectories, to avoid having
+/// too many files in a single directory with the local storage backend.
+pub(super) fn blob_path(hash: &str) -> String {
+    format!("{}{}/{}", BLOBS_PREFIX, &hash[..2], hash)
+}
+
+pub(super) fn content_hash(content: &[u8]) -> String {
+    hex::encode(Sha256::digest(content))
+}
+
+fn like_prefix(prefix: &str) -> String {
+    format!("{}%", escape_like(prefix))
+}
+
+/// Returns the hash of the content stored at `path` and its mime type, if the path is in the
+/// index.
+pub(super) fn lookup(
+    conn: &mut Client,
+    backend: &str,
+    path: &str,
+) -> Result<Option<(String, String)>, Error> {
+    let rows = conn.query(
+        "SELECT hash, mime FROM storage_paths WHERE backend = $1 AND path = $2;",
+        &[&backend, &path],
+    )?;
+    Ok(rows.get(0).map(|row| (row.get("hash"), row.get("mime"))))
+}
+
+/// Same as `Storage::list_paths`, for the paths in the index. The paths are sorted by bytes,
+/// like the paths listed by the storage backends.
+pub(super) fn list_paths(
+    conn: &mut Client,
+    backend: &str,
+    prefix: &str,
+    start_after: Option<&str>,
+    limit: usize,
+) -> Result<Vec<String>, Error> {
+    let limit = limit.min(std::i64::MAX as usize) as i64;
+    let rows = conn.query(
+        "SELECT path
+         FROM storage_paths
+         WHERE backend = $1 AND path LIKE $2 ESCAPE '\\' AND ($3::TEXT IS NULL OR path > $3)
+         ORDER BY path
+         LIMIT $4;",
+        &[&backend, &like_prefix(prefix), &start_after, &limit],
+    )?;
+    Ok(rows.into_iter().map(|row| row.get(0)).collect())
+}
+
+/// Same as `Storage::list_directories`, for the paths in the index.
+pub(super) fn list_directories(
+    conn: &mut Client,
+    backend: &str,
+    prefix: &str,
+) -> Result<Vec<String>, Error> {
+    // `SUBSTR` counts characters, not bytes, and starts at 1.
+    let start = (prefix.chars().count() + 1) as i32;
+    let rows = conn.query(
+        "SELECT DISTINCT SPLIT_PART(SUBSTR(path, $3), '/', 1)
+         FROM storage_paths
+         WHERE backend = $1 AND path LIKE $2 ESCAPE '\\' AND STRPOS(SUBSTR(path, $3), '/') > 0;",
+        &[&backend, &like_prefix(prefix), &start],
+    )?;
+    Ok(rows.into_iter().map(|row| row.get(0)).collect())
+}
+
+/// A path to add to the index.
+pub(super) struct Reference<'a> {
+    pub(super) path: &'a str,
+    pub(super) hash: &'a str,
+    pub(super) mime: &'a str,
+}
+
+/// The changes made to the index by `add_references`.
+pub(super) struct AddedReferences {
+    /// Hashes of the blobs that need to be uploaded.
+    pub(super) new_blobs: HashSet<String>,
+    /// Hashes the paths previously pointed to.
+    pub(super) replaced: Vec<String>,
+    /// The paths that weren't in the index before.
+    pub(super) new_paths: Vec<String>,
+}
+
+/// Point the paths of `references` to the blobs storing their hash.
+///
+/// The rows of the paths and of the blobs stay locked until the transaction ends, so the blobs
+/// can't be deleted concurrently before they're uploaded and the paths are committed. Concurrent
+/// transactions lock the rows in the same order to avoid deadlocks: first the existing paths and
+/// then the blobs, both sorted, and finally the new paths, sorted as well.
+pub(super) fn add_references(
+    trans: &mut Transaction<'_>,
+    backend: &str,
+    references: &[Reference<'_>],
+) -> Result<AddedReferences, Error> {
+    let paths: Vec<&str> = references.iter().map(|reference| reference.path).collect();
+    let previous: HashMap<String, String> = trans
+        .query(
+            "SELECT path, hash FROM storage_paths
+             WHERE backend = $1 AND path = ANY($2)
+             ORDER BY path
+             FOR UPDATE;",
+            &[&backend, &paths],
+        )?
+        .into_iter()
+        .map(|row| (row.get(0), row.get(1)))
+        .collect();
+
+    let mut refcounts: BTreeMap<&str, i32> = BTreeMap::new();
+    for reference in references {
+        *refcounts.entry(reference.hash).or_default() += 1;
+        if let Some(hash) = previous.get(reference.path) {
+            *refcounts.entry(hash.as_str()).or_default() -= 1;
+        }
+    }
+
+    let mut new_blobs = HashSet::new();
+    for (hash, change) in refcounts {
+        if change > 0 {
+            // The blob can be deleted by another transaction between the two queries, in which
+            // case it has to be inserted again.
+            loop {
+                let inserted = trans.execute(
+                    "INSERT INTO storage_blobs (backend, hash, refcount) VALUES ($1, $2, $3)
+                     ON CONFLICT (backend, hash) DO NOTHING;",
+                    &[&backend, &hash, &change],
+                )?;
+                if inserted == 1 {
+                    new_blobs.insert(hash.to_string());
+                    break;
+                }
+
+                let updated = trans.execute(
+                    "UPDATE storage_blobs SET refcount = refcount + $3
+                     WHERE backend = $1 AND hash = $2;",
+                    &[&backend, &hash, &change],
+                )?;
+                if updated == 1 {
+                    break;
+                }
+            }
+        } else if change < 0 {
+            // The blob is still referenced by the locked paths, so it can't have been deleted.
+            trans.execute(
+                "UPDATE storage_blobs SET refcount = refcount + $3
+                 WHERE backend = $1 AND hash = $2;",
+                &[&backend, &hash, &change],
+            )?;
+        }
+    }
+
+    let mut sorted: Vec<_> = references.iter().collect();
+    sorted.sort_by_key(|reference| reference.path);
+    for reference in sorted {
+        trans.execute(
+            "INSERT INTO storage_paths (backend, path, hash, mime) VALUES ($1, $2, $3, $4)
+             ON CONFLICT (backend, path) DO UPDATE SET hash = EXCLUDED.hash, mime = EXCLUDED.mime;",
+            &[&backend, &reference.path, &reference.hash, &reference.mime],
+        )?;
+    }
+
+    let new_paths = references
+        .iter()
+        .filter(|reference| !previous.contains_key(reference.path))
+        .map(|reference| reference.path.to_string())
+        .collect();
+    let mut replaced: Vec<String> = previous.into_iter().map(|(_, hash)| hash).collect();
+    replaced.sort();
+    replaced.dedup();
+    Ok(AddedReferences {
+        new_blobs,
+        replaced,
+        new_paths,
+    })
+}
+
+/// Remove the paths starting with `prefix` from the index, returning the hashes they pointed to.
+pub(super) fn remove_prefix(
+    trans: &mut Transaction<'_>,
+    backend: &str,
+    prefix: &str,
+) -> Result<Vec<String>, Error> {
+    remove_references(
+        trans,
+        backend,
+        "path LIKE $2 ESCAPE '\\'",
+        &like_prefix(prefix) as &(dyn postgres::types::ToSql + Sync),
+    )
+}
+
+/// Remove `paths` from the index, returning the hashes they pointed to.
+pub(super) fn remove_paths(
+    trans: &mut Transaction<'_>,
+    backend: &str,
+    paths: &[String],
+) -> Result<Vec<String>, Error> {
+    remove_references(trans, backend, "path = ANY($2)", &paths)
+}
+
+// WARNING: `condition` must be hard-coded and NEVER user input.
+fn remove_references(
+    trans: &mut Transaction<'_>,
+    backend: &str,
+    condition: &str,
+    param: &(dyn postgres::types::ToSql + Sync),
+) -> Result<Vec<String>, Error> {
+    let rows = trans.query(
+        format!(
+            "WITH removed AS (
+                 DELETE FROM storage_paths WHERE backend = $1 AND {} RETURNING hash
+             ), counts AS (
+                 SELECT hash, COUNT(*) AS removed_count FROM removed GROUP BY hash
+             )
+             UPDATE storage_blobs
+             SET refcount = storage_blobs.refcount - counts.removed_count
+             FROM counts
+             WHERE storage_blobs.backend = $1 AND storage_blobs.hash = counts.hash
+             RETURNING storage_blobs.hash;",
+            condition
+        )
+        .as_str(),
+        &[&backend, param],
+    )?;
+    Ok(rows.into_iter().map(|row| row.get(0)).collect())
+}
+
+/// Remove the blobs among `hashes` that aren't referenced anymore, returning their hashes. The
+/// caller is responsible for deleting them from the storage backend before committing.
+pub(super) fn remove_unreferenced(
+    trans: &mut Transaction<'_>,
+    backend: &str,
+    hashes: &[String],
+) -> Result<Vec<String>, Error> {
+    if hashes.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let rows = trans.query(
+        "DELETE FROM storage_blobs
+         WHERE backend = $1 AND hash = ANY($2) AND refcount <= 0
+         RETURNING hash;",
+        &[&backend, &hashes],
+    )?;
+    Ok(rows.into_iter().map(|row| row.get(0)).collect())
+}
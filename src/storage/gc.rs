@@ -0,0 +1,259 @@
+//! Cleanup of the files left in the storage without a matching release, for example when a build
+//! crashed while uploading the documentation or when deleting a release failed halfway.
+
+use super::Storage;
+use failure::Error;
+use postgres::{Client, IsolationLevel, Transaction};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// Directories of the storage containing a `<name>/<version>/` directory for every release.
+const RELEASE_PREFIXES: &[&str] = &["rustdoc", "sources"];
+
+/// The storage of the releases that doesn't match the database.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct GcReport {
+    pub dry_run: bool,
+    /// Directories of the storage without a matching release, like `rustdoc/foo/1.0.0/`.
+    pub orphans: Vec<String>,
+    /// Releases with documentation whose `rustdoc/` directory is missing.
+    pub missing_docs: Vec<(String, String)>,
+    /// Releases with a list of source files whose `sources/` directory is missing.
+    pub missing_sources: Vec<(String, String)>,
+}
+
+impl fmt::Display for GcReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.dry_run {
+            writeln!(f, "Found {} orphaned directories", self.orphans.len())?;
+        } else {
+            writeln!(f, "Deleted {} orphaned directories", self.orphans.len())?;
+        }
+        writeln!(
+            f,
+            "Found {} releases with missing documentation",
+            self.missing_docs.len()
+        )?;
+        write!(
+            f,
+            "Found {} releases with missing sources",
+            self.missing_sources.len()
+        )
+    }
+}
+
+struct Release {
+    has_docs: bool,
+    has_sources: bool,
+}
+
+/// Compares the `rustdoc/` and `sources/` directories of the storage with the releases in the
+/// database, finding the directories that don't belong to any release and the releases whose
+/// files are missing. Unless `dry_run` is set, the orphaned directories are deleted.
+///
+/// The releases in the build queue are skipped, as their files are uploaded before they're added
+/// to the database. The releases built outside of the queue, like with `build crate`, can't be
+/// told apart from the orphans while they're being built, so no builds should be running when the
+/// orphans are deleted.
+pub fn gc_storage(conn: &mut Client, storage: &Storage, dry_run: bool) -> Result<GcReport, Error> {
+    // A build adds its release before removing it from the queue, so reading both from the same
+    // snapshot finds every release whose files may be stored.
+    let mut trans = conn
+        .build_transaction()
+        .isolation_level(IsolationLevel::RepeatableRead)
+        .read_only(true)
+        .start()?;
+    let queued = queued_releases(&mut trans)?;
+    let releases = stored_releases(&mut trans)?;
+    trans.commit()?;
+
+    let mut report = GcReport {
+        dry_run,
+        ..GcReport::default()
+    };
+    let mut stored: HashMap<&str, HashSet<(String, String)>> = HashMap::new();
+    for prefix in RELEASE_PREFIXES {
+        let stored = stored.entry(*prefix).or_default();
+        for name in storage.list_directories(&format!("{}/", prefix))? {
+            for version in storage.list_directories(&format!("{}/{}/", prefix, name))? {
+                let known = releases
+                    .get(&name)
+                    .map_or(false, |versions| versions.contains_key(&version));
+                let release = (name.clone(), version);
+                if !known && !queued.contains(&release) {
+                    let path = format!("{}/{}/{}/", prefix, release.0, release.1);
+                    report.orphans.push(path);
+                }
+                stored.insert(release);
+            }
+        }
+    }
+
+    for (name, versions) in &releases {
+        for (version, release) in versions {
+            let release_key = (name.clone(), version.clone());
+            if release.has_docs && !stored["rustdoc"].contains(&release_key) {
+                report.missing_docs.push(release_key.clone());
+            }
+            if release.has_sources && !stored["sources"].contains(&release_key) {
+                report.missing_sources.push(release_key);
+            }
+        }
+    }
+    report.missing_docs.sort();
+    report.missing_sources.sort();
+
+    if !dry_run {
+        for path in &report.orphans {
+            storage.delete_prefix(path)?;
+        }
+    }
+
+    Ok(report)
+}
+
+fn queued_releases(trans: &mut Transaction<'_>) -> Result<HashSet<(String, String)>, Error> {
+    Ok(trans
+        .query("SELECT name, version FROM queue;", &[])?
+        .into_iter()
+        .map(|row| (row.get(0), row.get(1)))
+        .collect())
+}
+
+fn stored_releases(
+    trans: &mut Transaction<'_>,
+) -> Result<HashMap<String, HashMap<String, Release>>, Error> {
+    let mut releases: HashMap<String, HashMap<String, Release>> = HashMap::new();
+    for row in trans.query(
+        "SELECT crates.name, releases.version, releases.rustdoc_status,
+                releases.files IS NOT NULL AS has_sources
+         FROM releases
+         INNER JOIN crates ON crates.id = releases.crate_id;",
+        &[],
+    )? {
+        releases.entry(row.get("name")).or_default().insert(
+            row.get("version"),
+            Release {
+                has_docs: row
+                    .get::<_, Option<bool>>("rustdoc_status")
+                    .unwrap_or(false),
+                has_sources: row.get("has_sources"),
+            },
+        );
+    }
+    Ok(releases)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::Blob;
+    use crate::test::wrapper;
+    use chrono::Utc;
+
+    fn blob(path: &str) -> Blob {
+        Blob {
+            path: path.into(),
+            mime: "text/plain".into(),
+            date_updated: Utc::now(),
+            content: b"foo\n".to_vec(),
+            compression: None,
+        }
+    }
+
+    #[test]
+    fn test_gc_storage() {
+        wrapper(|env| {
+            env.fake_release().name("foo").version("1.0.0").create()?;
+            env.fake_release().name("foo").version("2.0.0").create()?;
+            env.fake_release()
+                .name("foo1bar")
+                .version("1.0.0")
+                .create()?;
+            env.fake_release()
+                .name("failed")
+                .version("1.0.0")
+                .build_result_successful(false)
+                .create()?;
+
+            let storage = env.storage();
+            // The documentation of foo 2.0.0 is removed to simulate a deletion that failed
+            // halfway, and its sources are never stored.
+            storage.delete_prefix("rustdoc/foo/2.0.0/")?;
+            storage.store_blobs(vec![
+                blob("sources/foo/1.0.0/src/lib.rs"),
+                blob("sources/failed/1.0.0/src/lib.rs"),
+                blob("rustdoc/foo/3.0.0/foo/index.html"),
+                blob("sources/bar/1.0.0/src/lib.rs"),
+                blob("rustdoc/queued/1.0.0/queued/index.html"),
+                blob("rustdoc/foo_bar/1.0.0/foo_bar/index.html"),
+            ])?;
+            env.build_queue().add_crate("queued", "1.0.0", 0)?;
+
+            let mut conn = env.db().conn();
+            let report = gc_storage(&mut conn, &storage, true)?;
+            assert_eq!(
+                report.orphans,
+                vec![
+                    "rustdoc/foo/3.0.0/",
+                    "rustdoc/foo_bar/1.0.0/",
+                    "sources/bar/1.0.0/"
+                ]
+            );
+            assert_eq!(
+                report.missing_docs,
+                vec![("foo".to_string(), "2.0.0".to_string())]
+            );
+            assert_eq!(
+                report.missing_sources,
+                vec![("foo".to_string(), "2.0.0".to_string())]
+            );
+            assert!(storage.exists("rustdoc/foo/3.0.0/foo/index.html")?);
+
+            let report = gc_storage(&mut conn, &storage, false)?;
+            assert_eq!(report.orphans.len(), 3);
+            assert!(!storage.exists("rustdoc/foo/3.0.0/foo/index.html")?);
+            assert!(!storage.exists("rustdoc/foo_bar/1.0.0/foo_bar/index.html")?);
+            // The `_` in `foo_bar` must not match other crates.
+            assert!(storage.exists("rustdoc/foo1bar/1.0.0/foo1bar/index.html")?);
+            assert!(!storage.exists("sources/bar/1.0.0/src/lib.rs")?);
+            assert!(storage.exists("rustdoc/queued/1.0.0/queued/index.html")?);
+            assert!(gc_storage(&mut conn, &storage, false)?.orphans.is_empty());
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_release_built_between_queries() {
+        wrapper(|env| {
+            env.build_queue().add_crate("foo", "1.0.0", 0)?;
+
+            let mut conn = env.db().conn();
+            let mut trans = conn
+                .build_transaction()
+                .isolation_level(IsolationLevel::RepeatableRead)
+                .read_only(true)
+                .start()?;
+            let queued = queued_releases(&mut trans)?;
+
+            // The build finishes after the queue is read.
+            env.fake_release().name("foo").version("1.0.0").create()?;
+            env.db()
+                .conn()
+                .execute("DELETE FROM queue WHERE name = 'foo';", &[])?;
+
+            let releases = stored_releases(&mut trans)?;
+            trans.commit()?;
+            assert!(queued.contains(&("foo".to_string(), "1.0.0".to_string())));
+            assert!(!releases.contains_key("foo"));
+
+            // Once the snapshot is taken after the build, the release is found in the database.
+            let report = gc_storage(&mut conn, &env.storage(), false)?;
+            assert!(report.orphans.is_empty());
+            assert!(env.storage().exists("rustdoc/foo/1.0.0/foo/index.html")?);
+
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,401 @@
+use super::{Blob, StorageTransaction};
+use crate::{Config, Metrics};
+use chrono::{DateTime, Utc};
+use failure::Error;
+use path_slash::PathExt;
+use serde::{Deserialize, Serialize};
+use std::{
+    convert::TryInto,
+    fs,
+    io::{self, Write},
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+};
+use tempfile::TempDir;
+
+/// Directory inside the storage root containing the content of the blobs.
+const CONTENT_DIR: &str = "content";
+/// Directory inside the storage root containing the metadata of the blobs, mirroring the layout
+/// of `CONTENT_DIR`.
+const METADATA_DIR: &str = "metadata";
+/// Directory inside the storage root where blobs are written before being moved in place. It
+/// needs to be on the same filesystem as the other directories for the renames to be atomic.
+const STAGING_DIR: &str = "staging";
+
+#[derive(Serialize, Deserialize)]
+struct BlobMetadata {
+    mime: String,
+    date_updated: DateTime<Utc>,
+    compression: Option<i32>,
+}
+
+pub(super) struct LocalBackend {
+    root: PathBuf,
+    metrics: Arc<Metrics>,
+    #[cfg(test)]
+    temporary: bool,
+}
+
+impl LocalBackend {
+    pub(super) fn new(metrics: Arc<Metrics>, config: &Config) -> Result<Self, Error> {
+        let root = config.local_storage_path.clone();
+        for dir in &[CONTENT_DIR, METADATA_DIR, STAGING_DIR] {
+            fs::create_dir_all(root.join(dir))?;
+        }
+
+        Ok(Self {
+            root,
+            metrics,
+            #[cfg(test)]
+            temporary: config.local_storage_is_temporary,
+        })
+    }
+
+    pub(super) fn exists(&self, path: &str) -> Result<bool, Error> {
+        match relative_path(path) {
+            Ok(relative) => Ok(self.root.join(CONTENT_DIR).join(relative).is_file()),
+            Err(_) => Ok(false),
+        }
+    }
+
+    pub(super) fn get(&self, path: &str, max_size: usize) -> Result<Blob, Error> {
+        let relative = relative_path(path)?;
+
+        let mut file = match fs::File::open(self.root.join(CONTENT_DIR).join(&relative)) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                return Err(super::PathNotFoundError.into())
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        // Opening a directory succeeds on some platforms, so make sure we got an actual file.
+        let file_metadata = file.metadata()?;
+        if !file_metadata.is_file() {
+            return Err(super::PathNotFoundError.into());
+        }
+
+        // Check the size limit before reading the file, to avoid loading it altogether if the
+        // limit is exceeded.
+        if file_metadata.len() > max_size as u64 {
+            return Err(
+                io::Error::new(io::ErrorKind::Other, crate::error::SizeLimitReached).into(),
+            );
+        }
+
+        let mut content = crate::utils::sized_buffer::SizedBuffer::new(max_size);
+        content.reserve(file_metadata.len().try_into().unwrap_or(0));
+        io::copy(&mut file, &mut content)?;
+
+        let metadata: BlobMetadata =
+            serde_json::from_slice(&fs::read(self.root.join(METADATA_DIR).join(&relative))?)?;
+        let compression = metadata
+            .compression
+            .map(|i| {
+                i.try_into().map_err(|i| {
+                    failure::format_err!(
+                        "invalid compression algorithm {} stored in the local storage",
+                        i
+                    )
+                })
+            })
+            .transpose()?;
+
+        Ok(Blob {
+            path: path.into(),
+            mime: metadata.mime,
+            date_updated: metadata.date_updated,
+            content: content.into_inner(),
+            compression,
+        })
+    }
+
+    pub(super) fn start_storage_transaction(&self) -> Result<LocalStorageTransaction, Error> {
+        Ok(LocalStorageTransaction {
+            local: self,
+            staging: tempfile::Builder::new()
+                .prefix("transaction")
+                .tempdir_in(self.root.join(STAGING_DIR))?,
+            operations: Vec::new(),
+        })
+    }
+
+    pub(super) fn list_paths(
+        &self,
+        prefix: &str,
+        start_after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<String>, Error> {
+        let base = self.root.join(CONTENT_DIR);
+        let start = base.join(prefix_dir(prefix)?);
+        if !start.is_dir() {
+            return Ok(Vec::new());
+        }
+
+        if limit == 0 {
+            return Ok(Vec::new());
+        }
+
+        // Directories are sorted as if their name ended with a `/`, so that the walk returns the
+        // files in the same order as their full paths. This allows skipping the directories
+        // before `start_after` and stopping as soon as enough paths are found.
+        let walk = walkdir::WalkDir::new(&start)
+            .sort_by(|a, b| walk_sort_key(a).cmp(&walk_sort_key(b)))
+            .into_iter()
+            .filter_entry(|entry| {
+                if entry.depth() == 0 || !entry.file_type().is_dir() {
+                    return true;
+                }
+                let dir = match storage_path(&base, entry.path()) {
+                    Ok(dir) => format!("{}/", dir),
+                    Err(_) => return true,
+                };
+                let matches_prefix = dir.starts_with(prefix) || prefix.starts_with(&dir);
+                let after_start = start_after.map_or(true, |after| {
+                    dir.as_str() > after || after.starts_with(&dir)
+                });
+                matches_prefix && after_start
+            });
+
+        let mut paths = Vec::new();
+        for entry in walk {
+            let entry = entry?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+
+            let path = storage_path(&base, entry.path())?;
+            if path.starts_with(prefix) && start_after.map_or(true, |after| path.as_str() > after) {
+                paths.push(path);
+                if paths.len() >= limit {
+                    break;
+                }
+            }
+        }
+
+        Ok(paths)
+    }
+
+    pub(super) fn list_directories(&self, prefix: &str) -> Result<Vec<String>, Error> {
+        let start = self.root.join(CONTENT_DIR).join(prefix_dir(prefix)?);
+        if !start.is_dir() {
+            return Ok(Vec::new());
+        }
+
+        let mut directories = Vec::new();
+        for entry in fs::read_dir(&start)? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                directories.push(
+                    entry
+                        .file_name()
+                        .into_string()
+                        .map_err(|_| failure::err_msg("found a non UTF-8 path in the storage"))?,
+                );
+            }
+        }
+        Ok(directories)
+    }
+
+    fn delete_prefix(&self, prefix: &str) -> Result<(), Error> {
+        let prefix_dir = prefix_dir(prefix)?;
+
+        for dir in &[CONTENT_DIR, METADATA_DIR] {
+            let base = self.root.join(dir);
+            let start = base.join(&prefix_dir);
+            if !start.is_dir() {
+                continue;
+            }
+
+            // Directories are visited after their contents, so that directories left empty by the
+            // deletion can be removed as well.
+            for entry in walkdir::WalkDir::new(&start).contents_first(true) {
+                let entry = entry?;
+                if entry.path() == base.as_path() {
+                    continue;
+                }
+
+                let relative = storage_path(&base, entry.path())?;
+
+                if entry.file_type().is_dir() {
+                    if format!("{}/", relative).starts_with(prefix) {
+                        // Directories still containing files outside of the prefix fail to be
+                        // removed, which is fine.
+                        let _ = fs::remove_dir(entry.path());
+                    }
+                } else if relative.starts_with(prefix) {
+                    fs::remove_file(entry.path())?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    #[cfg(test)]
+    pub(super) fn cleanup_after_test(&self) -> Result<(), Error> {
+        if !self.temporary {
+            return Ok(());
+        }
+
+        if cfg!(not(test)) {
+            panic!("safeguard to prevent deleting the production storage");
+        }
+
+        fs::remove_dir_all(&self.root)?;
+        Ok(())
+    }
+}
+
+/// Converts a storage path into a path relative to the storage root, refusing any path that
+/// could escape it.
+fn relative_path(path: &str) -> Result<PathBuf, Error> {
+    let mut relative = PathBuf::new();
+    for component in Path::new(path).components() {
+        match component {
+            Component::Normal(segment) => relative.push(segment),
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => {
+                return Err(super::PathNotFoundError.into())
+            }
+        }
+    }
+    Ok(relative)
+}
+
+/// Returns the deepest directory containing all the paths matching the prefix, to avoid walking
+/// the whole storage when only a part of it is needed.
+fn prefix_dir(prefix: &str) -> Result<PathBuf, Error> {
+    match prefix.rfind('/') {
+        Some(idx) => relative_path(&prefix[..idx]),
+        None => Ok(PathBuf::new()),
+    }
+}
+
+fn walk_sort_key(entry: &walkdir::DirEntry) -> String {
+    let mut key = entry.file_name().to_string_lossy().into_owned();
+    if entry.file_type().is_dir() {
+        key.push('/');
+    }
+    key
+}
+
+/// Converts a path on disk back into the storage path it represents.
+fn storage_path(base: &Path, path: &Path) -> Result<String, Error> {
+    path.strip_prefix(base)?
+        .to_slash()
+        .ok_or_else(|| failure::err_msg("found a non UTF-8 path in the storage"))
+}
+
+enum Operation {
+    Store {
+        relative: PathBuf,
+        staged_content: PathBuf,
+        staged_metadata: PathBuf,
+    },
+    DeletePrefix(String),
+    DeletePath(PathBuf),
+}
+
+pub(super) struct LocalStorageTransaction<'a> {
+    local: &'a LocalBackend,
+    // Dropping the transaction without completing it removes all the staged files.
+    staging: TempDir,
+    operations: Vec<Operation>,
+}
+
+impl<'a> StorageTransaction for LocalStorageTransaction<'a> {
+    fn store_batch(&mut self, batch: Vec<Blob>) -> Result<(), Error> {
+        for blob in batch {
+            let relative = relative_path(&blob.path)?;
+
+            let id = self.operations.len();
+            let staged_content = self.staging.path().join(format!("{}.content", id));
+            let staged_metadata = self.staging.path().join(format!("{}.metadata", id));
+
+            fs::write(&staged_content, &blob.content)?;
+            let mut metadata_file = fs::File::create(&staged_metadata)?;
+            serde_json::to_writer(
+                &mut metadata_file,
+                &BlobMetadata {
+                    mime: blob.mime,
+                    date_updated: Utc::now(),
+                    compression: blob.compression.map(|alg| alg as i32),
+                },
+            )?;
+            metadata_file.flush()?;
+
+            self.operations.push(Operation::Store {
+                relative,
+                staged_content,
+                staged_metadata,
+            });
+            self.local.metrics.uploaded_files_total.inc();
+        }
+        Ok(())
+    }
+
+    fn delete_prefix(&mut self, prefix: &str) -> Result<(), Error> {
+        self.operations
+            .push(Operation::DeletePrefix(prefix.to_string()));
+        Ok(())
+    }
+
+    fn delete_paths(&mut self, paths: &[String]) -> Result<(), Error> {
+        for path in paths {
+            self.operations
+                .push(Operation::DeletePath(relative_path(path)?));
+        }
+        Ok(())
+    }
+
+    fn complete(self: Box<Self>) -> Result<(), Error> {
+        let LocalStorageTransaction {
+            local,
+            staging,
+            operations,
+        } = *self;
+
+        for operation in operations {
+            match operation {
+                Operation::Store {
+                    relative,
+                    staged_content,
+                    staged_metadata,
+                } => {
+                    let content = local.root.join(CONTENT_DIR).join(&relative);
+                    let metadata = local.root.join(METADATA_DIR).join(&relative);
+                    for dest in &[&content, &metadata] {
+                        if let Some(parent) = dest.parent() {
+                            fs::create_dir_all(parent)?;
+                        }
+                    }
+
+                    // The metadata is moved first, as a blob is only considered present once
+                    // its content is in place.
+                    fs::rename(staged_metadata, metadata)?;
+                    fs::rename(staged_content, content)?;
+                }
+                Operation::DeletePrefix(prefix) => local.delete_prefix(&prefix)?,
+                Operation::DeletePath(relative) => {
+                    // The content is removed first, as a blob is only considered present while
+                    // its content is in place.
+                    for dir in &[CONTENT_DIR, METADATA_DIR] {
+                        match fs::remove_file(local.root.join(dir).join(&relative)) {
+                            Err(err) if err.kind() != io::ErrorKind::NotFound => {
+                                return Err(err.into())
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+            }
+        }
+
+        staging.close()?;
+        Ok(())
+    }
+}
+
+// The tests for this module are in src/storage/mod.rs, as part of the backend tests. Please add
+// any test checking the public interface there.
@@ -0,0 +1,305 @@
+//! Copy the files stored in a storage backend to another one, for example to move the
+//! documentation stored in the database to S3 without rebuilding everything.
+//!
+//! The migration copies the files in batches, and records the last copied path in the `config`
+//! table after each batch. If the migration is interrupted, running it again with the same
+//! parameters resumes from the last completed batch.
+
+use super::{Storage, StorageKind};
+use crate::Context;
+use failure::{bail, Error};
+use postgres::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Name of the row in the `config` table storing the progress of the migration.
+const CHECKPOINT_NAME: &str = "storage_migration";
+
+pub struct MigrationOptions {
+    /// Only migrate the files whose path starts with this prefix.
+    pub prefix: String,
+    /// Number of files fetched from the source backend and stored at once.
+    pub batch_size: usize,
+    /// Fetch every copied file back from the destination and compare it with the source.
+    pub verify: bool,
+    /// Ignore the progress of a previous migration and start from the beginning.
+    pub restart: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Checkpoint {
+    from: String,
+    to: String,
+    prefix: String,
+    last_path: String,
+}
+
+/// Copy all the files from the `from` backend to the `to` backend, returning the number of
+/// copied files.
+///
+/// The compression and the mime type of the files are preserved.
+pub fn migrate_storage(
+    ctx: &dyn Context,
+    from: StorageKind,
+    to: StorageKind,
+    options: &MigrationOptions,
+) -> Result<usize, Error> {
+    if from == to {
+        bail!("the source and destination storage backends are the same");
+    }
+    if options.batch_size == 0 {
+        bail!("the batch size must be greater than zero");
+    }
+
+    let (pool, metrics, config) = (ctx.pool()?, ctx.metrics()?, ctx.config()?);
+    let source = Storage::new_with_kind(from, pool.clone(), metrics.clone(), &config)?;
+    let destination = Storage::new_with_kind(to, pool.clone(), metrics, &config)?;
+    let mut conn = pool.get()?;
+
+    let mut checkpoint = Checkpoint {
+        from: from.to_string(),
+        to: to.to_string(),
+        prefix: options.prefix.clone(),
+        last_path: String::new(),
+    };
+    if options.restart {
+        clear_checkpoint(&mut conn)?;
+    } else if let Some(previous) = load_checkpoint(&mut conn)? {
+        if previous.from == checkpoint.from
+            && previous.to == checkpoint.to
+            && previous.prefix == checkpoint.prefix
+        {
+            log::info!(
+                "resuming the storage migration after {}",
+                previous.last_path
+            );
+            checkpoint = previous;
+        } else {
+            bail!(
+                "an unfinished migration from {} to {} (prefix '{}') was found, \
+                 pass --restart to discard it",
+                previous.from,
+                previous.to,
+                previous.prefix,
+            );
+        }
+    }
+
+    let mut copied = 0;
+    loop {
+        let start_after = if checkpoint.last_path.is_empty() {
+            None
+        } else {
+            Some(checkpoint.last_path.as_str())
+        };
+        let paths = source.list_paths(&options.prefix, start_after, options.batch_size)?;
+        let last_path = match paths.last() {
+            Some(last) => last.clone(),
+            None => break,
+        };
+
+        let blobs = paths
+            .iter()
+            .map(|path| source.get_raw(path, std::usize::MAX))
+            .collect::<Result<Vec<_>, Error>>()?;
+        destination.store_inner(blobs.iter().cloned().map(Ok))?;
+
+        if options.verify {
+            for blob in &blobs {
+                let copy = destination.get_raw(&blob.path, std::usize::MAX)?;
+                if copy.content != blob.content
+                    || copy.mime != blob.mime
+                    || copy.compression != blob.compression
+                {
+                    bail!("verification of the copy of {} failed", blob.path);
+                }
+            }
+        }
+
+        copied += blobs.len();
+        checkpoint.last_path = last_path;
+        save_checkpoint(&mut conn, &checkpoint)?;
+        log::info!(
+            "copied {} files from {} to {}, last one was {}",
+            copied,
+            from,
+            to,
+            checkpoint.last_path
+        );
+    }
+
+    clear_checkpoint(&mut conn)?;
+    Ok(copied)
+}
+
+fn load_checkpoint(conn: &mut Client) -> Result<Option<Checkpoint>, Error> {
+    let rows = conn.query(
+        "SELECT value FROM config WHERE name = $1;",
+        &[&CHECKPOINT_NAME],
+    )?;
+    match rows.get(0) {
+        Some(row) => Ok(Some(serde_json::from_value(row.get::<_, Value>(0))?)),
+        None => Ok(None),
+    }
+}
+
+fn save_checkpoint(conn: &mut Client, checkpoint: &Checkpoint) -> Result<(), Error> {
+    conn.execute(
+        "INSERT INTO config (name, value) VALUES ($1, $2)
+         ON CONFLICT (name) DO UPDATE SET value = EXCLUDED.value;",
+        &[&CHECKPOINT_NAME, &serde_json::to_value(checkpoint)?],
+    )?;
+    Ok(())
+}
+
+fn clear_checkpoint(conn: &mut Client) -> Result<(), Error> {
+    conn.execute("DELETE FROM config WHERE name = $1;", &[&CHECKPOINT_NAME])?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::Blob;
+    use chrono::Utc;
+
+    fn blob(path: &str, content: &[u8]) -> Blob {
+        Blob {
+            path: path.into(),
+            mime: "text/rust".into(),
+            date_updated: Utc::now(),
+            content: content.to_vec(),
+            compression: None,
+        }
+    }
+
+    fn options(prefix: &str) -> MigrationOptions {
+        MigrationOptions {
+            prefix: prefix.into(),
+            batch_size: 2,
+            verify: true,
+            restart: false,
+        }
+    }
+
+    #[test]
+    fn test_migrate_storage() {
+        crate::test::wrapper(|env| {
+            let mut compressed = blob("rustdoc/foo/0.1.0/compressed.html", b"");
+            compressed.content = crate::storage::compress(
+                &b"compressed content"[..],
+                crate::storage::CompressionAlgorithm::Zstd,
+            )?;
+            compressed.compression = Some(crate::storage::CompressionAlgorithm::Zstd);
+
+            env.storage().store_blobs(vec![
+                compressed.clone(),
+                blob("rustdoc/foo/0.1.0/index.html", b"index"),
+                blob("rustdoc/foo/0.1.0/src/lib.rs.html", b"source"),
+                blob("sources/foo/0.1.0/src/lib.rs", b"other prefix"),
+            ])?;
+
+            let copied = migrate_storage(
+                env,
+                StorageKind::Database,
+                StorageKind::Local,
+                &options("rustdoc/"),
+            )?;
+            assert_eq!(copied, 3);
+
+            let local = Storage::new_with_kind(
+                StorageKind::Local,
+                env.db().pool(),
+                env.metrics(),
+                &env.config(),
+            )?;
+            let copy = local.get_raw("rustdoc/foo/0.1.0/compressed.html", std::usize::MAX)?;
+            assert_eq!(copy.content, compressed.content);
+            assert_eq!(copy.compression, compressed.compression);
+            assert_eq!(copy.mime, "text/rust");
+            assert_eq!(
+                local
+                    .get("rustdoc/foo/0.1.0/index.html", std::usize::MAX)?
+                    .content,
+                b"index"
+            );
+            assert!(!local.exists("sources/foo/0.1.0/src/lib.rs")?);
+
+            // A completed migration doesn't leave a checkpoint behind.
+            assert!(load_checkpoint(&mut env.db().conn())?.is_none());
+
+            local.cleanup_after_test()?;
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_migrate_storage_resume() {
+        crate::test::wrapper(|env| {
+            env.storage().store_blobs(vec![
+                blob("rustdoc/foo/a", b"a"),
+                blob("rustdoc/foo/b", b"b"),
+                blob("rustdoc/foo/c", b"c"),
+            ])?;
+
+            // Simulate a migration interrupted after copying the first file.
+            save_checkpoint(
+                &mut env.db().conn(),
+                &Checkpoint {
+                    from: "database".into(),
+                    to: "local".into(),
+                    prefix: "rustdoc/".into(),
+                    last_path: "rustdoc/foo/a".into(),
+                },
+            )?;
+
+            // A migration with different parameters refuses to overwrite the progress.
+            assert!(
+                migrate_storage(env, StorageKind::Database, StorageKind::Local, &options(""))
+                    .is_err()
+            );
+
+            let copied = migrate_storage(
+                env,
+                StorageKind::Database,
+                StorageKind::Local,
+                &options("rustdoc/"),
+            )?;
+            assert_eq!(copied, 2);
+
+            let local = Storage::new_with_kind(
+                StorageKind::Local,
+                env.db().pool(),
+                env.metrics(),
+                &env.config(),
+            )?;
+            assert!(!local.exists("rustdoc/foo/a")?);
+            assert!(local.exists("rustdoc/foo/b")?);
+            assert!(local.exists("rustdoc/foo/c")?);
+
+            // Restarting copies everything again.
+            let mut restart = options("rustdoc/");
+            restart.restart = true;
+            let copied = migrate_storage(env, StorageKind::Database, StorageKind::Local, &restart)?;
+            assert_eq!(copied, 3);
+            assert!(local.exists("rustdoc/foo/a")?);
+
+            local.cleanup_after_test()?;
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_migrate_storage_same_backend() {
+        crate::test::wrapper(|env| {
+            assert!(migrate_storage(
+                env,
+                StorageKind::Database,
+                StorageKind::Database,
+                &options("")
+            )
+            .is_err());
+            Ok(())
+        })
+    }
+}
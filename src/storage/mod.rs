@@ -1,14 +1,22 @@
 mod compression;
 mod database;
+mod dedup;
+mod gc;
+mod local;
+mod migration;
 mod s3;
 
 pub use self::compression::{compress, decompress, CompressionAlgorithm, CompressionAlgorithms};
 use self::database::DatabaseBackend;
+pub use self::gc::{gc_storage, GcReport};
+use self::local::LocalBackend;
+pub use self::migration::{migrate_storage, MigrationOptions};
 use self::s3::S3Backend;
 use crate::{db::Pool, Config, Metrics};
 use chrono::{DateTime, Utc};
 use failure::{err_msg, Error};
 use path_slash::PathExt;
+use postgres::Transaction;
 use std::{
     collections::{HashMap, HashSet},
     ffi::OsStr,
@@ -69,12 +77,13 @@ pub fn get_file_list<P: AsRef<Path>>(path: P) -> Result<Vec<PathBuf>, Error> {
 
 #[derive(Debug, failure::Fail)]
 #[fail(display = "invalid storage backend")]
-pub(crate) struct InvalidStorageBackendError;
+pub struct InvalidStorageBackendError;
 
-#[derive(Debug)]
-pub(crate) enum StorageKind {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageKind {
     Database,
     S3,
+    Local,
 }
 
 impl std::str::FromStr for StorageKind {
@@ -84,44 +93,88 @@ impl std::str::FromStr for StorageKind {
         match input {
             "database" => Ok(StorageKind::Database),
             "s3" => Ok(StorageKind::S3),
+            "local" => Ok(StorageKind::Local),
             _ => Err(InvalidStorageBackendError),
         }
     }
 }
 
+impl fmt::Display for StorageKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            StorageKind::Database => "database",
+            StorageKind::S3 => "s3",
+            StorageKind::Local => "local",
+        })
+    }
+}
+
 enum StorageBackend {
     Database(DatabaseBackend),
     S3(Box<S3Backend>),
+    Local(LocalBackend),
 }
 
 pub struct Storage {
     backend: StorageBackend,
+    kind: StorageKind,
+    /// Connections to the database storing the deduplication index.
+    pool: Pool,
+    metrics: Arc<Metrics>,
 }
 
 impl Storage {
     pub fn new(pool: Pool, metrics: Arc<Metrics>, config: &Config) -> Result<Self, Error> {
+        Self::new_with_kind(config.storage_backend, pool, metrics, config)
+    }
+
+    /// Create a storage using a backend other than the configured one. The rest of the
+    /// configuration is still used to connect to the backend.
+    pub fn new_with_kind(
+        kind: StorageKind,
+        pool: Pool,
+        metrics: Arc<Metrics>,
+        config: &Config,
+    ) -> Result<Self, Error> {
         Ok(Storage {
-            backend: match config.storage_backend {
+            backend: match kind {
                 StorageKind::Database => {
-                    StorageBackend::Database(DatabaseBackend::new(pool, metrics))
+                    StorageBackend::Database(DatabaseBackend::new(pool.clone(), metrics.clone()))
+                }
+                StorageKind::S3 => {
+                    StorageBackend::S3(Box::new(S3Backend::new(metrics.clone(), config)?))
+                }
+                StorageKind::Local => {
+                    StorageBackend::Local(LocalBackend::new(metrics.clone(), config)?)
                 }
-                StorageKind::S3 => StorageBackend::S3(Box::new(S3Backend::new(metrics, config)?)),
             },
+            kind,
+            pool,
+            metrics,
         })
     }
 
+    /// Name of the backend in the deduplication index.
+    fn index_name(&self) -> String {
+        self.kind.to_string()
+    }
+
     pub(crate) fn exists(&self, path: &str) -> Result<bool, Error> {
-        match &self.backend {
+        let stored = match &self.backend {
             StorageBackend::Database(db) => db.exists(path),
             StorageBackend::S3(s3) => s3.exists(path),
+            StorageBackend::Local(local) => local.exists(path),
+        }?;
+        if stored {
+            return Ok(true);
         }
+
+        let indexed = dedup::lookup(&mut *self.pool.get()?, &self.index_name(), path)?;
+        Ok(indexed.is_some())
     }
 
     pub(crate) fn get(&self, path: &str, max_size: usize) -> Result<Blob, Error> {
-        let mut blob = match &self.backend {
-            StorageBackend::Database(db) => db.get(path, max_size),
-            StorageBackend::S3(s3) => s3.get(path, max_size),
-        }?;
+        let mut blob = self.get_raw(path, max_size)?;
         if let Some(alg) = blob.compression {
             blob.content = decompress(blob.content.as_slice(), alg, max_size)?;
             blob.compression = None;
@@ -129,17 +182,132 @@ impl Storage {
         Ok(blob)
     }
 
-    fn transaction<T, F>(&self, f: F) -> Result<T, Error>
+    /// Fetch a blob without decompressing its content. The index is only looked up when the path
+    /// isn't stored in the backend, as the paths are never stored in both.
+    fn get_raw(&self, path: &str, max_size: usize) -> Result<Blob, Error> {
+        match self.get_from_backend(path, max_size) {
+            Err(err) if err.downcast_ref::<PathNotFoundError>().is_some() => {}
+            res => return res,
+        }
+
+        let indexed = dedup::lookup(&mut *self.pool.get()?, &self.index_name(), path)?;
+        let (hash, mime) = indexed.ok_or(PathNotFoundError)?;
+        let blob = self.get_from_backend(&dedup::blob_path(&hash), max_size)?;
+        Ok(Blob {
+            path: path.into(),
+            mime,
+            ..blob
+        })
+    }
+
+    fn get_from_backend(&self, path: &str, max_size: usize) -> Result<Blob, Error> {
+        match &self.backend {
+            StorageBackend::Database(db) => db.get(path, max_size),
+            StorageBackend::S3(s3) => s3.get(path, max_size),
+            StorageBackend::Local(local) => local.get(path, max_size),
+        }
+    }
+
+    /// List at most `limit` paths starting with `prefix`. The paths are returned in a stable
+    /// order, and only the ones sorting after `start_after` are included, allowing to paginate
+    /// through the whole listing.
+    pub(crate) fn list_paths(
+        &self,
+        prefix: &str,
+        start_after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<String>, Error> {
+        let mut paths = self.list_backend_paths(prefix, start_after, limit)?;
+        paths.extend(dedup::list_paths(
+            &mut *self.pool.get()?,
+            &self.index_name(),
+            prefix,
+            start_after,
+            limit,
+        )?);
+
+        // Both listings are sorted by bytes, so the first `limit` paths of the merged listing
+        // are all included.
+        paths.sort();
+        paths.dedup();
+        paths.truncate(limit);
+        Ok(paths)
+    }
+
+    /// List the paths stored in the backend, skipping the deduplicated blobs.
+    fn list_backend_paths(
+        &self,
+        prefix: &str,
+        start_after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<String>, Error> {
+        let mut paths = Vec::new();
+        let mut start_after = start_after.map(String::from);
+        while paths.len() < limit {
+            let remaining = limit - paths.len();
+            let page = match &self.backend {
+                StorageBackend::Database(db) => {
+                    db.list_paths(prefix, start_after.as_deref(), remaining)
+                }
+                StorageBackend::S3(s3) => s3.list_paths(prefix, start_after.as_deref(), remaining),
+                StorageBackend::Local(local) => {
+                    local.list_paths(prefix, start_after.as_deref(), remaining)
+                }
+            }?;
+
+            // The blobs are listed next to each other, so the listing can resume after them.
+            match page
+                .iter()
+                .position(|path| path.starts_with(dedup::BLOBS_PREFIX))
+            {
+                Some(idx) => {
+                    paths.extend(page.into_iter().take(idx));
+                    start_after = Some(dedup::AFTER_BLOBS.into());
+                }
+                None => {
+                    let complete = page.len() < remaining;
+                    paths.extend(page);
+                    if complete {
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(paths)
+    }
+
+    /// List the names of the directories directly inside `prefix`, which must end with a `/`.
+    /// For example listing `rustdoc/foo/` returns the versions of `foo` with documentation.
+    pub(crate) fn list_directories(&self, prefix: &str) -> Result<Vec<String>, Error> {
+        let mut directories = match &self.backend {
+            StorageBackend::Database(db) => db.list_directories(prefix),
+            StorageBackend::S3(s3) => s3.list_directories(prefix),
+            StorageBackend::Local(local) => local.list_directories(prefix),
+        }?;
+        if prefix.is_empty() {
+            directories.retain(|dir| dedup::BLOBS_PREFIX.trim_end_matches('/') != dir);
+        }
+        directories.extend(dedup::list_directories(
+            &mut *self.pool.get()?,
+            &self.index_name(),
+            prefix,
+        )?);
+
+        directories.sort();
+        directories.dedup();
+        Ok(directories)
+    }
+
+    /// Run `f` in a transaction of the backend. The database backend runs it in a transaction
+    /// nested in `index`, instead of taking another connection.
+    fn transaction<T, F>(&self, index: &mut Transaction<'_>, f: F) -> Result<T, Error>
     where
         F: FnOnce(&mut dyn StorageTransaction) -> Result<T, Error>,
     {
-        let mut conn;
         let mut trans: Box<dyn StorageTransaction> = match &self.backend {
-            StorageBackend::Database(db) => {
-                conn = db.start_connection()?;
-                Box::new(conn.start_storage_transaction()?)
-            }
+            StorageBackend::Database(db) => Box::new(db.start_storage_transaction(index)?),
             StorageBackend::S3(s3) => Box::new(s3.start_storage_transaction()?),
+            StorageBackend::Local(local) => Box::new(local.start_storage_transaction()?),
         };
 
         let res = f(trans.as_mut())?;
@@ -149,8 +317,8 @@ impl Storage {
 
     // Store all files in `root_dir` into the backend under `prefix`.
     //
-    // If the environment is configured with S3 credentials, this will upload to S3;
-    // otherwise, this will store files in the database.
+    // Depending on the configured storage backend, this will upload to S3, store files in the
+    // database or write them to the local filesystem.
     //
     // This returns (map<filename, mime type>, set<compression algorithms>).
     pub(crate) fn store_all(
@@ -190,36 +358,149 @@ impl Storage {
                 })
             });
 
-        self.store_inner(blobs)?;
+        self.store_deduplicated(blobs)?;
         Ok((file_paths_and_mimes, algs))
     }
 
+    /// Store the blobs in the deduplication index, only uploading the content that isn't
+    /// already stored in the backend.
+    ///
+    /// Each batch is committed separately, so that the blobs shared with concurrent uploads are
+    /// only locked while a single batch is uploaded.
+    fn store_deduplicated(
+        &self,
+        mut blobs: impl Iterator<Item = Result<Blob, Error>>,
+    ) -> Result<(), Error> {
+        let index_name = self.index_name();
+        let mut conn = self.pool.get()?;
+        loop {
+            let batch: Vec<_> = blobs
+                .by_ref()
+                .take(MAX_CONCURRENT_UPLOADS)
+                .map(|blob| blob.map(|blob| (dedup::content_hash(&blob.content), blob)))
+                .collect::<Result<_, Error>>()?;
+            if batch.is_empty() {
+                break;
+            }
+
+            // The references are taken before uploading the new blobs, so that concurrent
+            // uploads of the same content wait for the upload to complete.
+            let mut index = conn.transaction()?;
+            let references: Vec<_> = batch
+                .iter()
+                .map(|(hash, blob)| dedup::Reference {
+                    path: &blob.path,
+                    hash,
+                    mime: &blob.mime,
+                })
+                .collect();
+            let dedup::AddedReferences {
+                mut new_blobs,
+                replaced,
+                new_paths,
+            } = dedup::add_references(&mut index, &index_name, &references)?;
+
+            let mut uploads = Vec::new();
+            for (hash, blob) in batch {
+                if new_blobs.remove(&hash) {
+                    uploads.push(Blob {
+                        path: dedup::blob_path(&hash),
+                        ..blob
+                    });
+                } else {
+                    self.metrics.deduplicated_files_total.inc();
+                    self.metrics
+                        .deduplicated_bytes_total
+                        .inc_by(blob.content.len() as i64);
+                }
+            }
+            let unreferenced = dedup::remove_unreferenced(&mut index, &index_name, &replaced)?;
+
+            self.transaction(&mut index, |trans| {
+                if !uploads.is_empty() {
+                    trans.store_batch(uploads)?;
+                }
+                for hash in &unreferenced {
+                    trans.delete_prefix(&dedup::blob_path(hash))?;
+                }
+                Ok(())
+            })?;
+            index.commit()?;
+
+            // The backend is read before the index, so the copies stored before the paths were
+            // deduplicated would take precedence. They're deleted once the index is committed,
+            // so that the paths can always be found.
+            if !new_paths.is_empty() {
+                let mut cleanup = conn.transaction()?;
+                self.transaction(&mut cleanup, |trans| trans.delete_paths(&new_paths))?;
+                cleanup.commit()?;
+            }
+        }
+        Ok(())
+    }
+
     #[cfg(test)]
     pub(crate) fn store_blobs(&self, blobs: Vec<Blob>) -> Result<(), Error> {
         self.store_inner(blobs.into_iter().map(Ok))
     }
 
+    /// Store the blobs at their path in the backend, bypassing the deduplication.
     fn store_inner(
         &self,
         mut blobs: impl Iterator<Item = Result<Blob, Error>>,
     ) -> Result<(), Error> {
-        self.transaction(|trans| {
-            loop {
-                let batch: Vec<_> = blobs
-                    .by_ref()
-                    .take(MAX_CONCURRENT_UPLOADS)
-                    .collect::<Result<_, Error>>()?;
-                if batch.is_empty() {
-                    break;
-                }
-                trans.store_batch(batch)?;
+        let index_name = self.index_name();
+        let mut conn = self.pool.get()?;
+        loop {
+            let batch: Vec<_> = blobs
+                .by_ref()
+                .take(MAX_CONCURRENT_UPLOADS)
+                .collect::<Result<_, Error>>()?;
+            if batch.is_empty() {
+                break;
             }
-            Ok(())
-        })
+
+            // A path is never both in the index and in the backend.
+            let paths = batch
+                .iter()
+                .map(|blob| blob.path.clone())
+                .collect::<Vec<_>>();
+            let mut index = conn.transaction()?;
+            let removed = dedup::remove_paths(&mut index, &index_name, &paths)?;
+            let unreferenced = dedup::remove_unreferenced(&mut index, &index_name, &removed)?;
+
+            self.transaction(&mut index, |trans| {
+                trans.store_batch(batch)?;
+                for hash in &unreferenced {
+                    trans.delete_prefix(&dedup::blob_path(hash))?;
+                }
+                Ok(())
+            })?;
+            index.commit()?;
+        }
+        Ok(())
     }
 
+    /// Delete all the paths starting with `prefix`. The deduplicated content is only deleted
+    /// from the backend once no other path references it.
     pub(crate) fn delete_prefix(&self, prefix: &str) -> Result<(), Error> {
-        self.transaction(|trans| trans.delete_prefix(prefix))
+        let index_name = self.index_name();
+        let mut conn = self.pool.get()?;
+        let mut index = conn.transaction()?;
+        let removed = dedup::remove_prefix(&mut index, &index_name, prefix)?;
+        let unreferenced = dedup::remove_unreferenced(&mut index, &index_name, &removed)?;
+
+        // The blobs are deleted before committing, so that concurrent uploads of the same
+        // content wait for the deletion to complete before storing it again.
+        self.transaction(&mut index, |trans| {
+            trans.delete_prefix(prefix)?;
+            for hash in &unreferenced {
+                trans.delete_prefix(&dedup::blob_path(hash))?;
+            }
+            Ok(())
+        })?;
+        index.commit()?;
+        Ok(())
     }
 
     // We're using `&self` instead of consuming `self` or creating a Drop impl because during tests
@@ -227,8 +508,10 @@ impl Storage {
     // still holds a reference to the storage).
     #[cfg(test)]
     pub(crate) fn cleanup_after_test(&self) -> Result<(), Error> {
-        if let StorageBackend::S3(s3) = &self.backend {
-            s3.cleanup_after_test()?;
+        match &self.backend {
+            StorageBackend::S3(s3) => s3.cleanup_after_test()?,
+            StorageBackend::Local(local) => local.cleanup_after_test()?,
+            StorageBackend::Database(_) => {}
         }
         Ok(())
     }
@@ -239,6 +522,7 @@ impl std::fmt::Debug for Storage {
         match &self.backend {
             StorageBackend::Database(_) => write!(f, "database-backed storage"),
             StorageBackend::S3(_) => write!(f, "S3-backed storage"),
+            StorageBackend::Local(_) => write!(f, "local filesystem-backed storage"),
         }
     }
 }
@@ -246,6 +530,8 @@ impl std::fmt::Debug for Storage {
 trait StorageTransaction {
     fn store_batch(&mut self, batch: Vec<Blob>) -> Result<(), Error>;
     fn delete_prefix(&mut self, prefix: &str) -> Result<(), Error>;
+    /// Delete the files stored at exactly these paths, if any.
+    fn delete_paths(&mut self, paths: &[String]) -> Result<(), Error>;
     fn complete(self: Box<Self>) -> Result<(), Error>;
 }
 
@@ -303,6 +589,52 @@ mod test {
         check_mime("important.svg", "image/svg+xml");
     }
 
+    #[test]
+    fn test_concurrent_store_all() {
+        crate::test::wrapper(|env| {
+            // The local backend only needs a database connection for the index, leaving enough
+            // connections in the test pool for both uploads.
+            env.override_config(|config| config.storage_backend = StorageKind::Local);
+            let storage = env.storage();
+
+            // The shared content spans multiple batches, stored in a different order by each
+            // upload as the directories are listed in an arbitrary order.
+            let files = MAX_CONCURRENT_UPLOADS + 10;
+            let mut uploads = Vec::new();
+            for prefix in &["first", "second"] {
+                let dir = tempfile::Builder::new()
+                    .prefix("docs.rs-upload-test")
+                    .tempdir()?;
+                for i in 0..files {
+                    std::fs::write(dir.path().join(format!("{}.txt", i)), i.to_string())?;
+                }
+
+                let storage = storage.clone();
+                uploads.push(std::thread::spawn(move || {
+                    storage.store_all(prefix, dir.path()).map(|_| ())
+                }));
+            }
+            for upload in uploads {
+                upload.join().expect("the upload panicked")?;
+            }
+
+            let row = env.db().conn().query_one(
+                "SELECT COUNT(*), MIN(refcount), MAX(refcount) FROM storage_blobs;",
+                &[],
+            )?;
+            assert_eq!(row.get::<_, i64>(0), files as i64);
+            assert_eq!(row.get::<_, i32>(1), 2);
+            assert_eq!(row.get::<_, i32>(2), 2);
+
+            for prefix in &["first", "second"] {
+                let file = storage.get(&format!("{}/42.txt", prefix), std::usize::MAX)?;
+                assert_eq!(file.content, b"42");
+            }
+
+            Ok(())
+        });
+    }
+
     fn check_mime(path: &str, expected_mime: &str) {
         let detected_mime = detect_mime(Path::new(&path));
         let detected_mime = detected_mime.expect("no mime was given");
@@ -472,7 +804,102 @@ mod backend_tests {
         expected_algs.insert(CompressionAlgorithm::default());
         assert_eq!(algs, expected_algs);
 
-        assert_eq!(2, metrics.uploaded_files_total.get());
+        // Both files have the same content, which is only uploaded once.
+        assert_eq!(1, metrics.uploaded_files_total.get());
+        assert_eq!(1, metrics.deduplicated_files_total.get());
+
+        Ok(())
+    }
+
+    fn test_store_all_deduplication(storage: &Storage, metrics: &Metrics) -> Result<(), Error> {
+        let dir = tempfile::Builder::new()
+            .prefix("docs.rs-upload-test")
+            .tempdir()?;
+        fs::write(dir.path().join("shared.rs"), "shared")?;
+        fs::write(dir.path().join("changed.rs"), "first")?;
+        storage.store_all("first", dir.path())?;
+        fs::write(dir.path().join("changed.rs"), "second")?;
+        storage.store_all("second", dir.path())?;
+
+        assert_eq!(3, metrics.uploaded_files_total.get());
+        assert_eq!(1, metrics.deduplicated_files_total.get());
+        assert!(metrics.deduplicated_bytes_total.get() > 0);
+
+        let hashes = |storage: &Storage| -> Result<Vec<String>, Error> {
+            Ok(storage
+                .pool
+                .get()?
+                .query("SELECT hash FROM storage_blobs;", &[])?
+                .into_iter()
+                .map(|row| row.get(0))
+                .collect())
+        };
+        let stored_hashes = hashes(storage)?;
+        assert_eq!(stored_hashes.len(), 3);
+
+        // The blobs are not visible outside of the storage.
+        assert_eq!(
+            storage.list_paths("", None, std::usize::MAX)?,
+            vec![
+                "first/changed.rs",
+                "first/shared.rs",
+                "second/changed.rs",
+                "second/shared.rs"
+            ]
+        );
+        assert_eq!(storage.list_paths("", None, 1)?, vec!["first/changed.rs"]);
+        assert_eq!(storage.list_directories("")?, vec!["first", "second"]);
+
+        let file = storage.get("second/shared.rs", std::usize::MAX)?;
+        assert_eq!(file.path, "second/shared.rs");
+        assert_eq!(file.mime, "text/rust");
+        assert_eq!(file.content, b"shared");
+
+        // The shared content is kept until no path references it anymore.
+        storage.delete_prefix("first/")?;
+        assert!(!storage.exists("first/shared.rs")?);
+        assert_eq!(
+            storage.get("second/shared.rs", std::usize::MAX)?.content,
+            b"shared"
+        );
+        assert_eq!(hashes(storage)?.len(), 2);
+
+        storage.delete_prefix("second/")?;
+        assert!(hashes(storage)?.is_empty());
+        for hash in &stored_hashes {
+            assert!(storage
+                .get_from_backend(&dedup::blob_path(hash), std::usize::MAX)
+                .unwrap_err()
+                .downcast_ref::<PathNotFoundError>()
+                .is_some());
+        }
+
+        Ok(())
+    }
+
+    fn test_store_all_replaces_stored_blob(storage: &Storage) -> Result<(), Error> {
+        storage.store_blobs(vec![Blob {
+            path: "prefix/lib.rs".into(),
+            mime: "text/rust".into(),
+            date_updated: Utc::now(),
+            compression: None,
+            content: b"stored".to_vec(),
+        }])?;
+
+        let dir = tempfile::Builder::new()
+            .prefix("docs.rs-upload-test")
+            .tempdir()?;
+        fs::write(dir.path().join("lib.rs"), "deduplicated")?;
+        storage.store_all("prefix", dir.path())?;
+
+        // The copy stored before the path was deduplicated doesn't shadow the new content.
+        let file = storage.get("prefix/lib.rs", std::usize::MAX)?;
+        assert_eq!(file.content, b"deduplicated");
+        assert!(storage
+            .get_from_backend("prefix/lib.rs", std::usize::MAX)
+            .unwrap_err()
+            .downcast_ref::<PathNotFoundError>()
+            .is_some());
 
         Ok(())
     }
@@ -502,6 +929,106 @@ mod backend_tests {
         Ok(())
     }
 
+    fn test_list_paths(storage: &Storage) -> Result<(), Error> {
+        const PATHS: &[&str] = &["foo/a.txt", "foo/b/c.txt", "foo/d.txt", "x.txt"];
+        storage.store_blobs(
+            PATHS
+                .iter()
+                .map(|path| Blob {
+                    path: (*path).to_string(),
+                    content: b"foo\n".to_vec(),
+                    compression: None,
+                    mime: "text/plain".into(),
+                    date_updated: Utc::now(),
+                })
+                .collect(),
+        )?;
+
+        assert_eq!(storage.list_paths("", None, std::usize::MAX)?, PATHS);
+        assert_eq!(
+            storage.list_paths("foo/", None, std::usize::MAX)?,
+            &PATHS[..3]
+        );
+
+        // Paginate through the listing.
+        assert_eq!(storage.list_paths("foo/", None, 2)?, &PATHS[..2]);
+        assert_eq!(
+            storage.list_paths("foo/", Some("foo/b/c.txt"), 2)?,
+            &PATHS[2..3]
+        );
+        assert!(storage.list_paths("foo/", Some("foo/d.txt"), 2)?.is_empty());
+
+        Ok(())
+    }
+
+    fn test_list_paths_order(storage: &Storage) -> Result<(), Error> {
+        // Sorted by bytes, `foo/b/` comes between `foo/b.txt` and `foo/b0.txt`.
+        const PATHS: &[&str] = &["foo/b.txt", "foo/b/c.txt", "foo/b0.txt", "foo_bar/d.txt"];
+        storage.store_blobs(
+            PATHS
+                .iter()
+                .chain(&["fooxbar/e.txt"])
+                .map(|path| Blob {
+                    path: (*path).to_string(),
+                    content: b"foo\n".to_vec(),
+                    compression: None,
+                    mime: "text/plain".into(),
+                    date_updated: Utc::now(),
+                })
+                .collect(),
+        )?;
+
+        assert_eq!(
+            storage.list_paths("foo/", None, std::usize::MAX)?,
+            &PATHS[..3]
+        );
+        let mut listed = Vec::new();
+        let mut start_after: Option<String> = None;
+        while let Some(path) = storage.list_paths("foo/", start_after.as_deref(), 1)?.pop() {
+            listed.push(path.clone());
+            start_after = Some(path);
+        }
+        assert_eq!(listed, &PATHS[..3]);
+
+        // Wildcards in the prefix are matched literally.
+        assert_eq!(
+            storage.list_paths("foo_", None, std::usize::MAX)?,
+            &PATHS[3..]
+        );
+
+        Ok(())
+    }
+
+    fn test_list_directories(storage: &Storage) -> Result<(), Error> {
+        const PATHS: &[&str] = &[
+            "foo/b/1.txt",
+            "foo/a/2.txt",
+            "foo/a/b/3.txt",
+            "foo/c.txt",
+            "foobar/d/4.txt",
+        ];
+        storage.store_blobs(
+            PATHS
+                .iter()
+                .map(|path| Blob {
+                    path: (*path).to_string(),
+                    content: b"foo\n".to_vec(),
+                    compression: None,
+                    mime: "text/plain".into(),
+                    date_updated: Utc::now(),
+                })
+                .collect(),
+        )?;
+
+        assert_eq!(storage.list_directories("")?, vec!["foo", "foobar"]);
+        assert_eq!(storage.list_directories("foo/")?, vec!["a", "b"]);
+        assert_eq!(storage.list_directories("foo/a/")?, vec!["b"]);
+        assert!(storage.list_directories("foo/b/")?.is_empty());
+        assert!(storage.list_directories("missing/")?.is_empty());
+
+        Ok(())
+    }
+
     fn test_delete_prefix(storage: &Storage) -> Result<(), Error> {
         test_deletion(
             storage,
@@ -618,6 +1145,7 @@ mod backend_tests {
         backends {
             s3 => StorageKind::S3,
             database => StorageKind::Database,
+            local => StorageKind::Local,
         }
 
         tests {
@@ -625,13 +1153,18 @@ mod backend_tests {
             test_exists,
             test_get_object,
             test_get_too_big,
+            test_list_paths,
+            test_list_paths_order,
+            test_list_directories,
             test_delete_prefix,
             test_delete_percent,
+            test_store_all_replaces_stored_blob,
         }
 
         tests_with_metrics {
             test_store_blobs,
             test_store_all,
+            test_store_all_deduplication,
         }
     }
 }
@@ -134,6 +134,83 @@ impl S3Backend {
         })
     }
 
+    pub(super) fn list_paths(
+        &self,
+        prefix: &str,
+        start_after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<String>, Error> {
+        self.runtime.handle().block_on(async {
+            let mut paths = Vec::new();
+            let mut continuation_token = None;
+            while paths.len() < limit {
+                let list = self
+                    .client
+                    .list_objects_v2(ListObjectsV2Request {
+                        bucket: self.bucket.clone(),
+                        prefix: Some(prefix.into()),
+                        start_after: start_after.map(Into::into),
+                        continuation_token,
+                        // S3 never returns more than 1000 keys per request anyway.
+                        max_keys: Some((limit - paths.len()).min(1000) as i64),
+                        ..ListObjectsV2Request::default()
+                    })
+                    .await?;
+
+                paths.extend(
+                    list.contents
+                        .unwrap_or_else(Vec::new)
+                        .into_iter()
+                        .filter_map(|o| o.key),
+                );
+
+                continuation_token = list.next_continuation_token;
+                if continuation_token.is_none() {
+                    break;
+                }
+            }
+            Ok(paths)
+        })
+    }
+
+    pub(super) fn list_directories(&self, prefix: &str) -> Result<Vec<String>, Error> {
+        self.runtime.handle().block_on(async {
+            let mut directories = Vec::new();
+            let mut continuation_token = None;
+            loop {
+                let list = self
+                    .client
+                    .list_objects_v2(ListObjectsV2Request {
+                        bucket: self.bucket.clone(),
+                        prefix: Some(prefix.into()),
+                        delimiter: Some("/".into()),
+                        continuation_token,
+                        ..ListObjectsV2Request::default()
+                    })
+                    .await?;
+
+                // The keys containing the delimiter after the prefix are grouped in common
+                // prefixes, like `rustdoc/foo/1.0.0/` when listing `rustdoc/foo/`.
+                directories.extend(
+                    list.common_prefixes
+                        .unwrap_or_else(Vec::new)
+                        .into_iter()
+                        .filter_map(|common| common.prefix)
+                        .filter_map(|common| {
+                            common
+                                .strip_prefix(prefix)
+                                .map(|dir| dir.trim_end_matches('/').to_string())
+                        }),
+                );
+
+                continuation_token = list.next_continuation_token;
+                if continuation_token.is_none() {
+                    return Ok(directories);
+                }
+            }
+        })
+    }
+
     pub(super) fn start_storage_transaction(&self) -> Result<S3StorageTransaction, Error> {
         Ok(S3StorageTransaction { s3: self })
     }
@@ -270,6 +347,41 @@ impl<'a> StorageTransaction for S3StorageTransaction<'a> {
         })
     }
 
+    fn delete_paths(&mut self, paths: &[String]) -> Result<(), Error> {
+        self.s3.runtime.handle().block_on(async {
+            // A single request can delete at most 1000 objects.
+            for chunk in paths.chunks(1000) {
+                let resp = self
+                    .s3
+                    .client
+                    .delete_objects(DeleteObjectsRequest {
+                        bucket: self.s3.bucket.clone(),
+                        delete: rusoto_s3::Delete {
+                            objects: chunk
+                                .iter()
+                                .map(|path| ObjectIdentifier {
+                                    key: path.clone(),
+                                    version_id: None,
+                                })
+                                .collect(),
+                            quiet: None,
+                        },
+                        ..DeleteObjectsRequest::default()
+                    })
+                    .await?;
+
+                if let Some(errs) = resp.errors {
+                    for err in &errs {
+                        log::error!("error deleting file from s3: {:?}", err);
+                    }
+
+                    failure::bail!("deleting from s3 failed");
+                }
+            }
+            Ok(())
+        })
+    }
+
     fn complete(self: Box<Self>) -> Result<(), Error> {
         Ok(())
     }
@@ -1,11 +1,12 @@
 use super::TestDatabase;
-use crate::docbuilder::{BuildResult, DocCoverage};
+use crate::docbuilder::{BuildFailureReason, BuildResult, BuildStats, DocCoverage};
 use crate::index::api::{CrateData, CrateOwner, ReleaseData};
 use crate::storage::Storage;
 use crate::utils::{Dependency, MetadataPackage, Target};
 use chrono::{DateTime, Utc};
 use failure::Error;
 use std::sync::Arc;
+use std::time::Duration;
 
 #[must_use = "FakeRelease does nothing until you call .create()"]
 pub(crate) struct FakeRelease<'a> {
@@ -19,6 +20,8 @@ pub(crate) struct FakeRelease<'a> {
     rustdoc_files: Vec<(&'a str, &'a [u8])>,
     doc_targets: Vec<String>,
     default_target: Option<&'a str>,
+    /// target, whether the build was successful
+    other_build_targets: Vec<(&'a str, bool)>,
     registry_crate_data: CrateData,
     registry_release_data: ReleaseData,
     has_docs: bool,
@@ -59,12 +62,17 @@ impl<'a> FakeRelease<'a> {
                 docsrs_version: "docs.rs 1.0.0 (000000000 1970-01-01)".into(),
                 build_log: "It works!".into(),
                 successful: true,
+                failure_reason: None,
                 doc_coverage: None,
+                duration: Duration::from_secs(10),
+                documentation_size: Some(1024),
+                peak_memory: Some(256 * 1024 * 1024),
             },
             source_files: Vec::new(),
             rustdoc_files: Vec::new(),
             doc_targets: Vec::new(),
             default_target: None,
+            other_build_targets: Vec::new(),
             registry_crate_data: CrateData { owners: Vec::new() },
             registry_release_data: ReleaseData {
                 release_time: Utc::now(),
@@ -117,6 +125,22 @@ impl<'a> FakeRelease<'a> {
     pub(crate) fn build_result_successful(mut self, new: bool) -> Self {
         self.has_docs = new;
         self.build_result.successful = new;
+        if !new {
+            self.build_result.documentation_size = None;
+        }
+        self
+    }
+
+    pub(crate) fn failure_reason(mut self, new: BuildFailureReason) -> Self {
+        self.has_docs = false;
+        self.build_result.successful = false;
+        self.build_result.failure_reason = Some(new);
+        self.build_result.documentation_size = None;
+        self
+    }
+
+    pub(crate) fn rustc_version(mut self, new: &str) -> Self {
+        self.build_result.rustc_version = new.into();
         self
     }
 
@@ -153,6 +177,12 @@ impl<'a> FakeRelease<'a> {
         self
     }
 
+    /// Record the build of a target other than the default one, without adding any documentation
+    pub(crate) fn add_build_target(mut self, target: &'a str, successful: bool) -> Self {
+        self.other_build_targets.push((target, successful));
+        self
+    }
+
     pub(crate) fn binary(mut self, bin: bool) -> Self {
         self.has_docs = !bin;
         if bin {
@@ -263,12 +293,13 @@ impl<'a> FakeRelease<'a> {
         if let Some(markdown) = self.readme {
             fs::write(crate_dir.join("README.md"), markdown)?;
         }
+        let default_target = self.default_target.unwrap_or("x86_64-unknown-linux-gnu");
         let release_id = crate::db::add_package_into_database(
             &mut db.conn(),
             &package,
             crate_dir,
             &self.build_result,
-            self.default_target.unwrap_or("x86_64-unknown-linux-gnu"),
+            default_target,
             source_meta,
             self.doc_targets,
             &self.registry_release_data,
@@ -281,7 +312,41 @@ impl<'a> FakeRelease<'a> {
             &package.name,
             &self.registry_crate_data,
         )?;
-        crate::db::add_build_into_database(&mut db.conn(), release_id, &self.build_result)?;
+        let stats = BuildStats {
+            duration: self.build_result.duration,
+            documentation_size: self.build_result.documentation_size,
+            peak_memory: self.build_result.peak_memory,
+        };
+        let build_id = crate::db::add_build_into_database(
+            &mut db.conn(),
+            release_id,
+            &self.build_result,
+            &stats,
+        )?;
+        crate::db::add_build_target_into_database(
+            &mut db.conn(),
+            build_id,
+            default_target,
+            true,
+            &self.build_result,
+        )?;
+        for (target, successful) in self.other_build_targets {
+            let mut result = BuildResult {
+                successful,
+                ..self.build_result.clone()
+            };
+            if !successful {
+                result.failure_reason = Some(BuildFailureReason::CompileError);
+                result.documentation_size = None;
+            }
+            crate::db::add_build_target_into_database(
+                &mut db.conn(),
+                build_id,
+                target,
+                false,
+                &result,
+            )?;
+        }
         if let Some(coverage) = self.build_result.doc_coverage {
             crate::db::add_doc_coverage(&mut db.conn(), release_id, coverage)?;
         }
@@ -10,6 +10,7 @@ use once_cell::unsync::OnceCell;
 use postgres::Client as Connection;
 use reqwest::{
     blocking::{Client, RequestBuilder},
+    redirect::Policy,
     Method,
 };
 use std::{panic, sync::Arc};
@@ -147,6 +148,11 @@ impl TestEnvironment {
         config.s3_bucket = format!("docsrs-test-bucket-{}", rand::random::<u64>());
         config.s3_bucket_is_temporary = true;
 
+        // Use a temporary directory for the local storage.
+        config.local_storage_path =
+            std::env::temp_dir().join(format!("docsrs-test-storage-{}", rand::random::<u64>()));
+        config.local_storage_is_temporary = true;
+
         config
     }
 
@@ -319,6 +325,7 @@ impl Drop for TestDatabase {
 pub(crate) struct TestFrontend {
     server: Server,
     client: Client,
+    client_no_redirects: Client,
 }
 
 impl TestFrontend {
@@ -327,15 +334,28 @@ impl TestFrontend {
             server: Server::start(Some("127.0.0.1:0"), false, context)
                 .expect("failed to start the web server"),
             client: Client::new(),
+            client_no_redirects: Client::builder()
+                .redirect(Policy::none())
+                .build()
+                .expect("failed to build the http client"),
         }
     }
 
-    fn build_request(&self, method: Method, url: &str) -> RequestBuilder {
-        self.client
-            .request(method, &format!("http://{}{}", self.server.addr(), url))
+    fn build_request(&self, client: &Client, method: Method, url: &str) -> RequestBuilder {
+        client.request(method, &format!("http://{}{}", self.server.addr(), url))
     }
 
     pub(crate) fn get(&self, url: &str) -> RequestBuilder {
-        self.build_request(Method::GET, url)
+        self.build_request(&self.client, Method::GET, url)
+    }
+
+    /// Like `get`, but the redirects are returned instead of being followed, to check redirects
+    /// pointing outside of the test server.
+    pub(crate) fn get_no_redirect(&self, url: &str) -> RequestBuilder {
+        self.build_request(&self.client_no_redirects, Method::GET, url)
+    }
+
+    pub(crate) fn post(&self, url: &str) -> RequestBuilder {
+        self.build_request(&self.client, Method::POST, url)
     }
 }
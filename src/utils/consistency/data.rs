@@ -21,7 +21,9 @@ pub(crate) struct Crate {
 pub(crate) struct Version(pub(crate) String);
 
 #[derive(Default, Debug)]
-pub(crate) struct Release {}
+pub(crate) struct Release {
+    pub(crate) yanked: bool,
+}
 
 impl PartialEq<String> for CrateName {
     fn eq(&self, other: &String) -> bool {
@@ -6,7 +6,8 @@ pub(crate) fn load(conn: &mut postgres::Client) -> Result<Data, failure::Error>
         "
         SELECT
             crates.name,
-            releases.version
+            releases.version,
+            releases.yanked
         FROM crates
         INNER JOIN releases ON releases.crate_id = crates.id
         ORDER BY crates.id, releases.id
@@ -31,7 +32,7 @@ pub(crate) fn load(conn: &mut postgres::Client) -> Result<Data, failure::Error>
             krate: Crate {
                 releases: {
                     let mut releases = BTreeMap::new();
-                    releases.insert(Version(row.get("version")), Release {});
+                    releases.insert(Version(row.get("version")), release(row));
                     releases
                 },
             },
@@ -51,10 +52,16 @@ pub(crate) fn load(conn: &mut postgres::Client) -> Result<Data, failure::Error>
         current
             .krate
             .releases
-            .insert(Version(row.get("version")), Release::default());
+            .insert(Version(row.get("version")), release(row));
     }
 
     data.crates.insert(current.name, current.krate);
 
     Ok(data)
 }
+
+fn release(row: &postgres::Row) -> Release {
+    Release {
+        yanked: row.get::<_, Option<bool>>("yanked").unwrap_or(false),
+    }
+}
@@ -11,7 +11,14 @@ pub(crate) fn load(index: &Index) -> Result<Data, failure::Error> {
                 releases: krate
                     .versions()
                     .iter()
-                    .map(|version| (Version(version.version().into()), Release::default()))
+                    .map(|version| {
+                        (
+                            Version(version.version().into()),
+                            Release {
+                                yanked: version.is_yanked(),
+                            },
+                        )
+                    })
                     .collect(),
             },
         );
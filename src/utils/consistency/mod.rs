@@ -1,58 +1,410 @@
+use self::data::Data;
 use self::diff::{Diff, Diffable};
-use crate::Index;
+use crate::db::set_yanked;
+use crate::utils::get_crate_priority;
+use crate::Context;
 use failure::ResultExt;
+use postgres::Client;
 
 mod data;
 mod db;
 mod diff;
 mod index;
 
+/// Compares the crates and releases in the database with the ones in the index, logging all the
+/// differences. Unless `dry_run` is set, the differences are also resolved:
+///
+/// - releases missing from the database are added to the build queue, unless they are yanked;
+/// - crates and releases missing from the index are deleted;
+/// - the yanked status of the releases is updated to match the index.
+///
+/// To avoid wrecking the database when the index is broken, nothing is done if resolving the
+/// differences takes more than `max_actions` actions.
 pub fn run_check(
-    conn: &mut postgres::Client,
-    index: &Index,
+    ctx: &dyn Context,
     dry_run: bool,
+    max_actions: Option<usize>,
 ) -> Result<(), failure::Error> {
-    if !dry_run {
-        failure::bail!("TODO: only a --dry-run synchronization is supported currently");
-    }
+    let mut conn = ctx.pool()?.get()?;
 
     log::info!("Loading data from database...");
     let timer = std::time::Instant::now();
-    let db_data =
-        self::db::load(conn).context("Loading crate data from database for consistency check")?;
+    let db_data = self::db::load(&mut conn)
+        .context("Loading crate data from database for consistency check")?;
     log::info!("...loaded in {:?}", timer.elapsed());
 
     log::info!("Loading data from index...");
     let timer = std::time::Instant::now();
-    let index_data =
-        self::index::load(index).context("Loading crate data from index for consistency check")?;
+    let index_data = self::index::load(&*ctx.index()?)
+        .context("Loading crate data from index for consistency check")?;
     log::info!("...loaded in {:?}", timer.elapsed());
 
-    let diff = db_data.diff(index_data);
+    let summary = resolve(ctx, &mut conn, db_data, index_data, dry_run, max_actions)?;
+    println!("{}", summary);
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Difference {
+    CrateNotInIndex(String),
+    CrateNotInDb(String),
+    ReleaseNotInIndex(String, String),
+    /// Releases yanked in the index are not included, as they're not built when they're
+    /// published either.
+    ReleaseNotInDb(String, String),
+    /// The release is yanked in the index but not in the database.
+    ReleaseYanked(String, String),
+    /// The release is yanked in the database but not in the index.
+    ReleaseUnyanked(String, String),
+}
 
-    for krate in diff.crates {
+/// Number of actions taken to resolve the differences.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Summary {
+    dry_run: bool,
+    differences: usize,
+    queued: usize,
+    deleted_crates: usize,
+    deleted_releases: usize,
+    yanked: usize,
+    unyanked: usize,
+}
+
+impl std::fmt::Display for Summary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Found {} differences", self.differences)?;
+        if self.dry_run {
+            return write!(f, "Dry run, no action taken");
+        }
+        writeln!(f, "Queued {} releases for build", self.queued)?;
+        writeln!(f, "Deleted {} crates", self.deleted_crates)?;
+        writeln!(f, "Deleted {} releases", self.deleted_releases)?;
+        writeln!(f, "Yanked {} releases", self.yanked)?;
+        write!(f, "Unyanked {} releases", self.unyanked)
+    }
+}
+
+fn resolve(
+    ctx: &dyn Context,
+    conn: &mut Client,
+    db_data: Data,
+    index_data: Data,
+    dry_run: bool,
+    max_actions: Option<usize>,
+) -> Result<Summary, failure::Error> {
+    let differences = find_differences(db_data, index_data);
+    for difference in &differences {
+        match difference {
+            Difference::CrateNotInIndex(name) => {
+                log::info!("Crate in db not in index: {}", name);
+            }
+            Difference::CrateNotInDb(name) => {
+                log::info!("Crate in index not in db: {}", name);
+            }
+            Difference::ReleaseNotInIndex(name, version) => {
+                log::info!("Release in db not in index: {} {}", name, version);
+            }
+            Difference::ReleaseNotInDb(name, version) => {
+                log::info!("Release in index not in db: {} {}", name, version);
+            }
+            Difference::ReleaseYanked(name, version) => {
+                log::info!("Release yanked in index not in db: {} {}", name, version);
+            }
+            Difference::ReleaseUnyanked(name, version) => {
+                log::info!("Release yanked in db not in index: {} {}", name, version);
+            }
+        }
+    }
+
+    let mut summary = Summary {
+        dry_run,
+        differences: differences.len(),
+        ..Summary::default()
+    };
+    if dry_run {
+        return Ok(summary);
+    }
+
+    if let Some(max_actions) = max_actions {
+        // The releases of the crates missing from the database are queued individually.
+        let actions = differences
+            .iter()
+            .filter(|difference| !matches!(difference, Difference::CrateNotInDb(_)))
+            .count();
+        if actions > max_actions {
+            failure::bail!(
+                "resolving the differences takes {} actions, more than the maximum of {}: \
+                 not resolving them",
+                actions,
+                max_actions
+            );
+        }
+    }
+
+    let build_queue = ctx.build_queue()?;
+    let storage = ctx.storage()?;
+    for difference in differences {
+        match difference {
+            Difference::CrateNotInIndex(name) => {
+                crate::db::delete_crate(conn, &storage, &name)
+                    .with_context(|_| format!("failed to delete crate {}", name))?;
+                summary.deleted_crates += 1;
+            }
+            // All the releases of the crate are listed as well, they will be queued individually.
+            Difference::CrateNotInDb(_) => {}
+            Difference::ReleaseNotInIndex(name, version) => {
+                crate::db::delete_version(conn, &storage, &name, &version)
+                    .with_context(|_| format!("failed to delete release {} {}", name, version))?;
+                summary.deleted_releases += 1;
+            }
+            Difference::ReleaseNotInDb(name, version) => {
+                let priority = get_crate_priority(conn, &name)?;
+                build_queue.add_crate(&name, &version, priority)?;
+                summary.queued += 1;
+            }
+            Difference::ReleaseYanked(name, version) => {
+                set_yanked(conn, &name, &version, true)?;
+                summary.yanked += 1;
+            }
+            Difference::ReleaseUnyanked(name, version) => {
+                set_yanked(conn, &name, &version, false)?;
+                summary.unyanked += 1;
+            }
+        }
+    }
+
+    Ok(summary)
+}
+
+fn find_differences(db_data: Data, index_data: Data) -> Vec<Difference> {
+    let mut differences = Vec::new();
+
+    for krate in db_data.diff(index_data).crates {
         match krate {
             Diff::Both(name, diff) => {
                 for release in diff.releases {
                     match release {
-                        Diff::Both(_, _) => {}
+                        Diff::Both(version, diff) => {
+                            let (name, version) = (name.to_string(), version.to_string());
+                            match diff.yanked {
+                                Some((false, true)) => {
+                                    differences.push(Difference::ReleaseYanked(name, version))
+                                }
+                                Some((true, false)) => {
+                                    differences.push(Difference::ReleaseUnyanked(name, version))
+                                }
+                                _ => {}
+                            }
+                        }
                         Diff::Left(version, _) => {
-                            log::info!("Release in db not in index: {} {}", name, version);
+                            differences.push(Difference::ReleaseNotInIndex(
+                                name.to_string(),
+                                version.to_string(),
+                            ));
                         }
-                        Diff::Right(version, _) => {
-                            log::info!("Release in index not in db: {} {}", name, version);
+                        Diff::Right(version, release) => {
+                            if !release.yanked {
+                                differences.push(Difference::ReleaseNotInDb(
+                                    name.to_string(),
+                                    version.to_string(),
+                                ));
+                            }
                         }
                     }
                 }
             }
             Diff::Left(name, _) => {
-                log::info!("Crate in db not in index: {}", name);
+                differences.push(Difference::CrateNotInIndex(name.to_string()));
             }
-            Diff::Right(name, _) => {
-                log::info!("Crate in index not in db: {}", name);
+            Diff::Right(name, krate) => {
+                differences.push(Difference::CrateNotInDb(name.to_string()));
+                for (version, release) in krate.releases {
+                    if !release.yanked {
+                        differences.push(Difference::ReleaseNotInDb(
+                            name.to_string(),
+                            version.to_string(),
+                        ));
+                    }
+                }
             }
         }
     }
 
-    Ok(())
+    differences
+}
+
+#[cfg(test)]
+mod tests {
+    use super::data::{Crate, CrateName, Release, Version};
+    use super::*;
+    use crate::test::{wrapper, TestEnvironment};
+
+    fn index_data(crates: &[(&str, &[(&str, bool)])]) -> Data {
+        Data {
+            crates: crates
+                .iter()
+                .map(|(name, releases)| {
+                    (
+                        CrateName((*name).into()),
+                        Crate {
+                            releases: releases
+                                .iter()
+                                .map(|(version, yanked)| {
+                                    (Version((*version).into()), Release { yanked: *yanked })
+                                })
+                                .collect(),
+                        },
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    fn resolve_with_env(
+        env: &TestEnvironment,
+        index: Data,
+        dry_run: bool,
+        max_actions: Option<usize>,
+    ) -> Result<Summary, failure::Error> {
+        let mut conn = env.db().conn();
+        let db_data = super::db::load(&mut conn)?;
+        resolve(env, &mut conn, db_data, index, dry_run, max_actions)
+    }
+
+    fn releases_in_db(
+        env: &TestEnvironment,
+    ) -> Result<Vec<(String, String, bool)>, failure::Error> {
+        Ok(env
+            .db()
+            .conn()
+            .query(
+                "SELECT crates.name, releases.version, releases.yanked
+                 FROM crates
+                 INNER JOIN releases ON releases.crate_id = crates.id
+                 ORDER BY crates.name, releases.version",
+                &[],
+            )?
+            .into_iter()
+            .map(|row| (row.get(0), row.get(1), row.get(2)))
+            .collect())
+    }
+
+    #[test]
+    fn test_find_differences() {
+        let db = index_data(&[
+            (
+                "both",
+                &[("1.0.0", false), ("1.1.0", false), ("1.2.0", true)],
+            ),
+            ("db-only", &[("1.0.0", false)]),
+        ]);
+        let index = index_data(&[
+            (
+                "both",
+                &[("1.0.0", true), ("1.2.0", false), ("2.0.0", true)],
+            ),
+            ("index-only", &[("0.1.0", false)]),
+        ]);
+
+        assert_eq!(
+            find_differences(db, index),
+            vec![
+                Difference::ReleaseYanked("both".into(), "1.0.0".into()),
+                Difference::ReleaseNotInIndex("both".into(), "1.1.0".into()),
+                Difference::ReleaseUnyanked("both".into(), "1.2.0".into()),
+                Difference::CrateNotInIndex("db-only".into()),
+                Difference::CrateNotInDb("index-only".into()),
+                Difference::ReleaseNotInDb("index-only".into(), "0.1.0".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_resolve_differences() {
+        wrapper(|env| {
+            env.fake_release().name("both").version("1.0.0").create()?;
+            env.fake_release().name("both").version("1.1.0").create()?;
+            env.fake_release()
+                .name("both")
+                .version("1.2.0")
+                .yanked(true)
+                .create()?;
+            env.fake_release()
+                .name("db-only")
+                .version("1.0.0")
+                .create()?;
+
+            let index = || {
+                index_data(&[
+                    (
+                        "both",
+                        &[("1.0.0", true), ("1.2.0", false), ("2.0.0", false)],
+                    ),
+                    ("index-only", &[("0.1.0", false), ("0.2.0", true)]),
+                ])
+            };
+
+            // Dry runs don't change anything.
+            let summary = resolve_with_env(env, index(), true, None)?;
+            assert_eq!(summary.differences, 7);
+            assert_eq!(summary.queued, 0);
+            assert_eq!(releases_in_db(env)?.len(), 4);
+
+            let summary = resolve_with_env(env, index(), false, None)?;
+            assert_eq!(
+                summary,
+                Summary {
+                    dry_run: false,
+                    differences: 7,
+                    queued: 2,
+                    deleted_crates: 1,
+                    deleted_releases: 1,
+                    yanked: 1,
+                    unyanked: 1,
+                }
+            );
+
+            assert_eq!(
+                releases_in_db(env)?,
+                vec![
+                    ("both".into(), "1.0.0".into(), true),
+                    ("both".into(), "1.2.0".into(), false),
+                ]
+            );
+            assert_eq!(
+                env.build_queue()
+                    .queued_crates()?
+                    .iter()
+                    .map(|c| (c.name.as_str(), c.version.as_str()))
+                    .collect::<Vec<_>>(),
+                vec![("both", "2.0.0"), ("index-only", "0.1.0")]
+            );
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_resolve_max_actions() {
+        wrapper(|env| {
+            env.fake_release().name("foo").version("1.0.0").create()?;
+            env.fake_release().name("bar").version("1.0.0").create()?;
+
+            // An empty index would delete everything.
+            assert!(resolve_with_env(env, Data::default(), false, Some(1)).is_err());
+            assert_eq!(releases_in_db(env)?.len(), 2);
+
+            resolve_with_env(env, Data::default(), false, Some(2))?;
+            assert!(releases_in_db(env)?.is_empty());
+
+            // Adding a crate only takes one action per queued release, and the yanked releases
+            // are not queued.
+            let index = index_data(&[("new", &[("1.0.0", false), ("1.1.0", true)])]);
+            let summary = resolve_with_env(env, index, false, Some(1))?;
+            assert_eq!(summary.queued, 1);
+
+            Ok(())
+        })
+    }
 }
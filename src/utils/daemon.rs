@@ -9,6 +9,7 @@ use crate::{
 use chrono::{Timelike, Utc};
 use failure::Error;
 use log::{debug, error, info};
+use std::sync::{Mutex, PoisonError};
 use std::thread;
 use std::time::{Duration, Instant};
 
@@ -58,19 +59,38 @@ pub fn start_daemon(context: &dyn Context, enable_registry_watcher: bool) -> Res
         start_registry_watcher(context)?;
     }
 
-    // build new crates every minute
-    let pool = context.pool()?;
-    let build_queue = context.build_queue()?;
-    let cloned_config = config.clone();
-    let rustwide_builder = RustwideBuilder::init(context)?;
-    thread::Builder::new()
-        .name("build queue reader".to_string())
-        .spawn(move || {
-            let doc_builder =
-                DocBuilder::new(cloned_config.clone(), pool.clone(), build_queue.clone());
-            queue_builder(doc_builder, rustwide_builder, build_queue).unwrap();
-        })
-        .unwrap();
+    // build new crates every minute, with each worker building a different crate
+    let mut rustwide_builder = RustwideBuilder::init(context)?;
+    if let Err(err) = rustwide_builder.update_toolchain() {
+        error!("failed to install the toolchain: {:?}", err);
+    }
+    for id in 0..config.build_workers {
+        let pool = context.pool()?;
+        let build_queue = context.build_queue()?;
+        let cloned_config = config.clone();
+        let worker = rustwide_builder.worker(id);
+        thread::Builder::new()
+            .name(format!("build queue reader {}", id))
+            .spawn(move || {
+                let doc_builder =
+                    DocBuilder::new(cloned_config.clone(), pool.clone(), build_queue.clone());
+                queue_builder(doc_builder, worker, build_queue, &cloned_config).unwrap();
+            })
+            .unwrap();
+    }
+
+    // update the toolchain of the workers outside of the builds
+    let rustwide_builder = Mutex::new(rustwide_builder);
+    cron(
+        "toolchain updater",
+        Duration::from_secs(config.toolchain_update_interval),
+        move || {
+            rustwide_builder
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .update_toolchain()
+        },
+    )?;
 
     // update release activity everyday at 23:55
     let pool = context.pool()?;
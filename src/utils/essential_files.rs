@@ -0,0 +1,171 @@
+//! Garbage collection of the essential files uploaded by `add_essential_files` for every
+//! toolchain, which are only needed as long as some documentation links to them.
+
+use crate::docbuilder::ESSENTIAL_FILES_VERSIONED;
+use crate::utils::parse_rustc_version;
+use crate::Storage;
+use failure::Error;
+use postgres::Client;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashSet};
+
+/// Number of paths listed at once while looking for the essential files.
+const LIST_BATCH_SIZE: usize = 1000;
+
+/// Find the essential files of the resource suffixes that are not used by any release, by the
+/// current toolchain or by the toolchains that can be rolled back to, and delete them unless
+/// `dry_run` is set. Returns the unused files, grouped by resource suffix.
+pub fn gc_essential_files(
+    conn: &mut Client,
+    storage: &Storage,
+    dry_run: bool,
+) -> Result<BTreeMap<String, Vec<String>>, Error> {
+    let used = used_resource_suffixes(conn)?;
+
+    let mut unused = BTreeMap::new();
+    for file in ESSENTIAL_FILES_VERSIONED {
+        let segments = file.rsplitn(2, '.').collect::<Vec<_>>();
+        let (prefix, extension) = (format!("{}-", segments[1]), format!(".{}", segments[0]));
+
+        // The documentation of the crates is stored under `rustdoc/` and `sources/`, so the
+        // prefix only matches the essential files at the root of the storage.
+        let mut start_after: Option<String> = None;
+        loop {
+            let paths = storage.list_paths(&prefix, start_after.as_deref(), LIST_BATCH_SIZE)?;
+            for path in &paths {
+                let suffix = path
+                    .strip_prefix(&prefix)
+                    .and_then(|rest| rest.strip_suffix(&extension));
+                match suffix {
+                    Some(suffix) if !used.contains(suffix) => unused
+                        .entry(suffix.to_string())
+                        .or_insert_with(Vec::new)
+                        .push(path.clone()),
+                    _ => {}
+                }
+            }
+
+            match paths.last() {
+                Some(last) if paths.len() == LIST_BATCH_SIZE => start_after = Some(last.clone()),
+                _ => break,
+            }
+        }
+    }
+
+    if !dry_run {
+        for path in unused.values().flatten() {
+            log::info!("deleting unused essential file {}", path);
+            storage.delete_prefix(path)?;
+        }
+    }
+
+    Ok(unused)
+}
+
+fn used_resource_suffixes(conn: &mut Client) -> Result<HashSet<String>, Error> {
+    let mut versions: Vec<String> = conn
+        .query(
+            "SELECT DISTINCT doc_rustc_version FROM releases
+             UNION
+             SELECT rustc_version FROM toolchains WHERE rolled_back_at IS NULL;",
+            &[],
+        )?
+        .into_iter()
+        .filter_map(|row| row.get(0))
+        .collect();
+
+    let current = conn.query(
+        "SELECT value FROM config WHERE name = 'rustc_version';",
+        &[],
+    )?;
+    if let Some(Value::String(version)) = current.get(0).map(|row| row.get(0)) {
+        versions.push(version);
+    }
+
+    // Releases built by very old versions of docs.rs have a rustc version that can't be parsed,
+    // and there are no essential files for them anyway.
+    Ok(versions
+        .iter()
+        .filter_map(|version| parse_rustc_version(version).ok())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::Blob;
+    use crate::test::wrapper;
+    use chrono::Utc;
+
+    fn blob(path: &str) -> Blob {
+        Blob {
+            path: path.into(),
+            mime: "text/css".into(),
+            date_updated: Utc::now(),
+            content: b"body {}".to_vec(),
+            compression: None,
+        }
+    }
+
+    #[test]
+    fn test_gc_essential_files() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("foo")
+                .rustc_version("rustc 1.50.0-nightly (aaaaaaaaa 2020-11-20)")
+                .create()?;
+            let mut conn = env.db().conn();
+            conn.execute(
+                "INSERT INTO config (name, value) VALUES ('rustc_version', $1);",
+                &[&Value::String(
+                    "rustc 1.50.0-nightly (ccccccccc 2020-11-22)".into(),
+                )],
+            )?;
+
+            let storage = env.storage();
+            storage.store_blobs(vec![
+                blob("rustdoc-20201120-1.50.0-nightly-aaaaaaaaa.css"),
+                blob("rustdoc-20201121-1.50.0-nightly-bbbbbbbbb.css"),
+                blob("main-20201121-1.50.0-nightly-bbbbbbbbb.js"),
+                blob("rustdoc-20201122-1.50.0-nightly-ccccccccc.css"),
+                blob("rustdoc/foo/0.1.0/rustdoc-20201121-1.50.0-nightly-bbbbbbbbb.css"),
+            ])?;
+
+            let mut expected = BTreeMap::new();
+            expected.insert(
+                "20201121-1.50.0-nightly-bbbbbbbbb".to_string(),
+                vec![
+                    "rustdoc-20201121-1.50.0-nightly-bbbbbbbbb.css".to_string(),
+                    "main-20201121-1.50.0-nightly-bbbbbbbbb.js".to_string(),
+                ],
+            );
+
+            // Dry runs don't delete anything.
+            let unused = gc_essential_files(&mut conn, &storage, true)?;
+            assert_eq!(
+                unused.keys().collect::<Vec<_>>(),
+                expected.keys().collect::<Vec<_>>()
+            );
+            assert!(storage.exists("main-20201121-1.50.0-nightly-bbbbbbbbb.js")?);
+
+            let mut unused = gc_essential_files(&mut conn, &storage, false)?;
+            for paths in unused.values_mut() {
+                paths.sort();
+            }
+            for paths in expected.values_mut() {
+                paths.sort();
+            }
+            assert_eq!(unused, expected);
+
+            assert!(!storage.exists("main-20201121-1.50.0-nightly-bbbbbbbbb.js")?);
+            assert!(!storage.exists("rustdoc-20201121-1.50.0-nightly-bbbbbbbbb.css")?);
+            assert!(storage.exists("rustdoc-20201120-1.50.0-nightly-aaaaaaaaa.css")?);
+            assert!(storage.exists("rustdoc-20201122-1.50.0-nightly-ccccccccc.css")?);
+            assert!(storage
+                .exists("rustdoc/foo/0.1.0/rustdoc-20201121-1.50.0-nightly-bbbbbbbbb.css")?);
+            assert!(gc_essential_files(&mut conn, &storage, false)?.is_empty());
+
+            Ok(())
+        });
+    }
+}
@@ -3,6 +3,7 @@
 pub(crate) use self::cargo_metadata::{CargoMetadata, Package as MetadataPackage};
 pub(crate) use self::copy::copy_doc_dir;
 pub use self::daemon::start_daemon;
+pub use self::essential_files::gc_essential_files;
 pub use self::github_updater::GithubUpdater;
 pub(crate) use self::html::rewrite_lol;
 pub use self::queue::{get_crate_priority, remove_crate_priority, set_crate_priority};
@@ -17,6 +18,7 @@ mod cargo_metadata;
 pub mod consistency;
 mod copy;
 mod daemon;
+mod essential_files;
 mod github_updater;
 mod html;
 mod pubsubhubbub;
@@ -4,24 +4,25 @@ use reqwest::{
 };
 use std::collections::HashMap;
 
-fn ping_hub(url: &str) -> Result<Response> {
+fn ping_hub(url: &str, feed_url: &str) -> Result<Response> {
     let mut params = HashMap::with_capacity(2);
     params.insert("hub.mode", "publish");
-    params.insert("hub.url", "https://docs.rs/releases/feed");
+    params.insert("hub.url", feed_url);
 
     let client = Client::new();
     client.post(url).form(&params).send()
 }
 
-/// Ping the two predefined hubs. Return either the number of successfully
-/// pinged hubs, or the first error.
-pub fn ping_hubs() -> Result<usize> {
+/// Ping the two predefined hubs about the releases feed of the instance at `base_url`. Return
+/// either the number of successfully pinged hubs, or the first error.
+pub fn ping_hubs(base_url: &str) -> Result<usize> {
+    let feed_url = format!("{}/releases/feed", base_url);
     vec![
         "https://pubsubhubbub.appspot.com",
         "https://pubsubhubbub.superfeedr.com",
     ]
     .into_iter()
-    .map(ping_hub)
+    .map(|hub| ping_hub(hub, &feed_url))
     .collect::<Result<Vec<_>>>()
     .map(|v| v.len())
 }
@@ -1,4 +1,4 @@
-use crate::{docbuilder::RustwideBuilder, utils::pubsubhubbub, BuildQueue, DocBuilder};
+use crate::{docbuilder::RustwideBuilder, utils::pubsubhubbub, BuildQueue, Config, DocBuilder};
 use failure::Error;
 use log::{debug, error, info, warn};
 use std::panic::{catch_unwind, AssertUnwindSafe};
@@ -11,6 +11,7 @@ pub fn queue_builder(
     mut doc_builder: DocBuilder,
     mut builder: RustwideBuilder,
     build_queue: Arc<BuildQueue>,
+    config: &Config,
 ) -> Result<(), Error> {
     /// Represents the current state of the builder thread.
     enum BuilderState {
@@ -44,7 +45,7 @@ pub fn queue_builder(
             debug!("10 builds in a row; pinging pubsubhubhub");
             status = BuilderState::QueueInProgress(0);
 
-            match pubsubhubbub::ping_hubs() {
+            match pubsubhubbub::ping_hubs(&config.base_url) {
                 Err(e) => error!("Failed to ping hub: {}", e),
                 Ok(n) => debug!("Succesfully pinged {} hubs", n),
             }
@@ -61,7 +62,7 @@ pub fn queue_builder(
             Ok(0) => {
                 if status.count() > 0 {
                     // ping the hubs before continuing
-                    match pubsubhubbub::ping_hubs() {
+                    match pubsubhubbub::ping_hubs(&config.base_url) {
                         Err(e) => error!("Failed to ping hub: {}", e),
                         Ok(n) => debug!("Succesfully pinged {} hubs", n),
                     }
@@ -89,6 +90,9 @@ pub fn queue_builder(
                 Ok(crate_built) => {
                     if crate_built {
                         status.increment();
+                    } else {
+                        // All the crates in the queue are being built by other workers.
+                        status = BuilderState::EmptyQueue;
                     }
                 }
             }
@@ -1,8 +1,9 @@
 use crate::{
-    db::Pool,
-    docbuilder::Limits,
+    db::{sandbox_overrides::get_override, sandbox_overrides::SandboxOverride, Pool},
+    docbuilder::{BuildFailureReason, Limits},
     impl_webpage,
     web::{page::WebPage, MetaData},
+    Config,
 };
 use chrono::{DateTime, NaiveDateTime, Utc};
 use iron::{
@@ -13,23 +14,50 @@ use iron::{
 };
 use router::Router;
 use serde::Serialize;
+use std::collections::HashMap;
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub(crate) struct Build {
     id: i32,
     rustc_version: String,
     docsrs_version: String,
     build_status: bool,
+    failure_reason: Option<BuildFailureReason>,
     build_time: DateTime<Utc>,
+    /// Duration of the whole build in seconds, missing for older builds.
+    duration: Option<f64>,
+    /// Size of the documentation of all the targets in bytes.
+    documentation_size: Option<i64>,
+    /// Highest peak memory usage of the targets in bytes, missing for older builds.
+    peak_memory: Option<i64>,
     output: Option<String>,
+    /// The result of building each target, empty for the builds done before they were recorded.
+    targets: Vec<BuildTarget>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct BuildTarget {
+    target: String,
+    rustc_version: String,
+    build_status: bool,
+    failure_reason: Option<BuildFailureReason>,
+    /// Duration of the build in seconds.
+    duration: f64,
+    /// Size of the documentation in bytes.
+    documentation_size: Option<i64>,
+    /// Peak memory usage in bytes.
+    peak_memory: Option<i64>,
+    output: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
 struct BuildsPage {
     metadata: MetaData,
     builds: Vec<Build>,
     build_details: Option<Build>,
     limits: Limits,
+    /// The overrides of the default limits, if any.
+    limits_override: Option<SandboxOverride>,
 }
 
 impl_webpage! {
@@ -42,8 +70,10 @@ pub fn build_list_handler(req: &mut Request) -> IronResult<Response> {
     let version = cexpect!(req, router.find("version"));
     let req_build_id: i32 = router.find("id").unwrap_or("0").parse().unwrap_or(0);
 
+    let config = extension!(req, Config);
     let mut conn = extension!(req, Pool).get()?;
-    let limits = ctry!(req, Limits::for_crate(&mut conn, name));
+    let limits = ctry!(req, Limits::for_crate(&config, &mut conn, name));
+    let limits_override = ctry!(req, get_override(&mut conn, name));
 
     let query = ctry!(
         req,
@@ -53,11 +83,16 @@ pub fn build_list_handler(req: &mut Request) -> IronResult<Response> {
                 releases.description,
                 releases.rustdoc_status,
                 releases.target_name,
+                releases.default_target,
                 builds.id,
                 builds.rustc_version,
                 builds.cratesfyi_version,
                 builds.build_status,
+                builds.failure_reason,
                 builds.build_time,
+                builds.duration,
+                builds.documentation_size,
+                builds.peak_memory,
                 builds.output
              FROM builds
              INNER JOIN releases ON releases.id = builds.rid
@@ -68,20 +103,81 @@ pub fn build_list_handler(req: &mut Request) -> IronResult<Response> {
         )
     );
 
+    // Only the logs of the requested build are loaded, as they can be quite big.
+    let target_rows = ctry!(
+        req,
+        conn.query(
+            "SELECT build_targets.build_id,
+                build_targets.target,
+                build_targets.rustc_version,
+                build_targets.build_status,
+                build_targets.failure_reason,
+                build_targets.duration,
+                build_targets.documentation_size,
+                build_targets.peak_memory,
+                CASE WHEN build_targets.build_id = $3 THEN build_targets.output END AS output
+             FROM build_targets
+             INNER JOIN builds ON builds.id = build_targets.build_id
+             INNER JOIN releases ON releases.id = builds.rid
+             INNER JOIN crates ON releases.crate_id = crates.id
+             WHERE crates.name = $1 AND releases.version = $2
+             ORDER BY build_targets.id ASC",
+            &[&name, &version, &req_build_id]
+        )
+    );
+    let mut targets: HashMap<i32, Vec<BuildTarget>> = HashMap::new();
+    for row in target_rows {
+        targets
+            .entry(row.get("build_id"))
+            .or_default()
+            .push(BuildTarget {
+                target: row.get("target"),
+                rustc_version: row.get("rustc_version"),
+                build_status: row.get("build_status"),
+                failure_reason: row
+                    .get::<_, Option<String>>("failure_reason")
+                    .and_then(|reason| reason.parse().ok()),
+                duration: row.get("duration"),
+                documentation_size: row.get("documentation_size"),
+                peak_memory: row.get("peak_memory"),
+                output: row.get("output"),
+            });
+    }
+
     let mut build_details = None;
     // FIXME: getting builds.output may cause performance issues when release have tons of builds
     let mut builds = query
         .into_iter()
         .map(|row| {
             let id: i32 = row.get("id");
+            let output: Option<String> = row.get("output");
+
+            // The log of the default target is only stored in the build.
+            let mut build_targets = targets.remove(&id).unwrap_or_default();
+            if id == req_build_id {
+                let default_target: String = row.get("default_target");
+                for target in build_targets
+                    .iter_mut()
+                    .filter(|target| target.target == default_target)
+                {
+                    target.output = output.clone();
+                }
+            }
 
             let build = Build {
                 id,
                 rustc_version: row.get("rustc_version"),
                 docsrs_version: row.get("cratesfyi_version"),
                 build_status: row.get("build_status"),
+                failure_reason: row
+                    .get::<_, Option<String>>("failure_reason")
+                    .and_then(|reason| reason.parse().ok()),
                 build_time: DateTime::from_utc(row.get::<_, NaiveDateTime>("build_time"), Utc),
-                output: row.get("output"),
+                duration: row.get("duration"),
+                documentation_size: row.get("documentation_size"),
+                peak_memory: row.get("peak_memory"),
+                output,
+                targets: build_targets,
             };
 
             if id == req_build_id {
@@ -96,6 +192,9 @@ pub fn build_list_handler(req: &mut Request) -> IronResult<Response> {
         // Remove build output from build list for json output
         for build in builds.iter_mut() {
             build.output = None;
+            for target in build.targets.iter_mut() {
+                target.output = None;
+            }
         }
 
         let mut resp = Response::with((status::Ok, serde_json::to_string(&builds).unwrap()));
@@ -115,7 +214,132 @@ pub fn build_list_handler(req: &mut Request) -> IronResult<Response> {
             builds,
             build_details,
             limits,
+            limits_override,
         }
         .into_response(req)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::db::sandbox_overrides::{set_override, SandboxOverride};
+    use crate::test::wrapper;
+    use kuchiki::traits::TendrilSink;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn build_targets() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("foo")
+                .version("0.1.0")
+                .add_build_target("i686-pc-windows-msvc", false)
+                .create()?;
+
+            let builds: Value = env
+                .frontend()
+                .get("/crate/foo/0.1.0/builds.json")
+                .send()?
+                .json()?;
+            let build = &builds.as_array().unwrap()[0];
+            assert_eq!(build["output"], Value::Null);
+            assert_eq!(build["duration"], json!(10.0));
+            assert_eq!(build["documentation_size"], json!(1024));
+            assert_eq!(build["peak_memory"], json!(256 * 1024 * 1024));
+            assert_eq!(
+                build["targets"],
+                json!([
+                    {
+                        "target": "x86_64-unknown-linux-gnu",
+                        "rustc_version": "rustc 2.0.0-nightly (000000000 1970-01-01)",
+                        "build_status": true,
+                        "failure_reason": null,
+                        "duration": 10.0,
+                        "documentation_size": 1024,
+                        "peak_memory": 256 * 1024 * 1024,
+                        "output": null,
+                    },
+                    {
+                        "target": "i686-pc-windows-msvc",
+                        "rustc_version": "rustc 2.0.0-nightly (000000000 1970-01-01)",
+                        "build_status": false,
+                        "failure_reason": "compile-error",
+                        "duration": 10.0,
+                        "documentation_size": null,
+                        "peak_memory": 256 * 1024 * 1024,
+                        "output": null,
+                    },
+                ])
+            );
+
+            let page = kuchiki::parse_html().one(
+                env.frontend()
+                    .get(&format!("/crate/foo/0.1.0/builds/{}", build["id"]))
+                    .send()?
+                    .text()?,
+            );
+            let targets = page
+                .select(".build-target")
+                .unwrap()
+                .map(|target| target.text_contents())
+                .collect::<Vec<_>>();
+            assert_eq!(targets.len(), 2);
+            assert!(targets[0].contains("x86_64-unknown-linux-gnu"));
+            assert!(targets[1].contains("i686-pc-windows-msvc"));
+            assert!(targets[1].contains("Compilation error"));
+
+            // The log of the default target is only stored once, but still shown with the target.
+            let logs = page
+                .select("pre")
+                .unwrap()
+                .map(|log| log.text_contents())
+                .collect::<Vec<_>>();
+            assert_eq!(logs.len(), 3);
+            assert!(!logs[0].contains("build log"));
+            assert!(logs[1].contains("It works!"));
+            let stored: Option<String> = env
+                .db()
+                .conn()
+                .query_one(
+                    "SELECT output FROM build_targets WHERE target = 'x86_64-unknown-linux-gnu'",
+                    &[],
+                )?
+                .get(0);
+            assert_eq!(stored, None);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn limits_override() {
+        wrapper(|env| {
+            env.fake_release().name("foo").version("0.1.0").create()?;
+            set_override(
+                &mut env.db().conn(),
+                "foo",
+                &SandboxOverride {
+                    networking: Some(true),
+                    reason: Some("downloads its data".into()),
+                    ..SandboxOverride::default()
+                },
+            )?;
+
+            let page = kuchiki::parse_html().one(
+                env.frontend()
+                    .get("/crate/foo/0.1.0/builds")
+                    .send()?
+                    .text()?,
+            );
+            let limits = page.select_first(".about table").unwrap().text_contents();
+            assert!(limits.contains("allowed"));
+            let note = page
+                .select_first(".limits-override")
+                .unwrap()
+                .text_contents();
+            assert!(note.contains("downloads its data"));
+
+            Ok(())
+        });
+    }
+}
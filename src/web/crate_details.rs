@@ -1,8 +1,11 @@
-use super::{match_version, redirect_base, render_markdown, MatchSemver, MetaData};
-use crate::{db::Pool, impl_webpage, web::page::WebPage};
+use super::{error::Nope, match_version, redirect_base, render_markdown, MatchSemver, MetaData};
+use crate::{db::Pool, docbuilder::BuildFailureReason, impl_webpage, web::page::WebPage};
 use chrono::{DateTime, NaiveDateTime, Utc};
+use iron::headers::{
+    AccessControlAllowOrigin, CacheControl, CacheDirective, ContentType, Expires, HttpDate,
+};
 use iron::prelude::*;
-use iron::Url;
+use iron::{status, Url};
 use postgres::Client;
 use router::Router;
 use serde::{ser::Serializer, Serialize};
@@ -25,6 +28,8 @@ pub struct CrateDetails {
     rustdoc: Option<String>, // this is description_long in database
     release_time: DateTime<Utc>,
     build_status: bool,
+    /// Why the last build of the release failed, if it failed.
+    failure_reason: Option<BuildFailureReason>,
     last_successful_build: Option<String>,
     rustdoc_status: bool,
     repository_url: Option<String>,
@@ -83,6 +88,13 @@ impl CrateDetails {
                 releases.description_long,
                 releases.release_time,
                 releases.build_status,
+                (
+                    SELECT builds.failure_reason
+                    FROM builds
+                    WHERE builds.rid = releases.id
+                    ORDER BY builds.id DESC
+                    LIMIT 1
+                ) AS failure_reason,
                 releases.rustdoc_status,
                 releases.repository_url,
                 releases.homepage_url,
@@ -169,6 +181,9 @@ impl CrateDetails {
             rustdoc: krate.get("description_long"),
             release_time: DateTime::from_utc(krate.get::<_, NaiveDateTime>("release_time"), Utc),
             build_status: krate.get("build_status"),
+            failure_reason: krate
+                .get::<_, Option<String>>("failure_reason")
+                .and_then(|reason| reason.parse().ok()),
             last_successful_build: None,
             rustdoc_status: krate.get("rustdoc_status"),
             repository_url: krate.get("repository_url"),
@@ -278,6 +293,82 @@ fn map_to_release(conn: &mut Client, crate_id: i32, version: semver::Version) ->
     }
 }
 
+/// Version of the format returned by `details.json`. Fields can be added to the format without
+/// changing it, but it has to be bumped whenever fields are removed or their meaning changes.
+const DETAILS_JSON_FORMAT_VERSION: u32 = 1;
+
+/// Stable representation of [`CrateDetails`] returned by `details.json`, kept separate from the
+/// struct used by the templates so that changes to the web pages don't break API consumers.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct CrateDetailsJson {
+    format_version: u32,
+    name: String,
+    version: String,
+    description: Option<String>,
+    license: Option<String>,
+    release_time: DateTime<Utc>,
+    yanked: bool,
+    build_status: bool,
+    failure_reason: Option<BuildFailureReason>,
+    rustdoc_status: bool,
+    last_successful_build: Option<String>,
+    is_library: bool,
+    target_name: String,
+    default_target: String,
+    doc_targets: Vec<String>,
+    documented_items: Option<i32>,
+    total_items: Option<i32>,
+    repository_url: Option<String>,
+    homepage_url: Option<String>,
+    documentation_url: Option<String>,
+    releases: Vec<ReleaseJson>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct ReleaseJson {
+    version: String,
+    build_status: bool,
+    yanked: bool,
+    is_library: bool,
+}
+
+impl From<CrateDetails> for CrateDetailsJson {
+    fn from(details: CrateDetails) -> Self {
+        CrateDetailsJson {
+            format_version: DETAILS_JSON_FORMAT_VERSION,
+            name: details.name,
+            version: details.version,
+            description: details.description,
+            license: details.license,
+            release_time: details.release_time,
+            yanked: details.yanked,
+            build_status: details.build_status,
+            failure_reason: details.failure_reason,
+            rustdoc_status: details.rustdoc_status,
+            last_successful_build: details.last_successful_build,
+            is_library: details.is_library,
+            target_name: details.target_name,
+            default_target: details.metadata.default_target,
+            doc_targets: details.doc_targets,
+            documented_items: details.documented_items.map(|items| items as i32),
+            total_items: details.total_items.map(|items| items as i32),
+            repository_url: details.repository_url,
+            homepage_url: details.homepage_url,
+            documentation_url: details.documentation_url,
+            releases: details
+                .releases
+                .into_iter()
+                .map(|release| ReleaseJson {
+                    version: release.version.to_string(),
+                    build_status: release.build_status,
+                    yanked: release.yanked,
+                    is_library: release.is_library,
+                })
+                .collect(),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize)]
 struct CrateDetailsPage {
     details: CrateDetails,
@@ -318,6 +409,35 @@ pub fn crate_details_handler(req: &mut Request) -> IronResult<Response> {
     }
 }
 
+pub fn crate_details_json_handler(req: &mut Request) -> IronResult<Response> {
+    let router = extension!(req, Router);
+    let name = cexpect!(req, router.find("name"));
+    let req_version = router.find("version");
+
+    let mut conn = extension!(req, Pool).get()?;
+    // The version is resolved instead of redirecting, as the response includes it anyway.
+    let matched = match_version(&mut conn, &name, req_version)?;
+    let name = matched.corrected_name.as_deref().unwrap_or(name);
+    let (version, _) = matched.version.into_parts();
+    let details = match CrateDetails::new(&mut conn, name, &version) {
+        Some(details) => details,
+        None => return Err(Nope::VersionNotFound.into()),
+    };
+
+    let body = ctry!(req, serde_json::to_string(&CrateDetailsJson::from(details)));
+    let mut resp = Response::with((status::Ok, body));
+    resp.headers.set(ContentType::json());
+    resp.headers.set(Expires(HttpDate(time::now())));
+    resp.headers.set(CacheControl(vec![
+        CacheDirective::NoCache,
+        CacheDirective::NoStore,
+        CacheDirective::MustRevalidate,
+    ]));
+    resp.headers.set(AccessControlAllowOrigin::Any);
+
+    Ok(resp)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -325,6 +445,7 @@ mod tests {
     use crate::test::{wrapper, TestDatabase};
     use failure::Error;
     use kuchiki::traits::TendrilSink;
+    use serde_json::json;
 
     fn assert_last_successful_build_equals(
         db: &TestDatabase,
@@ -651,6 +772,31 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_failure_reason() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("foo")
+                .version("0.1.0")
+                .failure_reason(BuildFailureReason::OutOfMemory)
+                .create()?;
+
+            let page =
+                kuchiki::parse_html().one(env.frontend().get("/crate/foo/0.1.0").send()?.text()?);
+            let warning = page.select_first(".warning").unwrap();
+            assert!(warning.text_contents().contains("Reason: Out of memory"));
+
+            let details: Value = env
+                .frontend()
+                .get("/crate/foo/0.1.0/details.json")
+                .send()?
+                .json()?;
+            assert_eq!(details["failure_reason"], json!("out-of-memory"));
+
+            Ok(())
+        });
+    }
+
     #[test]
     fn test_updating_owners() {
         wrapper(|env| {
@@ -741,4 +887,67 @@ mod tests {
             Ok(())
         });
     }
+
+    #[test]
+    fn test_details_json() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("foo")
+                .version("0.1.0")
+                .yanked(true)
+                .create()?;
+            env.fake_release()
+                .name("foo")
+                .version("0.2.0")
+                .default_target("x86_64-unknown-linux-gnu")
+                .add_target("i686-pc-windows-msvc")
+                .coverage(5, 10)
+                .create()?;
+
+            let resp = env.frontend().get("/crate/foo/0.2.0/details.json").send()?;
+            assert!(resp.status().is_success());
+            assert_eq!(
+                resp.headers().get("Access-Control-Allow-Origin").unwrap(),
+                "*"
+            );
+
+            let details: Value = resp.json()?;
+            assert_eq!(details["format_version"], json!(1));
+            assert_eq!(details["name"], json!("foo"));
+            assert_eq!(details["version"], json!("0.2.0"));
+            assert_eq!(details["yanked"], json!(false));
+            assert_eq!(details["license"], json!("MIT"));
+            assert_eq!(details["documented_items"], json!(5));
+            assert_eq!(details["total_items"], json!(10));
+            assert_eq!(details["default_target"], json!("x86_64-unknown-linux-gnu"));
+            assert_eq!(details["failure_reason"], Value::Null);
+            assert_eq!(
+                details["releases"],
+                json!([
+                    {"version": "0.2.0", "build_status": true, "yanked": false, "is_library": true},
+                    {"version": "0.1.0", "build_status": true, "yanked": true, "is_library": true},
+                ])
+            );
+            assert!(details["doc_targets"]
+                .as_array()
+                .unwrap()
+                .contains(&json!("i686-pc-windows-msvc")));
+
+            let resp = env.frontend().get("/crate/foo/0.3.0/details.json").send()?;
+            assert_eq!(resp.status(), reqwest::StatusCode::NOT_FOUND);
+
+            // Semver requirements are resolved like on the crate page.
+            for version in &["latest", "*", "^0.2"] {
+                let resp = env
+                    .frontend()
+                    .get(&format!("/crate/foo/{}/details.json", version))
+                    .send()?;
+                assert!(resp.status().is_success());
+                let details: Value = resp.json()?;
+                assert_eq!(details["version"], json!("0.2.0"));
+            }
+
+            Ok(())
+        });
+    }
 }
@@ -88,8 +88,9 @@ mod rustdoc;
 mod sitemap;
 mod source;
 mod statics;
+mod webhook;
 
-use crate::{impl_webpage, Context};
+use crate::{impl_webpage, Config, Context};
 use chrono::{DateTime, Utc};
 use error::Nope;
 use extensions::InjectExtensions;
@@ -102,7 +103,7 @@ use iron::{
     status::Status,
     Chain, Handler, Iron, IronError, IronResult, Listening, Request, Response, Url,
 };
-use page::TemplateData;
+use page::{TemplateData, WebPage};
 use postgres::Client;
 use router::NoRoute;
 use semver::{Version, VersionReq};
@@ -112,7 +113,6 @@ use std::{borrow::Cow, env, fmt, net::SocketAddr, path::PathBuf, sync::Arc, time
 
 /// Duration of static files for staticfile and DatabaseFileHandler (in seconds)
 const STATIC_FILE_CACHE_DURATION: u64 = 60 * 60 * 24 * 30 * 12; // 12 months
-const OPENSEARCH_XML: &[u8] = include_bytes!("opensearch.xml");
 
 const DEFAULT_BIND: &str = "0.0.0.0:3000";
 
@@ -518,16 +518,25 @@ fn redirect_base(req: &Request) -> String {
     }
 }
 
-fn opensearch_xml_handler(_: &mut Request) -> IronResult<Response> {
-    let mut response = Response::with((status::Ok, OPENSEARCH_XML));
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct OpenSearchXml {
+    base_url: String,
+}
+
+impl_webpage! {
+    OpenSearchXml = "core/opensearch.xml",
+    content_type = ContentType("application/opensearchdescription+xml".parse().unwrap()),
+}
+
+fn opensearch_xml_handler(req: &mut Request) -> IronResult<Response> {
+    let mut response = OpenSearchXml {
+        base_url: extension!(req, Config).base_url.clone(),
+    }
+    .into_response(req)?;
     let cache = vec![
         CacheDirective::Public,
         CacheDirective::MaxAge(STATIC_FILE_CACHE_DURATION as u32),
     ];
-    response.headers.set(ContentType(
-        "application/opensearchdescription+xml".parse().unwrap(),
-    ));
-
     response.headers.set(CacheControl(cache));
 
     Ok(response)
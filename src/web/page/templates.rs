@@ -1,4 +1,4 @@
-use crate::{db::Pool, error::Result};
+use crate::{db::Pool, docbuilder::BuildFailureReason, error::Result};
 use arc_swap::ArcSwap;
 use chrono::{DateTime, Utc};
 use failure::ResultExt;
@@ -148,6 +148,7 @@ pub(super) fn load_templates(conn: &mut Client) -> Result<Tera> {
     tera.register_filter("timeformat", timeformat);
     tera.register_filter("dbg", dbg);
     tera.register_filter("dedent", dedent);
+    tera.register_filter("failure_reason", failure_reason);
     tera.register_filter("fas", IconType::Strong);
     tera.register_filter("far", IconType::Regular);
     tera.register_filter("fab", IconType::Brand);
@@ -278,6 +279,18 @@ fn dedent(value: &Value, args: &HashMap<String, Value>) -> TeraResult<Value> {
     Ok(Value::String(unindented))
 }
 
+/// Describe the reason of a build failure, from its name
+fn failure_reason(value: &Value, _args: &HashMap<String, Value>) -> TeraResult<Value> {
+    let name = value
+        .as_str()
+        .ok_or_else(|| tera::Error::msg("failure_reason takes a string"))?;
+    let reason: BuildFailureReason = name
+        .parse()
+        .map_err(|_| tera::Error::msg(format!("unknown build failure reason {}", name)))?;
+
+    Ok(Value::String(reason.description().into()))
+}
+
 enum IconType {
     Strong,
     Regular,
@@ -363,4 +376,14 @@ mod tests {
             Ok(())
         });
     }
+    #[test]
+    fn test_failure_reason_filter() {
+        let args = HashMap::new();
+        assert_eq!(
+            failure_reason(&Value::String("timeout".into()), &args).unwrap(),
+            Value::String("Timed out".into())
+        );
+        assert!(failure_reason(&Value::String("foo".into()), &args).is_err());
+        assert!(failure_reason(&Value::Null, &args).is_err());
+    }
 }
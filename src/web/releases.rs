@@ -2,14 +2,18 @@
 
 use crate::{
     build_queue::QueuedCrate,
-    db::Pool,
+    db::{escape_like, Pool},
+    docbuilder::BuildFailureReason,
     impl_webpage,
     web::{error::Nope, match_version, page::WebPage, redirect_base},
-    BuildQueue,
+    BuildQueue, Config,
 };
 use chrono::{DateTime, NaiveDateTime, Utc};
 use iron::{
-    headers::{ContentType, Expires, HttpDate},
+    headers::{
+        Accept, AccessControlAllowOrigin, CacheControl, CacheDirective, ContentType, Expires,
+        HttpDate,
+    },
     mime::{Mime, SubLevel, TopLevel},
     modifiers::Redirect,
     status, IronResult, Request, Response, Url,
@@ -18,6 +22,7 @@ use postgres::Client;
 use router::Router;
 use serde::Serialize;
 use serde_json::Value;
+use strum::IntoEnumIterator;
 
 /// Number of release in home page
 const RELEASES_IN_HOME: i64 = 15;
@@ -65,7 +70,17 @@ impl Default for Order {
     }
 }
 
-pub(crate) fn get_releases(conn: &mut Client, page: i64, limit: i64, order: Order) -> Vec<Release> {
+/// Value of `?reason=` matching the failed builds without a failure reason, like the ones built
+/// before the reasons were recorded.
+const UNKNOWN_FAILURE_REASON: &str = "unknown";
+
+pub(crate) fn get_releases(
+    conn: &mut Client,
+    page: i64,
+    limit: i64,
+    order: Order,
+    failure_reason: Option<&str>,
+) -> Vec<Release> {
     let offset = (page - 1) * limit;
 
     // WARNING: it is _crucial_ that this always be hard-coded and NEVER be user input
@@ -88,24 +103,34 @@ pub(crate) fn get_releases(conn: &mut Client, page: i64, limit: i64, order: Orde
         WHERE
             ((NOT $3) OR (releases.build_status = FALSE AND releases.is_library = TRUE))
             AND crates.latest_version_id = releases.id
+            AND ($4::TEXT IS NULL OR $4 = COALESCE((
+                SELECT builds.failure_reason
+                FROM builds
+                WHERE builds.rid = releases.id
+                ORDER BY builds.id DESC
+                LIMIT 1
+            ), '{}'))
         ORDER BY {} DESC
         LIMIT $1 OFFSET $2",
-        ordering,
+        UNKNOWN_FAILURE_REASON, ordering,
     );
 
-    conn.query(query.as_str(), &[&limit, &offset, &filter_failed])
-        .unwrap()
-        .into_iter()
-        .map(|row| Release {
-            name: row.get(0),
-            version: row.get(1),
-            description: row.get(2),
-            target_name: row.get(3),
-            release_time: DateTime::from_utc(row.get::<_, NaiveDateTime>(4), Utc),
-            rustdoc_status: row.get(5),
-            stars: row.get(6),
-        })
-        .collect()
+    conn.query(
+        query.as_str(),
+        &[&limit, &offset, &filter_failed, &failure_reason],
+    )
+    .unwrap()
+    .into_iter()
+    .map(|row| Release {
+        name: row.get(0),
+        version: row.get(1),
+        description: row.get(2),
+        target_name: row.get(3),
+        release_time: DateTime::from_utc(row.get::<_, NaiveDateTime>(4), Utc),
+        rustdoc_status: row.get(5),
+        stars: row.get(6),
+    })
+    .collect()
 }
 
 fn get_releases_by_author(
@@ -210,18 +235,46 @@ fn get_releases_by_owner(
     (author_name.unwrap_or_default(), packages)
 }
 
+/// The latest releases of the crates matching the search query `$1`, shared by the queries
+/// listing and counting the search results.
+const SEARCH_RESULTS: &str = "
+        FROM crates
+        INNER JOIN (
+            SELECT releases.id, releases.crate_id
+            FROM (
+                SELECT
+                    releases.id,
+                    releases.crate_id,
+                    RANK() OVER (PARTITION BY crate_id ORDER BY release_time DESC) as rank
+                FROM releases
+                WHERE releases.rustdoc_status AND NOT releases.yanked
+            ) AS releases
+            WHERE releases.rank = 1
+        ) AS latest_release ON latest_release.crate_id = crates.id
+        INNER JOIN releases ON latest_release.id = releases.id,
+        plainto_tsquery('english', $1) AS query,
+        LATERAL (
+            SELECT
+                ((char_length($1)::float - levenshtein(crates.name, $1)::float) / char_length($1)::float) >= 0.65
+                OR crates.name ILIKE CONCAT('%', $1, '%') AS name
+        ) AS matches
+        WHERE matches.name OR releases.search_vector @@ query";
+
 /// Get the search results for a crate search query
 ///
 /// Retrieves crates which names have a levenshtein distance of less than or equal to 3,
 /// crates who fit into or otherwise are made up of the query or crates whose descriptions
 /// match the search query.
 ///
+/// Name matches are always shown first. The other results are ordered by their full-text search
+/// rank (weighting the name, description, keywords and crate documentation), boosted by the
+/// downloads and stars of the crate.
+///
 /// * `query`: The query string, unfiltered
 /// * `page`: The page of results to show (1-indexed)
 /// * `limit`: The number of results to return
 ///
 /// Returns 0 and an empty Vec when no results are found or if a database error occurs
-///
 fn get_search_results(
     conn: &mut Client,
     mut query: &str,
@@ -229,10 +282,11 @@ fn get_search_results(
     limit: i64,
 ) -> (i64, Vec<Release>) {
     query = query.trim();
-    let offset = (page - 1) * limit;
+    // The page comes from the query string, so it can be arbitrarily large.
+    let offset = (page.max(1) - 1).saturating_mul(limit);
 
-    let statement = "
-        SELECT
+    let statement = format!(
+        "SELECT
             crates.name AS name,
             releases.version AS version,
             releases.description AS description,
@@ -241,41 +295,38 @@ fn get_search_results(
             releases.rustdoc_status AS rustdoc_status,
             crates.github_stars AS github_stars,
             COUNT(*) OVER() as total
-        FROM crates
-        INNER JOIN (
-            SELECT releases.id, releases.crate_id
-            FROM (
-                SELECT
-                    releases.id,
-                    releases.crate_id,
-                    RANK() OVER (PARTITION BY crate_id ORDER BY release_time DESC) as rank
-                FROM releases
-                WHERE releases.rustdoc_status AND NOT releases.yanked
-            ) AS releases
-            WHERE releases.rank = 1
-        ) AS latest_release ON latest_release.crate_id = crates.id
-        INNER JOIN releases ON latest_release.id = releases.id
-        WHERE
-            ((char_length($1)::float - levenshtein(crates.name, $1)::float) / char_length($1)::float) >= 0.65
-            OR crates.name ILIKE CONCAT('%', $1, '%')
-        GROUP BY crates.id, releases.id
+        {}
         ORDER BY
-            levenshtein(crates.name, $1) ASC,
+            matches.name DESC,
+            CASE WHEN matches.name THEN levenshtein(crates.name, $1) END ASC,
             crates.name ILIKE CONCAT('%', $1, '%'),
+            ts_rank(releases.search_vector, query) * (
+                1 + ln(1 + releases.downloads) + ln(1 + COALESCE(crates.github_stars, 0))
+            ) DESC,
             releases.downloads DESC
-        LIMIT $2 OFFSET $3";
+        LIMIT $2 OFFSET $3",
+        SEARCH_RESULTS
+    );
 
-    let rows = if let Ok(rows) = conn.query(statement, &[&query, &limit, &offset]) {
+    let rows = if let Ok(rows) = conn.query(statement.as_str(), &[&query, &limit, &offset]) {
         rows
     } else {
         return (0, Vec::new());
     };
 
-    // Each row contains the total number of possible/valid results, just get it once
-    let total_results = rows
-        .get(0)
-        .map(|row| row.get::<_, i64>("total"))
-        .unwrap_or_default();
+    // Each row contains the total number of possible/valid results, just get it once. Pages past
+    // the last result have no rows, so the results are counted separately for them.
+    let total_results = match rows.get(0) {
+        Some(row) => row.get::<_, i64>("total"),
+        None if offset > 0 => conn
+            .query_one(
+                format!("SELECT COUNT(*) {}", SEARCH_RESULTS).as_str(),
+                &[&query],
+            )
+            .map(|row| row.get(0))
+            .unwrap_or_default(),
+        None => 0,
+    };
     let packages: Vec<Release> = rows
         .into_iter()
         .map(|row| Release {
@@ -292,6 +343,89 @@ fn get_search_results(
     (total_results, packages)
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct SearchItemResult {
+    crate_name: String,
+    version: String,
+    path: String,
+    kind: String,
+    description: Option<String>,
+    /// Link to the rustdoc page of the item.
+    url: String,
+}
+
+/// Get the items whose path matches the query, in the latest release of every crate.
+///
+/// The last segment of the query is a prefix of the item name, while the whole query has to
+/// appear in the path of the item, so that both `entry` and `HashMap::entry` find
+/// `std::collections::HashMap::entry`. Exact name matches are shown first.
+fn get_item_search_results(
+    conn: &mut Client,
+    query: &str,
+    limit: i64,
+) -> Result<Vec<SearchItemResult>, failure::Error> {
+    let query = query.trim();
+    let name = query.rsplit("::").next().unwrap_or(query).to_lowercase();
+
+    let rows = conn.query(
+        "SELECT
+            crates.name,
+            releases.version,
+            search_items.path,
+            search_items.kind,
+            search_items.description,
+            search_items.url
+        FROM search_items
+        INNER JOIN (
+            SELECT releases.id
+            FROM (
+                SELECT
+                    releases.id,
+                    RANK() OVER (PARTITION BY crate_id ORDER BY release_time DESC) as rank
+                FROM releases
+                WHERE releases.rustdoc_status AND NOT releases.yanked
+            ) AS releases
+            WHERE releases.rank = 1
+        ) AS latest_release ON latest_release.id = search_items.release_id
+        INNER JOIN releases ON releases.id = search_items.release_id
+        INNER JOIN crates ON crates.id = releases.crate_id
+        WHERE LOWER(search_items.name) LIKE $1 AND search_items.path ILIKE $2
+        ORDER BY
+            LOWER(search_items.name) = $3 DESC,
+            releases.downloads DESC,
+            char_length(search_items.path),
+            search_items.path
+        LIMIT $4",
+        &[
+            &format!("{}%", escape_like(&name)),
+            &format!("%{}%", escape_like(query)),
+            &name,
+            &limit,
+        ],
+    )?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            let (crate_name, version): (String, String) = (row.get("name"), row.get("version"));
+            let url = format!(
+                "/{}/{}/{}",
+                crate_name,
+                version,
+                row.get::<_, String>("url")
+            );
+            SearchItemResult {
+                crate_name,
+                version,
+                path: row.get("path"),
+                kind: row.get("kind"),
+                description: row.get("description"),
+                url,
+            }
+        })
+        .collect())
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 struct HomePage {
     recent_releases: Vec<Release>,
@@ -303,7 +437,7 @@ impl_webpage! {
 
 pub fn home_page(req: &mut Request) -> IronResult<Response> {
     let mut conn = extension!(req, Pool).get()?;
-    let recent_releases = get_releases(&mut conn, 1, RELEASES_IN_HOME, Order::ReleaseTime);
+    let recent_releases = get_releases(&mut conn, 1, RELEASES_IN_HOME, Order::ReleaseTime, None);
 
     HomePage { recent_releases }.into_response(req)
 }
@@ -311,6 +445,7 @@ pub fn home_page(req: &mut Request) -> IronResult<Response> {
 #[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 struct ReleaseFeed {
     recent_releases: Vec<Release>,
+    base_url: String,
 }
 
 impl_webpage! {
@@ -320,9 +455,13 @@ impl_webpage! {
 
 pub fn releases_feed_handler(req: &mut Request) -> IronResult<Response> {
     let mut conn = extension!(req, Pool).get()?;
-    let recent_releases = get_releases(&mut conn, 1, RELEASES_IN_FEED, Order::ReleaseTime);
+    let recent_releases = get_releases(&mut conn, 1, RELEASES_IN_FEED, Order::ReleaseTime, None);
 
-    ReleaseFeed { recent_releases }.into_response(req)
+    ReleaseFeed {
+        recent_releases,
+        base_url: extension!(req, Config).base_url.clone(),
+    }
+    .into_response(req)
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize)]
@@ -334,6 +473,9 @@ struct ViewReleases {
     show_previous_page: bool,
     page_number: i64,
     author: Option<String>,
+    /// The reasons the releases of the failures lists can be filtered by, as `(name, description)`.
+    failure_reasons: Vec<(&'static str, &'static str)>,
+    failure_reason: Option<&'static str>,
 }
 
 impl_webpage! {
@@ -371,9 +513,35 @@ fn releases_handler(req: &mut Request, release_type: ReleaseType) -> IronResult<
         ),
     };
 
+    let is_failures_list = matches!(
+        release_type,
+        ReleaseType::RecentFailures | ReleaseType::Failures
+    );
+    let failure_reason = if is_failures_list {
+        req.url
+            .as_ref()
+            .query_pairs()
+            .find(|(key, _)| key == "reason")
+            .and_then(|(_, reason)| {
+                if reason == UNKNOWN_FAILURE_REASON {
+                    Some(UNKNOWN_FAILURE_REASON)
+                } else {
+                    reason.parse().ok().map(BuildFailureReason::as_str)
+                }
+            })
+    } else {
+        None
+    };
+
     let releases = {
         let mut conn = extension!(req, Pool).get()?;
-        get_releases(&mut conn, page_number, RELEASES_IN_RELEASES, release_order)
+        get_releases(
+            &mut conn,
+            page_number,
+            RELEASES_IN_RELEASES,
+            release_order,
+            failure_reason,
+        )
     };
 
     // Show next and previous page buttons
@@ -390,6 +558,15 @@ fn releases_handler(req: &mut Request, release_type: ReleaseType) -> IronResult<
         show_previous_page,
         page_number,
         author: None,
+        failure_reasons: if is_failures_list {
+            BuildFailureReason::iter()
+                .map(|reason| (reason.as_str(), reason.description()))
+                .chain(std::iter::once((UNKNOWN_FAILURE_REASON, "Not recorded")))
+                .collect()
+        } else {
+            Vec::new()
+        },
+        failure_reason,
     }
     .into_response(req)
 }
@@ -459,6 +636,8 @@ pub fn author_handler(req: &mut Request) -> IronResult<Response> {
         show_previous_page,
         page_number,
         author: Some(author_name),
+        failure_reasons: Vec::new(),
+        failure_reason: None,
     }
     .into_response(req)
 }
@@ -498,6 +677,107 @@ impl_webpage! {
     status = |search| search.status,
 }
 
+/// Search results returned by `/releases/search.json`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct SearchJson {
+    query: String,
+    total: i64,
+    page: i64,
+    per_page: i64,
+    next_page: Option<i64>,
+    previous_page: Option<i64>,
+    releases: Vec<Release>,
+}
+
+/// Whether the search results should be returned as JSON instead of HTML, either because the
+/// `.json` endpoint was requested or because the client asked for it with the `Accept` header.
+fn wants_json(req: &Request) -> bool {
+    if req.url.path().join("/").ends_with(".json") {
+        return true;
+    }
+
+    req.headers.get::<Accept>().map_or(false, |accept| {
+        accept
+            .iter()
+            .any(|item| matches!(item.item, Mime(TopLevel::Application, SubLevel::Json, _)))
+    })
+}
+
+fn search_json_response(req: &Request, conn: &mut Client, query: &str) -> IronResult<Response> {
+    let page = req
+        .url
+        .as_ref()
+        .query_pairs()
+        .find(|(key, _)| key == "page")
+        .and_then(|(_, page)| page.parse::<i64>().ok())
+        .filter(|&page| page > 0)
+        .unwrap_or(1);
+
+    let (total, releases) = get_search_results(conn, query, page, RELEASES_IN_RELEASES);
+    let search = SearchJson {
+        query: query.trim().to_owned(),
+        total,
+        page,
+        per_page: RELEASES_IN_RELEASES,
+        next_page: if page.saturating_mul(RELEASES_IN_RELEASES) < total {
+            page.checked_add(1)
+        } else {
+            None
+        },
+        previous_page: if page > 1 { Some(page - 1) } else { None },
+        releases,
+    };
+
+    let mut resp = Response::with((status::Ok, ctry!(req, serde_json::to_string(&search))));
+    resp.headers.set(ContentType::json());
+    resp.headers.set(Expires(HttpDate(time::now())));
+    resp.headers.set(CacheControl(vec![
+        CacheDirective::NoCache,
+        CacheDirective::NoStore,
+        CacheDirective::MustRevalidate,
+    ]));
+    resp.headers.set(AccessControlAllowOrigin::Any);
+    set_vary_accept(&mut resp);
+
+    Ok(resp)
+}
+
+/// The search results are returned as HTML or JSON depending on the `Accept` header, which caches
+/// need to take into account.
+fn set_vary_accept(resp: &mut Response) {
+    resp.headers.set_raw("Vary", vec![b"Accept".to_vec()]);
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct ItemSearch {
+    title: String,
+    search_query: String,
+    items: Vec<SearchItemResult>,
+}
+
+impl_webpage! {
+    ItemSearch = "releases/search_items.html",
+}
+
+fn item_search_response(req: &Request, conn: &mut Client, query: &str) -> IronResult<Response> {
+    let items = ctry!(
+        req,
+        get_item_search_results(conn, query, RELEASES_IN_RELEASES)
+    );
+    let title = if items.is_empty() {
+        format!("No items found for '{}'", query)
+    } else {
+        format!("Items matching '{}'", query)
+    };
+
+    ItemSearch {
+        title,
+        search_query: query.to_owned(),
+        items,
+    }
+    .into_response(req)
+}
+
 pub fn search_handler(req: &mut Request) -> IronResult<Response> {
     let url = req.url.as_ref();
     let mut params = url.query_pairs();
@@ -505,6 +785,17 @@ pub fn search_handler(req: &mut Request) -> IronResult<Response> {
     let mut conn = extension!(req, Pool).get()?;
 
     if let Some((_, query)) = query {
+        if url
+            .query_pairs()
+            .any(|(key, value)| key == "mode" && value == "items")
+        {
+            return item_search_response(req, &mut conn, &query);
+        }
+
+        if wants_json(req) {
+            return search_json_response(req, &mut conn, &query);
+        }
+
         // check if I am feeling lucky button pressed and redirect user to crate page
         // if there is a match
         // TODO: Redirecting to latest doc might be more useful
@@ -613,13 +904,15 @@ pub fn search_handler(req: &mut Request) -> IronResult<Response> {
         };
 
         // FIXME: There is no pagination
-        Search {
+        let mut resp = Search {
             title,
             results,
             search_query: Some(query.into_owned()),
             ..Default::default()
         }
-        .into_response(req)
+        .into_response(req)?;
+        set_vary_accept(&mut resp);
+        Ok(resp)
     } else {
         Err(Nope::NoResults.into())
     }
@@ -672,6 +965,8 @@ pub fn build_queue_handler(req: &mut Request) -> IronResult<Response> {
         // will be built after everything else, which is counter-intuitive for people not
         // familiar with docs.rs's inner workings.
         krate.priority = -krate.priority;
+        // The errors can contain details about the build servers, so they're kept private.
+        krate.last_error = None;
     }
 
     BuildQueuePage {
@@ -818,30 +1113,29 @@ mod tests {
         })
     }
 
-    // Description searching more than doubles search time
-    // #[test]
-    // fn search_descriptions() {
-    //     wrapper(|env| {
-    //         let db = env.db();
-    //         env.fake_release()
-    //             .name("something_completely_unrelated")
-    //             .description("Supercalifragilisticexpialidocious")
-    //             .create()?;
-    //
-    //         let (num_results, results) =
-    //             get_search_results(&mut db.conn(), "supercalifragilisticexpialidocious", 1, 100);
-    //         assert_eq!(num_results, 1);
-    //
-    //         let mut results = results.into_iter();
-    //         assert_eq!(
-    //             results.next().unwrap().name,
-    //             "something_completely_unrelated"
-    //         );
-    //         assert_eq!(results.count(), 0);
-    //
-    //         Ok(())
-    //     })
-    // }
+    #[test]
+    fn search_descriptions() {
+        wrapper(|env| {
+            let db = env.db();
+            env.fake_release()
+                .name("something_completely_unrelated")
+                .description("Supercalifragilisticexpialidocious")
+                .create()?;
+
+            let (num_results, results) =
+                get_search_results(&mut db.conn(), "supercalifragilisticexpialidocious", 1, 100);
+            assert_eq!(num_results, 1);
+
+            let mut results = results.into_iter();
+            assert_eq!(
+                results.next().unwrap().name,
+                "something_completely_unrelated"
+            );
+            assert_eq!(results.count(), 0);
+
+            Ok(())
+        })
+    }
 
     #[test]
     fn search_limits() {
@@ -936,45 +1230,72 @@ mod tests {
         })
     }
 
-    // Description searching more than doubles search time
-    // #[test]
-    // fn fuzzy_over_description() {
-    //     wrapper(|env| {
-    //         let db = env.db();
-    //         env.fake_release()
-    //             .name("name_better_than_description")
-    //             .description("this is the correct choice")
-    //             .create()?;
-    //         env.fake_release()
-    //             .name("im_completely_unrelated")
-    //             .description("name_better_than_description")
-    //             .create()?;
-    //         env.fake_release()
-    //             .name("i_have_zero_relation_whatsoever")
-    //             .create()?;
-    //
-    //         let (num_results, results) =
-    //             get_search_results(&mut db.conn(), "name_better_than_description", 1, 100);
-    //         assert_eq!(num_results, 2);
-    //
-    //         let mut results = results.into_iter();
-    //
-    //         let next = results.next().unwrap();
-    //         assert_eq!(next.name, "name_better_than_description");
-    //         assert_eq!(next.description, Some("this is the correct choice".into()));
-    //
-    //         let next = results.next().unwrap();
-    //         assert_eq!(next.name, "im_completely_unrelated");
-    //         assert_eq!(
-    //             next.description,
-    //             Some("name_better_than_description".into())
-    //         );
-    //
-    //         assert_eq!(results.count(), 0);
-    //
-    //         Ok(())
-    //     })
-    // }
+    #[test]
+    fn fuzzy_over_description() {
+        wrapper(|env| {
+            let db = env.db();
+            env.fake_release()
+                .name("name_better_than_description")
+                .description("this is the correct choice")
+                .create()?;
+            env.fake_release()
+                .name("im_completely_unrelated")
+                .description("name_better_than_description")
+                .create()?;
+            env.fake_release()
+                .name("i_have_zero_relation_whatsoever")
+                .create()?;
+
+            let (num_results, results) =
+                get_search_results(&mut db.conn(), "name_better_than_description", 1, 100);
+            assert_eq!(num_results, 2);
+
+            let mut results = results.into_iter();
+
+            let next = results.next().unwrap();
+            assert_eq!(next.name, "name_better_than_description");
+            assert_eq!(next.description, Some("this is the correct choice".into()));
+
+            let next = results.next().unwrap();
+            assert_eq!(next.name, "im_completely_unrelated");
+            assert_eq!(
+                next.description,
+                Some("name_better_than_description".into())
+            );
+
+            assert_eq!(results.count(), 0);
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn description_matches_ordered_by_popularity() {
+        wrapper(|env| {
+            let db = env.db();
+            env.fake_release()
+                .name("unpopular")
+                .description("Parses configuration files")
+                .downloads(10)
+                .create()?;
+            env.fake_release()
+                .name("popular")
+                .description("Parse configuration files quickly")
+                .downloads(10_000)
+                .create()?;
+
+            // Words are stemmed, so "parsing" also matches "parses".
+            let (num_results, results) = get_search_results(&mut db.conn(), "parsing", 1, 100);
+            assert_eq!(num_results, 2);
+
+            let mut results = results.into_iter();
+            assert_eq!(results.next().unwrap().name, "popular");
+            assert_eq!(results.next().unwrap().name, "unpopular");
+            assert_eq!(results.count(), 0);
+
+            Ok(())
+        })
+    }
 
     #[test]
     fn dont_return_unrelated() {
@@ -1073,6 +1394,211 @@ mod tests {
         })
     }
 
+    #[test]
+    fn search_json() {
+        wrapper(|env| {
+            let web = env.frontend();
+            env.fake_release()
+                .name("some_random_crate")
+                .description("some description")
+                .create()?;
+            for i in 0..RELEASES_IN_RELEASES {
+                env.fake_release()
+                    .name(&format!("some_random_crate_{}", i))
+                    .create()?;
+            }
+
+            let first: Value = web
+                .get("/releases/search.json?query=some_random_crate")
+                .send()?
+                .json()?;
+            assert_eq!(first["total"], RELEASES_IN_RELEASES + 1);
+            assert_eq!(first["page"], 1);
+            assert_eq!(first["next_page"], 2);
+            assert_eq!(first["previous_page"], Value::Null);
+            let releases = first["releases"].as_array().unwrap();
+            assert_eq!(releases.len() as i64, RELEASES_IN_RELEASES);
+            assert_eq!(releases[0]["name"], "some_random_crate");
+            assert_eq!(releases[0]["version"], "1.0.0");
+            assert_eq!(releases[0]["description"], "some description");
+            assert_eq!(releases[0]["target_name"], "some_random_crate");
+            assert_eq!(releases[0]["rustdoc_status"], true);
+            assert_eq!(releases[0]["stars"], 0);
+
+            let second: Value = web
+                .get("/releases/search.json?query=some_random_crate&page=2")
+                .send()?
+                .json()?;
+            assert_eq!(second["releases"].as_array().unwrap().len(), 1);
+            assert_eq!(second["next_page"], Value::Null);
+            assert_eq!(second["previous_page"], 1);
+
+            // Pages past the end still report the total, and huge pages don't overflow.
+            for page in &["3", "9223372036854775807"] {
+                let past_end: Value = web
+                    .get(&format!(
+                        "/releases/search.json?query=some_random_crate&page={}",
+                        page
+                    ))
+                    .send()?
+                    .json()?;
+                assert!(past_end["releases"].as_array().unwrap().is_empty());
+                assert_eq!(past_end["total"], RELEASES_IN_RELEASES + 1);
+                assert_eq!(past_end["next_page"], Value::Null);
+            }
+
+            // The HTML endpoint also returns JSON when asked to.
+            let resp = web
+                .get("/releases/search?query=some_random_crate")
+                .header("Accept", "application/json")
+                .send()?;
+            assert_eq!(
+                resp.headers().get("Content-Type").unwrap(),
+                "application/json"
+            );
+            assert_eq!(resp.headers().get("Vary").unwrap(), "Accept");
+            assert_eq!(resp.json::<Value>()?["total"], RELEASES_IN_RELEASES + 1);
+
+            let resp = web.get("/releases/search?query=some_random_crate").send()?;
+            assert_eq!(resp.headers().get("Vary").unwrap(), "Accept");
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn search_items() {
+        wrapper(|env| {
+            let item = |name: &str, path: &str, url: &str| crate::docbuilder::SearchItem {
+                name: name.into(),
+                path: path.into(),
+                kind: "method".into(),
+                url: url.into(),
+                description: Some(format!("The {} method.", name)),
+            };
+
+            let old = env.fake_release().name("foo").version("0.1.0").create()?;
+            let new = env.fake_release().name("foo").version("0.2.0").create()?;
+            let other = env
+                .fake_release()
+                .name("bar")
+                .version("1.0.0")
+                .downloads(100)
+                .create()?;
+
+            let mut conn = env.db().conn();
+            crate::db::add_search_items(
+                &mut conn,
+                old,
+                &[item(
+                    "entry",
+                    "foo::Map::entry",
+                    "foo/struct.Map.html#method.entry",
+                )],
+            )?;
+            crate::db::add_search_items(
+                &mut conn,
+                new,
+                &[
+                    item(
+                        "entry",
+                        "foo::Map::entry",
+                        "foo/struct.Map.html#method.entry",
+                    ),
+                    item(
+                        "entry_ref",
+                        "foo::Map::entry_ref",
+                        "foo/struct.Map.html#method.entry_ref",
+                    ),
+                    item(
+                        "insert",
+                        "foo::Map::insert",
+                        "foo/struct.Map.html#method.insert",
+                    ),
+                ],
+            )?;
+            crate::db::add_search_items(
+                &mut conn,
+                other,
+                &[
+                    item(
+                        "entry",
+                        "bar::Set::entry",
+                        "bar/struct.Set.html#method.entry",
+                    ),
+                    item(
+                        "entries",
+                        "bar::Set::entries",
+                        "bar/struct.Set.html#method.entries",
+                    ),
+                ],
+            )?;
+
+            let count_items = |conn: &mut Client, release: i32| -> Result<i64, Error> {
+                Ok(conn
+                    .query_one(
+                        "SELECT COUNT(*) FROM search_items WHERE release_id = $1",
+                        &[&release],
+                    )?
+                    .get(0))
+            };
+            // The items of the older release were replaced.
+            assert_eq!(count_items(&mut conn, old)?, 0);
+
+            // Rebuilding the older release doesn't add its items back.
+            crate::db::add_search_items(
+                &mut conn,
+                old,
+                &[item(
+                    "entry",
+                    "foo::Map::entry",
+                    "foo/struct.Map.html#method.entry",
+                )],
+            )?;
+            assert_eq!(count_items(&mut conn, old)?, 0);
+            assert_eq!(count_items(&mut conn, new)?, 3);
+
+            let paths = |query: &str| -> Result<Vec<String>, Error> {
+                Ok(get_item_search_results(&mut env.db().conn(), query, 100)?
+                    .into_iter()
+                    .map(|item| item.path)
+                    .collect())
+            };
+
+            // Exact matches come first, then the most downloaded crates.
+            assert_eq!(
+                paths("entry")?,
+                vec![
+                    "bar::Set::entry",
+                    "foo::Map::entry",
+                    "bar::Set::entries",
+                    "foo::Map::entry_ref",
+                ]
+            );
+            assert_eq!(
+                paths("map::Entry")?,
+                vec!["foo::Map::entry", "foo::Map::entry_ref"]
+            );
+            // LIKE wildcards in the query are matched literally.
+            assert_eq!(paths("entry_")?, vec!["foo::Map::entry_ref"]);
+
+            let page = kuchiki::parse_html().one(
+                env.frontend()
+                    .get("/releases/search?query=Map::insert&mode=items")
+                    .send()?
+                    .text()?,
+            );
+            let links = page
+                .select(".recent-releases-container li a")
+                .expect("invalid selector")
+                .map(|link| link.attributes.borrow().get("href").unwrap().to_string())
+                .collect::<Vec<_>>();
+            assert_eq!(links, vec!["/foo/0.2.0/foo/struct.Map.html#method.insert"]);
+
+            Ok(())
+        })
+    }
+
     #[test]
     fn releases() {
         wrapper(|env| {
@@ -1159,6 +1685,77 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_releases_queue_retries() {
+        wrapper(|env| {
+            let queue = env.build_queue();
+            queue.add_crate("foo", "1.0.0", 0)?;
+            queue.process_next_crate("test-worker", |_| failure::bail!("network error"))?;
+
+            let page =
+                kuchiki::parse_html().one(env.frontend().get("/releases/queue").send()?.text()?);
+            let retry = page
+                .select_first(".queue-list > li .queue-retry")
+                .expect("missing retry information");
+            assert!(retry.text_contents().contains("failed 1 time,"));
+            assert!(retry.text_contents().contains("next attempt at"));
+            assert_eq!(retry.attributes.borrow().get("title"), None);
+            assert!(!page.to_string().contains("network error"));
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn failures_filtered_by_reason() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("timed-out")
+                .failure_reason(BuildFailureReason::Timeout)
+                .create()?;
+            env.fake_release()
+                .name("no-library")
+                .failure_reason(BuildFailureReason::MissingSystemLibrary)
+                .create()?;
+            env.fake_release()
+                .name("unknown")
+                .build_result_successful(false)
+                .create()?;
+
+            let failed_crates = |path: &str| -> Result<Vec<String>, Error> {
+                let page = kuchiki::parse_html().one(env.frontend().get(path).send()?.text()?);
+                Ok(page
+                    .select(".recent-releases-container > ul > li .name")
+                    .expect("missing list of releases")
+                    .map(|name| name.text_contents().trim().to_string())
+                    .collect())
+            };
+
+            for list in &["recent-failures", "failures"] {
+                let path = format!("/releases/{}", list);
+                assert_eq!(failed_crates(&path)?.len(), 3);
+                assert_eq!(
+                    failed_crates(&format!("{}?reason=timeout", path))?,
+                    vec!["timed-out-1.0.0"]
+                );
+                assert_eq!(
+                    failed_crates(&format!("{}?reason=missing-system-library", path))?,
+                    vec!["no-library-1.0.0"]
+                );
+                assert!(failed_crates(&format!("{}?reason=out-of-memory", path))?.is_empty());
+                // Builds without a failure reason are listed as unknown.
+                assert_eq!(
+                    failed_crates(&format!("{}?reason=unknown", path))?,
+                    vec!["unknown-1.0.0"]
+                );
+                // Unknown reasons are ignored.
+                assert_eq!(failed_crates(&format!("{}?reason=foo", path))?.len(), 3);
+            }
+
+            Ok(())
+        });
+    }
+
     #[test]
     fn authors_page() {
         wrapper(|env| {
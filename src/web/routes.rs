@@ -15,6 +15,8 @@ pub(super) fn build_routes() -> Routes {
     routes.static_resource("/opensearch.xml", super::opensearch_xml_handler);
     routes.static_resource("/-/static/:file", super::statics::static_handler);
 
+    routes.post_endpoint("/_/index-webhook", super::webhook::index_webhook_handler);
+
     routes.internal_page("/", super::releases::home_page);
 
     routes.internal_page("/about", super::sitemap::about_handler);
@@ -28,6 +30,7 @@ pub(super) fn build_routes() -> Routes {
     routes.internal_page("/releases/:author/:page", super::releases::author_handler);
     routes.internal_page("/releases/activity", super::releases::activity_handler);
     routes.internal_page("/releases/search", super::releases::search_handler);
+    routes.static_resource("/releases/search.json", super::releases::search_handler);
     routes.internal_page("/releases/queue", super::releases::build_queue_handler);
     routes.internal_page(
         "/releases/recent/:page",
@@ -63,6 +66,10 @@ pub(super) fn build_routes() -> Routes {
         "/crate/:name/:version",
         super::crate_details::crate_details_handler,
     );
+    routes.static_resource(
+        "/crate/:name/:version/details.json",
+        super::crate_details::crate_details_json_handler,
+    );
     routes.internal_page(
         "/crate/:name/:version/builds",
         super::builds::build_list_handler,
@@ -140,6 +147,8 @@ pub(super) fn build_routes() -> Routes {
 pub(super) struct Routes {
     /// Normal GET routes.
     get: Vec<(String, Box<dyn Handler>)>,
+    /// POST routes, used by other services to notify docs.rs.
+    post: Vec<(String, Box<dyn Handler>)>,
     /// GET routes serving rustdoc content. The BlockBlacklistedPrefixes middleware is added
     /// automatically to all of them.
     rustdoc_get: Vec<(String, Box<dyn Handler>)>,
@@ -152,6 +161,7 @@ impl Routes {
     fn new() -> Self {
         Self {
             get: Vec::new(),
+            post: Vec::new(),
             rustdoc_get: Vec::new(),
             page_prefixes: HashSet::new(),
         }
@@ -166,6 +176,9 @@ impl Routes {
         for (pattern, handler) in self.get.drain(..) {
             router.get(&pattern, handler, calculate_id(&pattern));
         }
+        for (pattern, handler) in self.post.drain(..) {
+            router.post(&pattern, handler, calculate_id(&pattern));
+        }
 
         // All rustdoc pages have the prefixes of other docs.rs pages blacklisted. This prevents,
         // for example, a crate named "about" from hijacking /about/0.1.0/index.html.
@@ -189,6 +202,15 @@ impl Routes {
         ));
     }
 
+    /// A POST endpoint, for example a webhook. These don't need any special behavior on the
+    /// router side either.
+    fn post_endpoint(&mut self, pattern: &str, handler: impl Handler) {
+        self.post.push((
+            pattern.to_string(),
+            Box::new(RequestRecorder::new(handler, pattern)),
+        ));
+    }
+
     /// Internal pages are docs.rs's own pages, instead of the documentation of a crate uploaded by
     /// an user. The router adds these extra things when adding a new internal page:
     ///
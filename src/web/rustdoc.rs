@@ -560,7 +560,8 @@ pub fn badge_handler(req: &mut Request) -> IronResult<Response> {
             }
 
             Ok(MatchSemver::Semver((version, _))) => {
-                let base_url = format!("{}/{}/badge.svg", redirect_base(req), name);
+                // Badges are embedded in other websites, so they always point to the public URL.
+                let base_url = format!("{}/{}/badge.svg", extension!(req, Config).base_url, name);
                 let url = ctry!(
                     req,
                     iron::url::Url::parse_with_params(&base_url, &[("version", version)]),
@@ -962,12 +963,16 @@ mod test {
                 .version("0.5.1+zstd.1.4.4")
                 .create()?;
 
-            let frontend = env.frontend();
-            assert_redirect(
-                "/zstd/badge.svg",
-                "/zstd/badge.svg?version=0.5.1%2Bzstd.1.4.4",
-                &frontend,
-            )?;
+            let resp = env.frontend().get_no_redirect("/zstd/badge.svg").send()?;
+            assert_eq!(resp.status(), StatusCode::FOUND);
+            assert_eq!(
+                resp.headers()["Location"],
+                format!(
+                    "{}/zstd/badge.svg?version=0.5.1%2Bzstd.1.4.4",
+                    env.config().base_url
+                )
+                .as_str()
+            );
             Ok(())
         })
     }
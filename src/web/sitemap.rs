@@ -1,4 +1,4 @@
-use crate::{db::Pool, docbuilder::Limits, impl_webpage, web::page::WebPage};
+use crate::{db::Pool, docbuilder::Limits, impl_webpage, web::page::WebPage, Config};
 use chrono::{DateTime, NaiveDateTime, Utc};
 use iron::{
     headers::ContentType,
@@ -13,6 +13,7 @@ use serde_json::Value;
 struct SitemapXml {
     /// The release's names and RFC 3339 timestamp to be displayed on the sitemap
     releases: Vec<(String, String)>,
+    base_url: String,
 }
 
 impl_webpage! {
@@ -45,11 +46,19 @@ pub fn sitemap_handler(req: &mut Request) -> IronResult<Response> {
         })
         .collect::<Vec<(String, String)>>();
 
-    SitemapXml { releases }.into_response(req)
+    SitemapXml {
+        releases,
+        base_url: extension!(req, Config).base_url.clone(),
+    }
+    .into_response(req)
 }
 
-pub fn robots_txt_handler(_: &mut Request) -> IronResult<Response> {
-    let mut resp = Response::with((status::Ok, "Sitemap: https://docs.rs/sitemap.xml"));
+pub fn robots_txt_handler(req: &mut Request) -> IronResult<Response> {
+    let config = extension!(req, Config);
+    let mut resp = Response::with((
+        status::Ok,
+        format!("Sitemap: {}/sitemap.xml", config.base_url),
+    ));
     resp.headers.set(ContentType::plaintext());
 
     Ok(resp)
@@ -84,7 +93,7 @@ pub fn about_builds_handler(req: &mut Request) -> IronResult<Response> {
 
     AboutBuilds {
         rustc_version,
-        limits: Limits::default(),
+        limits: Limits::new(extension!(req, Config)),
         active_tab: "builds",
     }
     .into_response(req)
@@ -172,4 +181,28 @@ mod tests {
             assert_success("/robots.txt", web)
         })
     }
+
+    #[test]
+    fn configured_base_url() {
+        wrapper(|env| {
+            env.override_config(|config| {
+                config.base_url = "https://docs.example.com".into();
+            });
+            env.fake_release().name("some_random_crate").create()?;
+            let web = env.frontend();
+
+            let robots = web.get("/robots.txt").send()?.text()?;
+            assert_eq!(robots, "Sitemap: https://docs.example.com/sitemap.xml");
+
+            let sitemap = web.get("/sitemap.xml").send()?.text()?;
+            assert!(sitemap.contains("<loc>https://docs.example.com/some_random_crate</loc>"));
+
+            let opensearch = web.get("/opensearch.xml").send()?.text()?;
+            assert!(
+                opensearch.contains("https://docs.example.com/releases/search?query={searchTerms}")
+            );
+
+            Ok(())
+        })
+    }
 }
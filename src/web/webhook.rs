@@ -0,0 +1,216 @@
+//! Webhook sent by the registry index repository whenever a crate is published or yanked, used
+//! to start an index sync in the background instead of waiting for the next periodic one.
+
+use super::error::Nope;
+use crate::{db::Pool, BuildQueue, Config, DocBuilder, Index, Metrics};
+use failure::Error;
+use hmac::{Hmac, Mac, NewMac};
+use iron::{status, IronResult, Request, Response};
+use log::{debug, error};
+use sha2::Sha256;
+use std::io::Read;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use std::thread;
+
+/// Header containing the HMAC-SHA256 signature of the payload, in the format GitHub uses.
+const SIGNATURE_HEADER: &str = "X-Hub-Signature-256";
+
+/// Maximum size of the payload, which is read before the signature can be checked. The payload
+/// itself is never used, so this only needs to fit the payloads sent by GitHub.
+const MAX_PAYLOAD_SIZE: u64 = 1024 * 1024;
+
+/// Whether a sync was requested but didn't start yet. Webhooks received while a sync is pending
+/// don't need to spawn another one, as the pending sync will pick up their changes too.
+static SYNC_PENDING: AtomicBool = AtomicBool::new(false);
+
+pub fn index_webhook_handler(req: &mut Request) -> IronResult<Response> {
+    let config = extension!(req, Config).clone();
+    // The webhook is disabled if there is no secret to authenticate it.
+    let secret = match &config.index_webhook_secret {
+        Some(secret) => secret.clone(),
+        None => return Err(Nope::ResourceNotFound.into()),
+    };
+
+    let mut payload = Vec::new();
+    ctry!(
+        req,
+        Read::by_ref(&mut req.body)
+            .take(MAX_PAYLOAD_SIZE + 1)
+            .read_to_end(&mut payload)
+    );
+    if payload.len() as u64 > MAX_PAYLOAD_SIZE {
+        return Ok(Response::with((
+            status::PayloadTooLarge,
+            "payload too large",
+        )));
+    }
+
+    let signature = req
+        .headers
+        .get_raw(SIGNATURE_HEADER)
+        .and_then(|values| values.get(0))
+        .and_then(|value| std::str::from_utf8(value).ok());
+    if !signature.map_or(false, |sig| verify_signature(&secret, &payload, sig)) {
+        return Ok(Response::with((status::Forbidden, "invalid signature")));
+    }
+
+    if !SYNC_PENDING.swap(true, Ordering::SeqCst) {
+        let pool = extension!(req, Pool).clone();
+        let build_queue = extension!(req, BuildQueue).clone();
+
+        let spawned = thread::Builder::new()
+            .name("index webhook sync".into())
+            .spawn(move || {
+                SYNC_PENDING.store(false, Ordering::SeqCst);
+                if let Err(err) = sync_index(config, pool, build_queue) {
+                    error!("failed to sync the index after a webhook: {:?}", err);
+                }
+            });
+        if spawned.is_err() {
+            SYNC_PENDING.store(false, Ordering::SeqCst);
+        }
+        ctry!(req, spawned);
+        extension!(req, Metrics).index_webhook_syncs.inc();
+    } else {
+        debug!("an index sync is already pending, skipping the webhook");
+    }
+
+    Ok(Response::with((status::Accepted, "index sync started")))
+}
+
+/// Check the signature sent with the webhook, in the `sha256=<hex digest>` format.
+fn verify_signature(secret: &str, payload: &[u8], signature: &str) -> bool {
+    let signature = match signature
+        .strip_prefix("sha256=")
+        .and_then(|hex| hex::decode(hex).ok())
+    {
+        Some(signature) => signature,
+        None => return false,
+    };
+
+    let mut mac = match Hmac::<Sha256>::new_varkey(secret.as_bytes()) {
+        Ok(mac) => mac,
+        Err(_) => return false,
+    };
+    mac.update(payload);
+    // The comparison is done in constant time, to avoid leaking the expected signature.
+    mac.verify(&signature).is_ok()
+}
+
+fn sync_index(config: Arc<Config>, pool: Pool, build_queue: Arc<BuildQueue>) -> Result<(), Error> {
+    let mut doc_builder = DocBuilder::new(config.clone(), pool, build_queue);
+    if doc_builder.is_locked() {
+        debug!("Lock file exists, skipping checking new crates");
+        return Ok(());
+    }
+
+    // Concurrent syncs on the same machine are prevented by the lock on the index repository.
+    let index = Index::new(&config)?;
+    let added = doc_builder.get_new_crates(&index)?;
+    debug!("{} crates added to queue", added);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::wrapper;
+    use reqwest::StatusCode;
+
+    const SECRET: &str = "It's a Secret to Everybody";
+    const PAYLOAD: &str = "Hello, World!";
+    // Computed with `echo -n "Hello, World!" | openssl sha256 -hmac "It's a Secret to Everybody"`
+    const SIGNATURE: &str =
+        "sha256=757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17";
+
+    #[test]
+    fn test_verify_signature() {
+        assert!(verify_signature(SECRET, PAYLOAD.as_bytes(), SIGNATURE));
+
+        assert!(!verify_signature(
+            "other secret",
+            PAYLOAD.as_bytes(),
+            SIGNATURE
+        ));
+        assert!(!verify_signature(SECRET, b"other payload", SIGNATURE));
+        assert!(!verify_signature(
+            SECRET,
+            PAYLOAD.as_bytes(),
+            SIGNATURE.trim_start_matches("sha256=")
+        ));
+        assert!(!verify_signature(SECRET, PAYLOAD.as_bytes(), "sha256=zz"));
+    }
+
+    #[test]
+    fn test_webhook_disabled_without_secret() {
+        wrapper(|env| {
+            let resp = env
+                .frontend()
+                .post("/_/index-webhook")
+                .header(SIGNATURE_HEADER, SIGNATURE)
+                .body(PAYLOAD)
+                .send()?;
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_webhook_invalid_signature() {
+        wrapper(|env| {
+            env.override_config(|config| {
+                config.index_webhook_secret = Some(SECRET.into());
+            });
+            let web = env.frontend();
+
+            let resp = web.post("/_/index-webhook").body(PAYLOAD).send()?;
+            assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+            let resp = web
+                .post("/_/index-webhook")
+                .header(SIGNATURE_HEADER, SIGNATURE)
+                .body("tampered payload")
+                .send()?;
+            assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+            let resp = web
+                .post("/_/index-webhook")
+                .header(SIGNATURE_HEADER, SIGNATURE)
+                .body(vec![b'a'; MAX_PAYLOAD_SIZE as usize + 1])
+                .send()?;
+            assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+            assert_eq!(env.metrics().index_webhook_syncs.get(), 0);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_webhook_starts_sync() {
+        wrapper(|env| {
+            // The lock file makes the sync stop before fetching the registry index. The
+            // directory is kept, as the sync runs in the background after the test completes.
+            let prefix = tempfile::tempdir()?.into_path();
+            env.override_config(|config| {
+                config.index_webhook_secret = Some(SECRET.into());
+                config.prefix = prefix;
+            });
+            DocBuilder::new(env.config(), env.db().pool(), env.build_queue()).lock()?;
+
+            let resp = env
+                .frontend()
+                .post("/_/index-webhook")
+                .header(SIGNATURE_HEADER, SIGNATURE)
+                .body(PAYLOAD)
+                .send()?;
+            assert_eq!(resp.status(), StatusCode::ACCEPTED);
+            assert_eq!(env.metrics().index_webhook_syncs.get(), 1);
+
+            Ok(())
+        })
+    }
+}